@@ -1,51 +1,211 @@
-use std::time::{Duration, Instant};
+use std::{
+    collections::VecDeque,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
 
 use crate::config::CircuitBreakerConfig;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CircuitState {
+    /// Requests flow normally, failures are being counted
     Closed,
+    /// Requests are rejected until `retry_after`
     Open { retry_after: Instant },
-    HalfOpen,
+    /// A limited number of trial requests decide whether to close or reopen
+    HalfOpen { in_flight: u32, successes: u32 },
 }
 
 #[derive(Debug)]
 pub struct CircuitBreaker {
-    pub failures: u32,
+    pub instance: String,
+    /// Failures within the rolling window
+    pub failures: VecDeque<Instant>,
     pub state: CircuitState,
-    pub last_checked: Instant,
     pub config: CircuitBreakerConfig,
+    /// Bumped on every transition, so permits of a previous half-open state
+    /// don't give back slots of the current one
+    generation: u64,
 }
 
-impl Default for CircuitBreaker {
-    fn default() -> Self {
+impl CircuitBreaker {
+    pub fn new(instance: String, config: CircuitBreakerConfig) -> Self {
         Self {
-            failures: Default::default(),
-            state: CircuitState::HalfOpen,
-            last_checked: Instant::now(),
-            config: CircuitBreakerConfig::default(),
+            instance,
+            failures: VecDeque::new(),
+            state: CircuitState::Closed,
+            config,
+            generation: 0,
         }
     }
-}
 
-impl CircuitBreaker {
-    pub fn with_config(config: CircuitBreakerConfig) -> Self {
-        Self {
-            config,
-            ..Default::default()
+    /// Whether a request could be sent to the instance right now,
+    /// without reserving a half-open trial slot.
+    pub fn is_available(&self) -> bool {
+        match self.state {
+            CircuitState::Closed => true,
+            CircuitState::Open { retry_after } => Instant::now() >= retry_after,
+            CircuitState::HalfOpen { in_flight, .. } => {
+                in_flight < self.config.half_open_max_requests
+            }
         }
     }
+
+    /// Reserves the right to send a request to the instance.
+    /// Moves an expired open circuit into half-open.
+    pub fn try_acquire(&mut self) -> bool {
+        if let CircuitState::Open { retry_after } = self.state {
+            if Instant::now() < retry_after {
+                return false;
+            }
+            self.transition(CircuitState::HalfOpen {
+                in_flight: 0,
+                successes: 0,
+            });
+        }
+
+        match &mut self.state {
+            CircuitState::Closed => true,
+            CircuitState::Open { .. } => false,
+            CircuitState::HalfOpen { in_flight, .. } => {
+                if *in_flight >= self.config.half_open_max_requests {
+                    return false;
+                }
+                *in_flight += 1;
+                true
+            }
+        }
+    }
+
     pub fn register_failure(&mut self) {
-        self.failures += 1;
-        self.last_checked = Instant::now();
-        self.state = CircuitState::Open {
-            retry_after: Instant::now() + Duration::from_secs(self.config.open_window_seconds),
-        };
+        let now = Instant::now();
+        self.failures.push_back(now);
+        self.prune_failures(now);
+
+        match self.state {
+            CircuitState::Closed => {
+                if self.failures.len() >= self.config.failure_threshold as usize {
+                    self.open(now);
+                }
+            }
+            // Any failed trial reopens the circuit
+            CircuitState::HalfOpen { .. } => self.open(now),
+            CircuitState::Open { .. } => {}
+        }
     }
 
     pub fn register_success(&mut self) {
-        self.failures = 0;
-        self.state = CircuitState::Closed;
-        self.last_checked = Instant::now();
+        if let CircuitState::HalfOpen { successes, .. } = &mut self.state {
+            *successes += 1;
+            if *successes >= self.config.half_open_max_requests {
+                self.failures.clear();
+                self.transition(CircuitState::Closed);
+            }
+        }
+    }
+
+    /// Gives back a trial slot taken during `generation`
+    fn release(&mut self, generation: u64) {
+        if generation != self.generation {
+            return;
+        }
+        if let CircuitState::HalfOpen { in_flight, .. } = &mut self.state {
+            *in_flight = in_flight.saturating_sub(1);
+        }
+    }
+
+    fn open(&mut self, now: Instant) {
+        self.transition(CircuitState::Open {
+            retry_after: now + Duration::from_secs(self.config.open_window_seconds),
+        });
+    }
+
+    fn prune_failures(&mut self, now: Instant) {
+        let window = Duration::from_secs(self.config.failure_window_seconds);
+        while let Some(failure) = self.failures.front() {
+            if now.duration_since(*failure) <= window {
+                break;
+            }
+            self.failures.pop_front();
+        }
+    }
+
+    fn transition(&mut self, to: CircuitState) {
+        let from = std::mem::replace(&mut self.state, to);
+        self.generation += 1;
+        let failures = self.failures.len();
+
+        match to {
+            CircuitState::Open { .. } => tracing::warn!(
+                instance = self.instance,
+                failures,
+                ?from,
+                "Circuit breaker opened"
+            ),
+            CircuitState::HalfOpen { .. } => {
+                tracing::info!(instance = self.instance, ?from, "Circuit breaker half-open")
+            }
+            CircuitState::Closed => {
+                tracing::info!(instance = self.instance, ?from, "Circuit breaker closed")
+            }
+        }
+    }
+}
+
+/// Reports the outcome of a request to the breaker of `instance`, if it has one.
+pub(crate) fn record(breakers: &DashMap<String, CircuitBreaker>, instance: &str, success: bool) {
+    if let Some(mut cb) = breakers.get_mut(instance) {
+        if success {
+            cb.register_success();
+        } else {
+            cb.register_failure();
+        }
+    }
+}
+
+/// A request admitted by the breaker of an instance. A half-open trial slot is
+/// held until the permit is dropped, so requests that never report an outcome
+/// (rejected by the gateway, cancelled by the client) don't hold it forever.
+#[must_use]
+pub(crate) struct CircuitPermit {
+    breakers: Arc<DashMap<String, CircuitBreaker>>,
+    instance: String,
+    /// Generation of the half-open state the trial slot was taken in
+    trial: Option<u64>,
+}
+
+/// Asks the breaker of `instance`, created if it's the first request to it,
+/// to let a request through.
+pub(crate) fn acquire(
+    breakers: &Arc<DashMap<String, CircuitBreaker>>,
+    instance: &str,
+    config: &CircuitBreakerConfig,
+) -> Option<CircuitPermit> {
+    let mut cb = breakers
+        .entry(instance.to_owned())
+        .or_insert_with(|| CircuitBreaker::new(instance.to_owned(), config.clone()));
+    if !cb.try_acquire() {
+        return None;
+    }
+    let trial = matches!(cb.state, CircuitState::HalfOpen { .. }).then_some(cb.generation);
+    drop(cb);
+
+    Some(CircuitPermit {
+        breakers: breakers.clone(),
+        instance: instance.to_owned(),
+        trial,
+    })
+}
+
+impl Drop for CircuitPermit {
+    fn drop(&mut self) {
+        let Some(generation) = self.trial else {
+            return;
+        };
+        if let Some(mut cb) = self.breakers.get_mut(&self.instance) {
+            cb.release(generation);
+        }
     }
 }
@@ -1,4 +1,4 @@
-use std::fs;
+use std::{collections::HashMap, fs, net::IpAddr, time::Duration};
 
 use anyhow::Error;
 use dotenv::var;
@@ -6,11 +6,15 @@ use serde::Deserialize;
 
 #[derive(Debug, Clone, Default, Deserialize)]
 pub struct Config {
-    pub(crate) services: std::collections::HashMap<String, Service>,
+    pub(crate) services: HashMap<String, Service>,
     #[serde(default)]
     pub(crate) rate_limit: RateLimitConfig,
     #[serde(default)]
     pub(crate) circuit_breaker: CircuitBreakerConfig,
+    #[serde(default)]
+    pub(crate) identity: IdentityConfig,
+    #[serde(default)]
+    pub(crate) jwt: JwtConfig,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -19,61 +23,416 @@ pub(crate) struct Service {
     pub(crate) routes: Vec<Route>,
     #[serde(default)]
     pub(crate) strategy: Strategy,
+    pub(crate) health_check: Option<HealthCheckConfig>,
+    pub(crate) retry: Option<RetryConfig>,
+    #[serde(flatten)]
+    pub(crate) timeouts: TimeoutConfig,
 }
 
-#[derive(Debug, Clone, Deserialize, Default)]
+#[derive(Debug, Clone, PartialEq, Deserialize, Default)]
 pub(crate) enum Strategy {
     #[default]
     Random,
+    RoundRobin,
+    WeightedRoundRobin,
+    LeastConnections,
+    P2cEwma,
+    ConsistentHash {
+        key: HashKey,
+        #[serde(default = "default_virtual_nodes")]
+        virtual_nodes: usize,
+    },
 }
 
-#[derive(Debug, Clone, Deserialize)]
-pub(crate) struct Instance(pub(crate) String);
+/// Where the consistent hash strategy takes the routing key from.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub(crate) enum HashKey {
+    /// `user_id` claim of the JWT
+    UserId,
+    Header(String),
+    Cookie(String),
+    ClientIp,
+    /// Parameter of the matched route, e.g. `id` for `/channels/{id}`
+    Param(String),
+}
+
+fn default_virtual_nodes() -> usize {
+    160
+}
+
+/// An upstream instance. Can be declared either as a plain address
+/// (`"localhost:3001"`) or as a table (`{ address = "localhost:3001", weight = 3 }`).
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Deserialize)]
+#[serde(from = "InstanceConfig")]
+pub(crate) struct Instance {
+    pub(crate) address: String,
+    pub(crate) weight: u32,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum InstanceConfig {
+    Address(String),
+    Detailed {
+        address: String,
+        #[serde(default = "default_weight")]
+        weight: u32,
+    },
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+impl From<InstanceConfig> for Instance {
+    fn from(value: InstanceConfig) -> Self {
+        match value {
+            InstanceConfig::Address(address) => Instance {
+                address,
+                weight: default_weight(),
+            },
+            InstanceConfig::Detailed { address, weight } => Instance { address, weight },
+        }
+    }
+}
 
 #[derive(Debug, Clone, Deserialize)]
 pub(crate) struct Route {
     pub(crate) path: String,
     pub(crate) allow_methods: Vec<String>,
     pub(crate) protected: bool,
+    /// Roles the token must have, all of them. Only for protected routes
+    #[serde(default)]
+    pub(crate) requires: Vec<String>,
+    /// Scopes the token must have, all of them. Only for protected routes
+    #[serde(default)]
+    pub(crate) scopes: Vec<String>,
+    /// Overrides the retry policy of the service for this route
+    pub(crate) retry: Option<RetryConfig>,
+    /// Overrides the timeouts of the service for this route
+    #[serde(flatten)]
+    pub(crate) timeouts: TimeoutConfig,
+    /// Requests with a larger body are rejected with 413
+    pub(crate) max_body_bytes: Option<usize>,
+    /// Overrides the global rate limit policy. Its quota is separate from the global one
+    pub(crate) rate_limit: Option<RateLimitPolicy>,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+/// Upstream timeouts in milliseconds. Unset values fall back to the service,
+/// then to the defaults.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+pub struct TimeoutConfig {
+    /// Time allowed to open the connection to the instance
+    pub connect_timeout_ms: Option<u64>,
+    /// Time allowed until the response headers arrive
+    pub request_timeout_ms: Option<u64>,
+    /// Time allowed without any data flowing (response body chunks, WebSocket messages)
+    pub idle_timeout_ms: Option<u64>,
+}
+
+impl TimeoutConfig {
+    const DEFAULT_CONNECT_TIMEOUT_MS: u64 = 5_000;
+    const DEFAULT_REQUEST_TIMEOUT_MS: u64 = 30_000;
+
+    pub(crate) fn or(self, fallback: TimeoutConfig) -> TimeoutConfig {
+        TimeoutConfig {
+            connect_timeout_ms: self.connect_timeout_ms.or(fallback.connect_timeout_ms),
+            request_timeout_ms: self.request_timeout_ms.or(fallback.request_timeout_ms),
+            idle_timeout_ms: self.idle_timeout_ms.or(fallback.idle_timeout_ms),
+        }
+    }
+
+    pub(crate) fn connect_timeout(&self) -> Duration {
+        Duration::from_millis(
+            self.connect_timeout_ms
+                .unwrap_or(Self::DEFAULT_CONNECT_TIMEOUT_MS),
+        )
+    }
+
+    pub(crate) fn request_timeout(&self) -> Duration {
+        Duration::from_millis(
+            self.request_timeout_ms
+                .unwrap_or(Self::DEFAULT_REQUEST_TIMEOUT_MS),
+        )
+    }
+
+    pub(crate) fn idle_timeout(&self) -> Option<Duration> {
+        self.idle_timeout_ms.map(Duration::from_millis)
+    }
+}
+
+/// Global rate limiting. Routes can override the policy.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
 pub struct RateLimitConfig {
+    #[serde(flatten)]
+    pub policy: RateLimitPolicy,
+    /// Proxies allowed to set `X-Forwarded-For`, used by the `ForwardedFor` key
+    pub trusted_proxies: Vec<IpAddr>,
+    /// Hex encoded SHA-256 of the keys accepted by the `ApiKey` key. Others are
+    /// keyed like `ForwardedFor`, so made up keys don't get a quota of their own
+    pub api_keys: Vec<String>,
+    pub store: RateLimitStoreConfig,
+}
+
+/// Where the usage of every client is kept.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub enum RateLimitStoreConfig {
+    /// In the gateway process, every replica enforces its own quota
+    #[default]
+    Memory,
+    /// In a Redis compatible server shared by every replica. `SlidingLog` is
+    /// approximated with two fixed windows and `TokenBucket` isn't supported
+    Redis {
+        url: String,
+        /// Whether requests are let through while the store is unreachable
+        #[serde(default = "default_fail_open")]
+        fail_open: bool,
+        #[serde(default = "default_store_timeout_ms")]
+        timeout_ms: u64,
+    },
+}
+
+fn default_fail_open() -> bool {
+    true
+}
+
+fn default_store_timeout_ms() -> u64 {
+    100
+}
+
+/// Allows `max_requests` every `window_seconds` per client.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct RateLimitPolicy {
+    pub algorithm: RateLimitAlgorithm,
     pub max_requests: u32,
     pub window_seconds: u64,
+    pub key: RateLimitKey,
 }
 
-impl Default for RateLimitConfig {
+impl Default for RateLimitPolicy {
     fn default() -> Self {
         Self {
+            algorithm: RateLimitAlgorithm::default(),
             max_requests: 100,
             window_seconds: 60,
+            key: RateLimitKey::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+pub enum RateLimitAlgorithm {
+    /// Counter reset at the end of every window. Cheapest, but allows bursts
+    /// of twice the limit around the window boundaries
+    #[default]
+    FixedWindow,
+    /// Timestamp of every request within the last window
+    SlidingLog,
+    /// Bucket of `max_requests` tokens refilled over `window_seconds`
+    TokenBucket,
+}
+
+/// What identifies a client. Falls back to the peer address when the key
+/// can't be found in the request.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub enum RateLimitKey {
+    /// Address of the connecting socket
+    #[default]
+    PeerIp,
+    /// Client address from `X-Forwarded-For`, trusted only when the request
+    /// comes from one of `trusted_proxies`
+    ForwardedFor,
+    /// `user_id` claim of the JWT
+    UserId,
+    /// Value of the given header
+    ApiKey(String),
+}
+
+/// Identity forwarded to upstreams for requests authenticated by the gateway.
+/// `user_id` is always sent as `X-User-Id`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct IdentityConfig {
+    /// Other claims to forward, as claim name to header name
+    pub claims: HashMap<String, String>,
+    /// Signs the identity headers with the `GATEWAY_SIGNING_SECRET` env variable,
+    /// so upstreams can trust them without verifying the JWT again
+    pub sign: bool,
+}
+
+/// How tokens are verified. Without `jwks_url`, they are HS256 tokens signed
+/// with the `JWT_SECRET` env variable.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct JwtConfig {
+    /// JWKS published by auth-service, tokens are verified with the key matching their `kid`
+    pub jwks_url: Option<String>,
+    /// Keys are fetched again after this long, so removed ones stop being trusted
+    pub jwks_max_age_seconds: u64,
+    /// Minimum time between fetches triggered by unknown `kid`s
+    pub jwks_refresh_cooldown_seconds: u64,
+    /// Tokens revoked on logout, listed by auth-service. None are rejected when unset
+    pub revocations_url: Option<String>,
+    pub revocations_poll_seconds: u64,
+    /// Protected routes answer 503 while the revocations couldn't be fetched for this long,
+    /// and until they first are
+    pub revocations_max_stale_seconds: u64,
+}
+
+impl Default for JwtConfig {
+    fn default() -> Self {
+        Self {
+            jwks_url: None,
+            jwks_max_age_seconds: 300,
+            jwks_refresh_cooldown_seconds: 10,
+            revocations_url: None,
+            revocations_poll_seconds: 5,
+            revocations_max_stale_seconds: 60,
         }
     }
 }
 
 #[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
 pub struct CircuitBreakerConfig {
+    /// Failures within `failure_window_seconds` needed to open the circuit
     pub failure_threshold: u32,
+    pub failure_window_seconds: u64,
+    /// How long the circuit stays open before letting trial requests through
     pub open_window_seconds: u64,
+    /// Trial requests allowed while half-open. All of them must succeed to close the circuit
+    pub half_open_max_requests: u32,
 }
 
 impl Default for CircuitBreakerConfig {
     fn default() -> Self {
         Self {
             failure_threshold: 3,
+            failure_window_seconds: 60,
             open_window_seconds: 30,
+            half_open_max_requests: 1,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct HealthCheckConfig {
+    pub path: String,
+    pub expected_status: u16,
+    pub interval_seconds: u64,
+    pub timeout_seconds: u64,
+    /// Consecutive successful probes needed to mark an unhealthy instance as healthy
+    pub healthy_threshold: u32,
+    /// Consecutive failed probes needed to mark a healthy instance as unhealthy
+    pub unhealthy_threshold: u32,
+}
+
+impl Default for HealthCheckConfig {
+    fn default() -> Self {
+        Self {
+            path: "/health".to_string(),
+            expected_status: 200,
+            interval_seconds: 10,
+            timeout_seconds: 2,
+            healthy_threshold: 2,
+            unhealthy_threshold: 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct RetryConfig {
+    /// Total attempts, including the first one
+    pub max_attempts: u32,
+    /// Only idempotent methods are retried unless stated otherwise
+    pub methods: Vec<String>,
+    pub retry_on_connect_error: bool,
+    pub retry_on_timeout: bool,
+    pub retry_on_status: Vec<u16>,
+    pub backoff_base_ms: u64,
+    pub backoff_max_ms: u64,
+    /// Retries earned by every request. Caps retries at this share of the traffic
+    pub budget_ratio: f64,
+    /// Retries that can always be spent, so low traffic services can retry too
+    pub budget_min_retries: u32,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            methods: ["GET", "HEAD", "PUT", "DELETE"]
+                .map(str::to_string)
+                .to_vec(),
+            retry_on_connect_error: true,
+            retry_on_timeout: true,
+            retry_on_status: vec![502, 503, 504],
+            backoff_base_ms: 25,
+            backoff_max_ms: 250,
+            budget_ratio: 0.2,
+            budget_min_retries: 10,
         }
     }
 }
 
 pub fn load() -> Result<Config, Error> {
-    let config_path = var("CONFIG_PATH").unwrap_or("config/config.toml".to_string());
-    load_from_path(&config_path)
+    load_from_path(&path())
+}
+
+pub fn path() -> String {
+    var("CONFIG_PATH").unwrap_or("config/config.toml".to_string())
 }
 
 pub fn load_from_path(path: &str) -> Result<Config, Error> {
     let content = fs::read_to_string(path)?;
-    let config: Config = toml::from_str(&content)?;
+    parse(&content)
+}
+
+pub fn parse(content: &str) -> Result<Config, Error> {
+    let config: Config = toml::from_str(content)?;
+    validate(&config)?;
     Ok(config)
 }
+
+/// Checks what can't be expressed with the types alone.
+fn validate(config: &Config) -> Result<(), Error> {
+    crate::routing::compile(&config.services)?;
+    crate::identity::validate(&config.identity)?;
+
+    for (name, service) in &config.services {
+        for route in &service.routes {
+            let grants = !route.requires.is_empty() || !route.scopes.is_empty();
+            if grants && !route.protected {
+                anyhow::bail!(
+                    "Route `{}` of service `{name}` requires roles or scopes but isn't protected",
+                    route.path
+                );
+            }
+        }
+    }
+
+    if let RateLimitStoreConfig::Redis { url, .. } = &config.rate_limit.store {
+        redis::Client::open(url.as_str())?;
+
+        let token_bucket = std::iter::once(&config.rate_limit.policy)
+            .chain(
+                config
+                    .services
+                    .values()
+                    .flat_map(|service| service.routes.iter())
+                    .filter_map(|route| route.rate_limit.as_ref()),
+            )
+            .any(|policy| policy.algorithm == RateLimitAlgorithm::TokenBucket);
+        if token_bucket {
+            anyhow::bail!(
+                "The `TokenBucket` rate limit algorithm isn't supported by the Redis store"
+            );
+        }
+    }
+
+    Ok(())
+}
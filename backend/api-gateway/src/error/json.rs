@@ -0,0 +1,51 @@
+use axum::{
+    Json,
+    response::{IntoResponse, Response},
+};
+use hyper::StatusCode;
+use serde::Serialize;
+
+use crate::error::ErrorResponse;
+
+#[derive(Serialize)]
+struct ErrorBody {
+    message: String,
+}
+
+/// Error returned to the client as `{ "message": "..." }`.
+#[derive(Debug)]
+pub(crate) struct JsonError {
+    status: StatusCode,
+    message: String,
+}
+
+impl JsonError {
+    pub(crate) fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<StatusCode> for JsonError {
+    fn from(status: StatusCode) -> Self {
+        Self::new(status, status.canonical_reason().unwrap_or("Unknown error"))
+    }
+}
+
+impl IntoResponse for JsonError {
+    fn into_response(self) -> Response {
+        let body = Json(ErrorBody {
+            message: self.message,
+        });
+        (self.status, body).into_response()
+    }
+}
+
+impl ErrorResponse for JsonError {
+    fn with_debug(self, message: &str) -> Self {
+        tracing::debug!(message);
+        self
+    }
+}
@@ -1,3 +1,4 @@
+pub mod json;
 pub mod status_code;
 
 pub trait ErrorResponse {
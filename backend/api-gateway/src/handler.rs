@@ -1,79 +1,189 @@
+use std::io;
+use std::net::SocketAddr;
 use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use axum::body::to_bytes;
+use axum::body::{Bytes, HttpBody, to_bytes};
 use axum::extract::ws::{CloseFrame, Message, Utf8Bytes, WebSocket};
-use axum::extract::{State, WebSocketUpgrade};
+use axum::extract::{ConnectInfo, WebSocketUpgrade};
+use axum::http::request::Parts;
 use axum::{
     Extension,
     body::Body,
     extract::Request,
-    http::{StatusCode, Uri},
+    http::{HeaderMap, HeaderValue, StatusCode, Uri},
     response::Response,
 };
 use futures_util::{SinkExt, StreamExt};
-use hyper::header::CONTENT_LENGTH;
+use http_body_util::{LengthLimitError, Limited};
 
 use dashmap::DashMap;
 use reqwest::Client;
-use tokio_tungstenite::connect_async;
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream, connect_async};
 
-use crate::circuit_breaker::CircuitBreaker;
-use crate::config::{Instance, Service};
-use crate::error::ErrorResponse;
+use crate::circuit_breaker::{self, CircuitBreaker};
+use crate::config::{Instance, Route, Service, TimeoutConfig};
+use crate::error::{ErrorResponse, json::JsonError};
+use crate::identity;
+use crate::load_balancer::LoadBalancer;
+use crate::middleware::load_balancer::pick_instance;
 use crate::middleware::parser::ParsedURI;
 use crate::state::AppState;
 
+/// Bodies up to this size are buffered when the request may be retried,
+/// bigger ones are streamed and sent to a single instance.
+const RETRY_BUFFER_LIMIT: u64 = 64 * 1024;
+
+/// Upstreams see the client, not the gateway, as the last hop of `X-Forwarded-For`
+fn append_forwarded_for(parts: &mut Parts) {
+    let Some(ConnectInfo(peer)) = parts.extensions.get::<ConnectInfo<SocketAddr>>() else {
+        return;
+    };
+
+    let forwarded_for = match parts
+        .headers
+        .get("x-forwarded-for")
+        .and_then(|v| v.to_str().ok())
+    {
+        Some(hops) => format!("{hops}, {}", peer.ip()),
+        None => peer.ip().to_string(),
+    };
+    if let Ok(value) = HeaderValue::from_str(&forwarded_for) {
+        parts.headers.insert("x-forwarded-for", value);
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 pub(crate) async fn http_handler(
-    Extension(ParsedURI { prefix: _, subpath }): Extension<ParsedURI>,
-    Extension(_): Extension<Service>,
+    Extension(ParsedURI { prefix, subpath }): Extension<ParsedURI>,
+    Extension(service): Extension<Service>,
+    Extension(route): Extension<Route>,
     Extension(cb): Extension<Arc<DashMap<String, CircuitBreaker>>>,
-    Extension(Instance(base_uri)): Extension<Instance>,
-    State(state): State<AppState>,
-    mut req: Request,
-) -> Result<Response<Body>, StatusCode> {
-    let mut circuit_breaker = cb
-        .entry(base_uri.clone())
-        .or_insert(CircuitBreaker::with_config(
-            state.clone().config.circuit_breaker,
-        ));
-
-    let original_uri = req.uri();
-    let query = original_uri
+    Extension(lb): Extension<Arc<LoadBalancer>>,
+    Extension(instance): Extension<Instance>,
+    Extension(state): Extension<Arc<AppState>>,
+    req: Request,
+) -> Result<Response<Body>, JsonError> {
+    let query = req
+        .uri()
         .query()
         .map(|q| format!("?{q}"))
         .unwrap_or_default();
+    let path_and_query = format!("{subpath}{query}");
 
-    let uri_str = format!("http://{base_uri}{subpath}{query}");
-    let uri: Uri = uri_str
-        .parse()
-        .map_err(|_| StatusCode::BAD_GATEWAY.with_debug("Invalid uri. Could not parse"))?;
-    *req.uri_mut() = uri;
+    let (mut parts, body) = req.into_parts();
+    append_forwarded_for(&mut parts);
 
-    let (parts, body) = req.into_parts();
+    // Bodies announcing a bigger size are rejected upfront, the rest are
+    // limited while being read
+    let body = match route.max_body_bytes {
+        Some(limit) if body.size_hint().lower() > limit as u64 => {
+            return Err(payload_too_large());
+        }
+        Some(limit) => Body::new(Limited::new(body, limit)),
+        None => body,
+    };
 
-    let content_length = parts
-        .headers
-        .get(CONTENT_LENGTH)
-        .and_then(|val| val.to_str().ok()?.parse::<usize>().ok())
-        .unwrap_or(0);
+    let retry = route
+        .retry
+        .as_ref()
+        .or(service.retry.as_ref())
+        .filter(|retry| retry.allows_method(&parts.method))
+        .filter(|_| {
+            body.size_hint()
+                .upper()
+                .is_some_and(|len| len <= RETRY_BUFFER_LIMIT)
+        });
+    let budget = state.retry_budgets.get(&prefix);
+    let timeouts = route.timeouts.or(service.timeouts);
+    if let (Some(_), Some(budget)) = (retry, budget) {
+        budget.deposit();
+    }
 
-    let body_bytes = to_bytes(body, content_length).await.map_err(|_| {
-        StatusCode::BAD_GATEWAY.with_debug("Invalid request body. Could not convert to bytes")
+    // A retried request is sent several times, so its body has to be buffered
+    let mut body = match retry {
+        Some(_) => UpstreamBody::Buffered(to_bytes(body, usize::MAX).await.map_err(|e| {
+            if is_length_limit(&e) {
+                payload_too_large()
+            } else {
+                JsonError::from(StatusCode::BAD_REQUEST)
+                    .with_debug("Invalid request body. Could not read it")
+            }
+        })?),
+        None => UpstreamBody::Streaming(Some(body)),
+    };
+
+    let client = state.clients.get(timeouts.connect_timeout()).map_err(|_| {
+        JsonError::from(StatusCode::INTERNAL_SERVER_ERROR)
+            .with_debug("Could not build upstream client")
     })?;
+    let mut instance = instance;
+    // Trial slot of a retried instance, the first one is held by the load
+    // balancer middleware
+    let mut permit = None;
+    let mut tried = Vec::new();
+    // The first attempt is tracked by the load balancer middleware
+    let mut guard = None;
+    let mut attempt = 1;
 
-    // Send the request and receive the response
-    let client = Client::new();
-    let mut forward_req = client.request(parts.method.clone(), uri_str);
+    let resp = loop {
+        let result = forward(
+            &client,
+            &parts,
+            &instance,
+            &path_and_query,
+            body.take(),
+            &timeouts,
+            &cb,
+            &lb,
+        )
+        .await;
 
-    for (name, value) in parts.headers.iter() {
-        forward_req = forward_req.header(name, value);
-    }
+        let Some(retry) = retry else {
+            break result;
+        };
 
-    forward_req = forward_req.body(body_bytes);
+        let retryable = match &result {
+            Ok(resp) => retry.retries_status(resp.status()),
+            Err(e) => retry.retries_error(e),
+        };
+        if !retryable
+            || attempt >= retry.max_attempts
+            || !budget.is_some_and(|budget| budget.try_withdraw())
+        {
+            break result;
+        }
 
-    let resp = forward_req.send().await.map_err(|_| {
-        circuit_breaker.register_failure();
-        StatusCode::BAD_GATEWAY.with_debug("Could not forward request")
+        // Every retry goes to an instance that hasn't been tried yet
+        tried.push(instance.clone());
+        let probe = Request::from_parts(parts.clone(), Body::empty());
+        let Some((next, next_permit)) = pick_instance(&state, &service, &lb, &probe, &tried) else {
+            break result;
+        };
+
+        tokio::time::sleep(retry.backoff(attempt)).await;
+        tracing::debug!(
+            from = instance.address,
+            to = next.address,
+            attempt,
+            "Retrying upstream request"
+        );
+
+        guard = Some(lb.track(&next));
+        permit = Some(next_permit);
+        instance = next;
+        attempt += 1;
+    };
+    drop(permit);
+
+    let resp = resp.map_err(|e| match e {
+        UpstreamError::Timeout => upstream_timeout(),
+        UpstreamError::BodyTooLarge => payload_too_large(),
+        UpstreamError::Request(_) => {
+            JsonError::from(StatusCode::BAD_GATEWAY).with_debug("Could not forward request")
+        }
     })?;
 
     let mut response_builder = Response::builder().status(resp.status());
@@ -82,83 +192,257 @@ pub(crate) async fn http_handler(
         response_builder = response_builder.header(key, value);
     }
 
-    let resp_bytes = resp.bytes().await.map_err(|_| {
-        StatusCode::BAD_GATEWAY.with_debug("Invalid response body. Could not convert to bytes")
-    })?;
+    let body = stream_response(resp, timeouts.idle_timeout(), cb, instance);
+    let body = match guard {
+        Some(guard) => guard.attach(body),
+        None => body,
+    };
 
-    let response = response_builder
-        .body(Body::from(resp_bytes))
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR.with_debug("Could not build response"))?;
+    let response = response_builder.body(body).map_err(|_| {
+        JsonError::from(StatusCode::INTERNAL_SERVER_ERROR).with_debug("Could not build response")
+    })?;
 
-    circuit_breaker.register_success();
     Ok(response)
 }
 
-pub(crate) async fn ws_handler(
-    Extension(ParsedURI { prefix: _, subpath }): Extension<ParsedURI>,
-    Extension(_): Extension<Service>,
-    Extension(Instance(uri)): Extension<Instance>,
-    ws: WebSocketUpgrade,
-) -> Result<Response<Body>, StatusCode> {
-    let uri_str = format!("ws://{uri}{subpath}");
-    let uri: Uri = uri_str
-        .parse()
-        .map_err(|_| StatusCode::BAD_GATEWAY.with_debug("Invalid uri. Could not parse"))?;
+fn upstream_timeout() -> JsonError {
+    JsonError::new(StatusCode::GATEWAY_TIMEOUT, "Upstream timed out")
+        .with_debug("Upstream did not answer in time")
+}
 
-    Ok(ws.on_upgrade(move |ws| proxy_websockets(ws, uri)))
+fn payload_too_large() -> JsonError {
+    JsonError::from(StatusCode::PAYLOAD_TOO_LARGE)
+        .with_debug("Request body exceeds the route limit")
 }
 
-async fn proxy_websockets(mut client_socket: WebSocket, uri: Uri) {
-    let url = uri.to_string();
+/// Whether `err` was caused by a body going over its `Limited` size.
+fn is_length_limit(err: &(dyn std::error::Error + 'static)) -> bool {
+    let mut source = Some(err);
+    while let Some(err) = source {
+        if err.is::<LengthLimitError>() {
+            return true;
+        }
+        source = err.source();
+    }
+    false
+}
 
-    match connect_async(&url).await {
-        Ok((target_socket, _)) => {
-            let (mut client_tx, mut client_rx) = client_socket.split();
-            let (mut target_tx, mut target_rx) = target_socket.split();
+/// Request body sent upstream. Only a buffered body can be sent more than once.
+enum UpstreamBody {
+    Buffered(Bytes),
+    Streaming(Option<Body>),
+}
 
-            let client_to_target = async {
-                while let Some(Ok(msg)) = client_rx.next().await {
-                    let t_msg = to_tungstenite(msg);
-                    if let Err(e) = target_tx.send(t_msg).await {
-                        tracing::error!("Error sending to target: {}", e);
-                        break;
-                    }
-                }
-                let _ = target_tx.close().await;
-            };
+impl UpstreamBody {
+    fn take(&mut self) -> reqwest::Body {
+        match self {
+            UpstreamBody::Buffered(bytes) => reqwest::Body::from(bytes.clone()),
+            UpstreamBody::Streaming(body) => match body.take() {
+                Some(body) => reqwest::Body::wrap_stream(body.into_data_stream()),
+                None => reqwest::Body::from(Bytes::new()),
+            },
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) enum UpstreamError {
+    Timeout,
+    BodyTooLarge,
+    Request(reqwest::Error),
+}
+
+impl From<reqwest::Error> for UpstreamError {
+    fn from(err: reqwest::Error) -> Self {
+        if err.is_timeout() {
+            UpstreamError::Timeout
+        } else if is_length_limit(&err) {
+            UpstreamError::BodyTooLarge
+        } else {
+            UpstreamError::Request(err)
+        }
+    }
+}
+
+/// Sends the request to a single instance, reporting the outcome to its
+/// circuit breaker and the latency to the load balancer.
+#[allow(clippy::too_many_arguments)]
+async fn forward(
+    client: &Client,
+    parts: &Parts,
+    instance: &Instance,
+    path_and_query: &str,
+    body: reqwest::Body,
+    timeouts: &TimeoutConfig,
+    cb: &DashMap<String, CircuitBreaker>,
+    lb: &LoadBalancer,
+) -> Result<reqwest::Response, UpstreamError> {
+    let uri_str = format!("http://{}{path_and_query}", instance.address);
+    let mut forward_req = client.request(parts.method.clone(), uri_str);
+
+    for (name, value) in parts.headers.iter() {
+        forward_req = forward_req.header(name, value);
+    }
+
+    forward_req = forward_req.body(body);
 
-            let target_to_client = async {
-                while let Some(Ok(msg)) = target_rx.next().await {
-                    let c_msg = from_tungstenite(msg);
-                    if let Err(e) = client_tx.send(c_msg).await {
-                        tracing::error!("Error sending to client: {}", e);
-                        break;
+    let started_at = Instant::now();
+    let resp = match tokio::time::timeout(timeouts.request_timeout(), forward_req.send()).await {
+        Ok(Ok(resp)) => resp,
+        Ok(Err(e)) => {
+            let err = UpstreamError::from(e);
+            // An oversized body is the client's fault, not the instance's
+            if !matches!(err, UpstreamError::BodyTooLarge) {
+                circuit_breaker::record(cb, &instance.address, false);
+            }
+            return Err(err);
+        }
+        Err(_) => {
+            circuit_breaker::record(cb, &instance.address, false);
+            return Err(UpstreamError::Timeout);
+        }
+    };
+    lb.record_latency(instance, started_at.elapsed());
+
+    // The response is still proxied back, but 5xx count against the instance
+    circuit_breaker::record(cb, &instance.address, !resp.status().is_server_error());
+
+    Ok(resp)
+}
+
+/// Streams the upstream response body back to the client. The stream fails if
+/// the instance stays silent for longer than `idle_timeout`, which also counts
+/// against its circuit breaker.
+fn stream_response(
+    resp: reqwest::Response,
+    idle_timeout: Option<Duration>,
+    cb: Arc<DashMap<String, CircuitBreaker>>,
+    instance: Instance,
+) -> Body {
+    let chunks = futures_util::stream::unfold(Some(resp), move |resp| {
+        let cb = cb.clone();
+        let address = instance.address.clone();
+        async move {
+            let mut resp = resp?;
+            let chunk = match idle_timeout {
+                Some(idle) => match tokio::time::timeout(idle, resp.chunk()).await {
+                    Ok(chunk) => chunk,
+                    Err(_) => {
+                        circuit_breaker::record(&cb, &address, false);
+                        let err = io::Error::new(io::ErrorKind::TimedOut, "Upstream idle timeout");
+                        return Some((Err(err), None));
                     }
-                }
-                let _ = client_tx.close().await;
+                },
+                None => resp.chunk().await,
             };
 
-            tokio::select! {
-                _ = client_to_target => (),
-                _ = target_to_client => (),
+            match chunk {
+                Ok(Some(chunk)) => Some((Ok(chunk), Some(resp))),
+                Ok(None) => None,
+                Err(e) => Some((Err(io::Error::other(e)), None)),
             }
+        }
+    });
+
+    Body::from_stream(chunks)
+}
 
-            tracing::info!("WebSocket proxy connection closed: {}", url);
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn ws_handler(
+    Extension(ParsedURI { prefix: _, subpath }): Extension<ParsedURI>,
+    Extension(service): Extension<Service>,
+    Extension(route): Extension<Route>,
+    Extension(cb): Extension<Arc<DashMap<String, CircuitBreaker>>>,
+    Extension(Instance { address: uri, .. }): Extension<Instance>,
+    Extension(state): Extension<Arc<AppState>>,
+    original_uri: Uri,
+    headers: HeaderMap,
+    ws: WebSocketUpgrade,
+) -> Result<Response<Body>, JsonError> {
+    let timeouts = route.timeouts.or(service.timeouts);
+    // The identity signature covers the query too
+    let query = original_uri
+        .query()
+        .map(|q| format!("?{q}"))
+        .unwrap_or_default();
+    let uri_str = format!("ws://{uri}{subpath}{query}");
+    let mut request = uri_str.as_str().into_client_request().map_err(|_| {
+        JsonError::from(StatusCode::BAD_GATEWAY).with_debug("Invalid uri. Could not parse")
+    })?;
+
+    // Set by the auth middleware
+    for name in identity::header_names(&state.config.identity) {
+        if let Some(value) = headers.get(&name) {
+            request.headers_mut().insert(name, value.clone());
         }
-        Err(e) => {
-            tracing::error!("Failed to connect to upstream WebSocket: {}", e);
-            let _ = client_socket
-                .send(axum::extract::ws::Message::Close(Some(
-                    axum::extract::ws::CloseFrame {
-                        code: 1011,
-                        reason: axum::extract::ws::Utf8Bytes::from_static(
-                            "Upstream connection failed",
-                        ),
-                    },
-                )))
-                .await;
+    }
+
+    // Connect upstream before upgrading, so a failing instance is reported to
+    // its circuit breaker and the client gets a proper error status
+    let (target_socket, _) =
+        match tokio::time::timeout(timeouts.connect_timeout(), connect_async(request)).await {
+            Ok(Ok(socket)) => socket,
+            Ok(Err(e)) => {
+                circuit_breaker::record(&cb, &uri, false);
+                tracing::error!("Failed to connect to upstream WebSocket: {}", e);
+                return Err(JsonError::from(StatusCode::BAD_GATEWAY)
+                    .with_debug("Could not connect to upstream WebSocket"));
+            }
+            Err(_) => {
+                circuit_breaker::record(&cb, &uri, false);
+                return Err(upstream_timeout());
+            }
+        };
+    circuit_breaker::record(&cb, &uri, true);
+
+    let idle_timeout = timeouts.idle_timeout();
+    Ok(ws.on_upgrade(move |ws| proxy_websockets(ws, target_socket, uri_str, idle_timeout)))
+}
+
+async fn proxy_websockets(
+    client_socket: WebSocket,
+    target_socket: WebSocketStream<MaybeTlsStream<TcpStream>>,
+    url: String,
+    idle_timeout: Option<Duration>,
+) {
+    let (mut client_tx, mut client_rx) = client_socket.split();
+    let (mut target_tx, mut target_rx) = target_socket.split();
+
+    loop {
+        // Recreated on every message, so it only fires after `idle_timeout` of silence
+        let idle = async {
+            match idle_timeout {
+                Some(timeout) => tokio::time::sleep(timeout).await,
+                None => std::future::pending().await,
+            }
+        };
+
+        tokio::select! {
+            msg = client_rx.next() => {
+                let Some(Ok(msg)) = msg else { break };
+                if let Err(e) = target_tx.send(to_tungstenite(msg)).await {
+                    tracing::error!("Error sending to target: {}", e);
+                    break;
+                }
+            }
+            msg = target_rx.next() => {
+                let Some(Ok(msg)) = msg else { break };
+                if let Err(e) = client_tx.send(from_tungstenite(msg)).await {
+                    tracing::error!("Error sending to client: {}", e);
+                    break;
+                }
+            }
+            _ = idle => {
+                tracing::info!("WebSocket proxy connection idle for too long: {}", url);
+                break;
+            }
         }
     }
+
+    let _ = target_tx.close().await;
+    let _ = client_tx.close().await;
+
+    tracing::info!("WebSocket proxy connection closed: {}", url);
 }
 
 fn to_tungstenite(msg: Message) -> tokio_tungstenite::tungstenite::Message {
@@ -0,0 +1,119 @@
+use std::{sync::Arc, time::Duration};
+
+use dashmap::DashMap;
+use futures_util::future::join_all;
+use reqwest::Client;
+use tokio::task::JoinHandle;
+
+use crate::{
+    config::{HealthCheckConfig, Instance},
+    state::AppState,
+};
+
+/// Health of every probed instance, keyed by address.
+/// Instances without an entry haven't been probed and are considered healthy.
+pub(crate) type HealthRegistry = Arc<DashMap<String, InstanceHealth>>;
+
+#[derive(Debug)]
+pub struct InstanceHealth {
+    pub healthy: bool,
+    pub consecutive_successes: u32,
+    pub consecutive_failures: u32,
+}
+
+impl Default for InstanceHealth {
+    fn default() -> Self {
+        Self {
+            healthy: true,
+            consecutive_successes: 0,
+            consecutive_failures: 0,
+        }
+    }
+}
+
+impl InstanceHealth {
+    fn record(&mut self, success: bool, config: &HealthCheckConfig, address: &str) {
+        if success {
+            self.consecutive_successes += 1;
+            self.consecutive_failures = 0;
+            if !self.healthy && self.consecutive_successes >= config.healthy_threshold {
+                self.healthy = true;
+                tracing::info!(instance = address, "Instance marked as healthy");
+            }
+        } else {
+            self.consecutive_failures += 1;
+            self.consecutive_successes = 0;
+            if self.healthy && self.consecutive_failures >= config.unhealthy_threshold {
+                self.healthy = false;
+                tracing::warn!(instance = address, "Instance marked as unhealthy");
+            }
+        }
+    }
+}
+
+pub(crate) fn is_healthy(health: &HealthRegistry, instance: &Instance) -> bool {
+    health
+        .get(&instance.address)
+        .map(|h| h.healthy)
+        .unwrap_or(true)
+}
+
+/// Spawns one background prober per service with a `health_check` section.
+pub(crate) fn spawn(state: &AppState) -> Vec<JoinHandle<()>> {
+    state
+        .config
+        .services
+        .iter()
+        .filter_map(|(name, service)| {
+            let config = service.health_check.clone()?;
+            Some(tokio::spawn(probe_service(
+                name.clone(),
+                service.instances.clone(),
+                config,
+                state.health.clone(),
+            )))
+        })
+        .collect()
+}
+
+async fn probe_service(
+    name: String,
+    instances: Vec<Instance>,
+    config: HealthCheckConfig,
+    health: HealthRegistry,
+) {
+    let client = match Client::builder()
+        .timeout(Duration::from_secs(config.timeout_seconds))
+        .build()
+    {
+        Ok(client) => client,
+        Err(e) => {
+            tracing::error!(service = name, "Could not build health check client: {e}");
+            return;
+        }
+    };
+
+    let mut interval = tokio::time::interval(Duration::from_secs(config.interval_seconds.max(1)));
+
+    loop {
+        interval.tick().await;
+
+        let (client, config) = (&client, &config);
+        let probes = instances.iter().map(|instance| async move {
+            let url = format!("http://{}{}", instance.address, config.path);
+            let success = match client.get(url).send().await {
+                Ok(resp) => resp.status().as_u16() == config.expected_status,
+                Err(_) => false,
+            };
+            (instance, success)
+        });
+
+        for (instance, success) in join_all(probes).await {
+            health.entry(instance.address.clone()).or_default().record(
+                success,
+                config,
+                &instance.address,
+            );
+        }
+    }
+}
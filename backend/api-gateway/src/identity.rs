@@ -0,0 +1,122 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use axum::http::{HeaderMap, HeaderName, HeaderValue, Method};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::{config::IdentityConfig, jwt::Claims};
+
+pub(crate) const USER_ID_HEADER: HeaderName = HeaderName::from_static("x-user-id");
+/// Unix time of the signature, so upstreams can reject stale requests
+pub(crate) const TIMESTAMP_HEADER: HeaderName = HeaderName::from_static("x-gateway-timestamp");
+pub(crate) const SIGNATURE_HEADER: HeaderName = HeaderName::from_static("x-gateway-signature");
+
+const SECRET_ENV: &str = "GATEWAY_SIGNING_SECRET";
+
+pub(crate) fn validate(config: &IdentityConfig) -> anyhow::Result<()> {
+    for header in config.claims.values() {
+        HeaderName::try_from(header.as_str())?;
+    }
+    if config.sign && std::env::var(SECRET_ENV).is_err() {
+        anyhow::bail!("`identity.sign` requires the {SECRET_ENV} env variable");
+    }
+    Ok(())
+}
+
+/// Every header the gateway vouches for.
+pub(crate) fn header_names(config: &IdentityConfig) -> impl Iterator<Item = HeaderName> + '_ {
+    [USER_ID_HEADER, TIMESTAMP_HEADER, SIGNATURE_HEADER]
+        .into_iter()
+        .chain(
+            config
+                .claims
+                .values()
+                .filter_map(|header| HeaderName::try_from(header.as_str()).ok()),
+        )
+}
+
+/// Replaces any client supplied identity header with the verified `claims`,
+/// if there are any, and signs them when enabled.
+///
+/// The signature is the hex encoded HMAC-SHA256 of the timestamp, the method,
+/// the upstream path and query, and then every identity header as `name:value`
+/// sorted by name, each followed by `\n`.
+pub(crate) fn forward(
+    headers: &mut HeaderMap,
+    config: &IdentityConfig,
+    claims: Option<&Claims>,
+    method: &Method,
+    path_and_query: &str,
+) {
+    for name in header_names(config) {
+        headers.remove(name);
+    }
+
+    let mut identity = Vec::new();
+    if let Some(claims) = claims {
+        match HeaderValue::from_str(&claims.user_id) {
+            Ok(value) => identity.push((USER_ID_HEADER, value)),
+            Err(_) => tracing::warn!("`user_id` claim can't be sent as a header"),
+        }
+
+        for (claim, header) in config.claims.iter() {
+            let (Some(value), Ok(name)) = (
+                claims.extra.get(claim),
+                HeaderName::try_from(header.as_str()),
+            ) else {
+                continue;
+            };
+            match HeaderValue::from_str(&claim_value(value)) {
+                Ok(value) => identity.push((name, value)),
+                Err(_) => tracing::warn!(claim, "Claim can't be sent as a header"),
+            }
+        }
+    }
+    identity.sort_by(|(a, _), (b, _)| a.as_str().cmp(b.as_str()));
+
+    if config.sign {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let signature = sign(timestamp, method, path_and_query, &identity);
+        headers.insert(TIMESTAMP_HEADER, HeaderValue::from(timestamp));
+        headers.insert(SIGNATURE_HEADER, HeaderValue::from_str(&signature).unwrap());
+    }
+
+    for (name, value) in identity {
+        headers.insert(name, value);
+    }
+}
+
+fn sign(
+    timestamp: u64,
+    method: &Method,
+    path_and_query: &str,
+    identity: &[(HeaderName, HeaderValue)],
+) -> String {
+    let secret = std::env::var(SECRET_ENV).expect("env variable GATEWAY_SIGNING_SECRET is not set");
+    let mut mac =
+        Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC takes any key size");
+
+    mac.update(format!("{timestamp}\n{method}\n{path_and_query}\n").as_bytes());
+    for (name, value) in identity {
+        mac.update(name.as_str().as_bytes());
+        mac.update(b":");
+        mac.update(value.as_bytes());
+        mac.update(b"\n");
+    }
+
+    hex::encode(mac.finalize().into_bytes())
+}
+
+/// Strings are sent as is and lists comma separated, anything else as JSON.
+fn claim_value(value: &serde_json::Value) -> String {
+    match value {
+        serde_json::Value::String(s) => s.clone(),
+        serde_json::Value::Array(items) => {
+            items.iter().map(claim_value).collect::<Vec<_>>().join(",")
+        }
+        other => other.to_string(),
+    }
+}
@@ -0,0 +1,150 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use arc_swap::ArcSwap;
+use jsonwebtoken::{
+    Algorithm, DecodingKey,
+    jwk::{AlgorithmParameters, JwkSet},
+};
+use reqwest::Client;
+use tokio::sync::Mutex;
+
+use crate::config::JwtConfig;
+
+const FETCH_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone)]
+pub(crate) struct VerifyingKey {
+    pub(crate) key: DecodingKey,
+    pub(crate) algorithm: Algorithm,
+}
+
+#[derive(Default)]
+struct KeySet {
+    keys: HashMap<String, VerifyingKey>,
+    /// Last fetch attempt, successful or not
+    fetched_at: Option<Instant>,
+}
+
+/// Public keys published by the token issuer, cached by `kid`. An unknown
+/// `kid` triggers a new fetch, so keys added during a rotation are picked up
+/// while the old ones still verify the tokens they signed.
+pub(crate) struct Jwks {
+    url: String,
+    max_age: Duration,
+    refresh_cooldown: Duration,
+    client: Client,
+    current: ArcSwap<KeySet>,
+    /// Concurrent refreshes wait for the first one instead of fetching again
+    refreshing: Mutex<()>,
+}
+
+impl fmt::Debug for Jwks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Jwks")
+            .field("url", &self.url)
+            .field("keys", &self.current.load().keys.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl Jwks {
+    pub(crate) fn new(url: String, config: &JwtConfig) -> Arc<Self> {
+        Arc::new(Self {
+            url,
+            max_age: Duration::from_secs(config.jwks_max_age_seconds),
+            refresh_cooldown: Duration::from_secs(config.jwks_refresh_cooldown_seconds),
+            client: Client::new(),
+            current: ArcSwap::from_pointee(KeySet::default()),
+            refreshing: Mutex::new(()),
+        })
+    }
+
+    /// Never fetches, for callers that can't wait on the network.
+    pub(crate) fn cached(&self, kid: &str) -> Option<VerifyingKey> {
+        self.current.load().keys.get(kid).cloned()
+    }
+
+    pub(crate) async fn get(&self, kid: &str) -> Option<VerifyingKey> {
+        let current = self.current.load();
+        let stale = current
+            .fetched_at
+            .is_none_or(|fetched_at| fetched_at.elapsed() >= self.max_age);
+        if !stale && let Some(key) = current.keys.get(kid) {
+            return Some(key.clone());
+        }
+
+        self.refresh(stale).await;
+        self.cached(kid)
+    }
+
+    async fn refresh(&self, stale: bool) {
+        let _refreshing = self.refreshing.lock().await;
+
+        // Unless the keys are too old, garbage `kid`s can't make us fetch on every request
+        let fetched_at = self.current.load().fetched_at;
+        let cooldown = if stale {
+            self.max_age
+        } else {
+            self.refresh_cooldown
+        };
+        if fetched_at.is_some_and(|fetched_at| fetched_at.elapsed() < cooldown) {
+            return;
+        }
+
+        let keys = match self.fetch().await {
+            Ok(keys) => keys,
+            Err(e) => {
+                // Keep trusting the keys we have until the issuer is back
+                tracing::error!(url = self.url, "Could not fetch JWKS: {e}");
+                self.current.load().keys.clone()
+            }
+        };
+        self.current.store(Arc::new(KeySet {
+            keys,
+            fetched_at: Some(Instant::now()),
+        }));
+    }
+
+    async fn fetch(&self) -> anyhow::Result<HashMap<String, VerifyingKey>> {
+        let body = self
+            .client
+            .get(&self.url)
+            .timeout(FETCH_TIMEOUT)
+            .send()
+            .await?
+            .error_for_status()?
+            .bytes()
+            .await?;
+        let set: JwkSet = serde_json::from_slice(&body)?;
+
+        let mut keys = HashMap::new();
+        for jwk in &set.keys {
+            let (Some(kid), Some(alg)) = (&jwk.common.key_id, jwk.common.key_algorithm) else {
+                tracing::warn!(url = self.url, "Ignoring JWK without `kid` or `alg`");
+                continue;
+            };
+            // A published symmetric key would let anyone sign tokens
+            if matches!(jwk.algorithm, AlgorithmParameters::OctetKey(_)) {
+                tracing::warn!(kid, "Ignoring symmetric JWK");
+                continue;
+            }
+            match (
+                Algorithm::from_str(&alg.to_string()),
+                DecodingKey::from_jwk(jwk),
+            ) {
+                (Ok(algorithm), Ok(key)) => {
+                    keys.insert(kid.clone(), VerifyingKey { key, algorithm });
+                }
+                _ => tracing::warn!(kid, "Ignoring unsupported JWK"),
+            }
+        }
+
+        Ok(keys)
+    }
+}
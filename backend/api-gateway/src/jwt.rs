@@ -1,8 +1,123 @@
+use std::{collections::HashMap, sync::Arc};
+
+use axum::{extract::Request, http::header};
+use jsonwebtoken::{Algorithm, DecodingKey, Validation, decode, decode_header};
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize)]
+use crate::{
+    config::JwtConfig,
+    jwks::{Jwks, VerifyingKey},
+    state::AppState,
+};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub(crate) struct Claims {
     exp: u64,
+    /// Identifies the token, to revoke it on logout
+    pub(crate) jti: Option<String>,
+    /// Session the token was issued for, revoked with all of its tokens
+    pub(crate) sid: Option<String>,
     // Actual payload
-    user_id: String,
+    pub(crate) user_id: String,
+    /// Any other claim, which can be forwarded to upstreams
+    #[serde(flatten)]
+    pub(crate) extra: HashMap<String, serde_json::Value>,
+}
+
+impl Claims {
+    pub(crate) fn roles(&self) -> Vec<&str> {
+        self.list("roles")
+    }
+
+    pub(crate) fn scopes(&self) -> Vec<&str> {
+        self.list("scope")
+    }
+
+    /// Lists are either JSON arrays or space separated strings, as OAuth 2.0 scopes
+    fn list(&self, claim: &str) -> Vec<&str> {
+        match self.extra.get(claim) {
+            Some(serde_json::Value::String(s)) => s.split_whitespace().collect(),
+            Some(serde_json::Value::Array(items)) => {
+                items.iter().filter_map(|item| item.as_str()).collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum TokenVerifier {
+    /// Asymmetric keys published by auth-service, looked up by `kid`
+    Jwks(Arc<Jwks>),
+    /// HS256 with the `JWT_SECRET` env variable
+    Secret,
+}
+
+impl TokenVerifier {
+    pub(crate) fn new(config: &JwtConfig) -> Self {
+        match &config.jwks_url {
+            Some(url) => TokenVerifier::Jwks(Jwks::new(url.clone(), config)),
+            None => TokenVerifier::Secret,
+        }
+    }
+
+    pub(crate) async fn verify(&self, token: &str) -> Option<Claims> {
+        match self {
+            TokenVerifier::Jwks(jwks) => {
+                let kid = decode_header(token).ok()?.kid?;
+                decode_with(token, &jwks.get(&kid).await?)
+            }
+            TokenVerifier::Secret => decode_with(token, &secret_key()),
+        }
+    }
+
+    /// Like `verify`, but never fetches keys, for callers that can't wait on the network.
+    pub(crate) fn verify_cached(&self, token: &str) -> Option<Claims> {
+        match self {
+            TokenVerifier::Jwks(jwks) => {
+                let kid = decode_header(token).ok()?.kid?;
+                decode_with(token, &jwks.cached(&kid)?)
+            }
+            TokenVerifier::Secret => decode_with(token, &secret_key()),
+        }
+    }
+}
+
+fn secret_key() -> VerifyingKey {
+    let secret = std::env::var("JWT_SECRET").expect("env variable JWT_SECRET is not set");
+    VerifyingKey {
+        key: DecodingKey::from_secret(secret.as_ref()),
+        algorithm: Algorithm::HS256,
+    }
+}
+
+fn decode_with(token: &str, key: &VerifyingKey) -> Option<Claims> {
+    decode::<Claims>(token, &key.key, &Validation::new(key.algorithm))
+        .map(|data| data.claims)
+        .ok()
+}
+
+/// Prefer the claims verified by the auth middleware. Unprotected routes
+/// (e.g. WebSockets sending the token as a subprotocol) get the token decoded here.
+pub(crate) fn user_id(req: &Request) -> Option<String> {
+    if let Some(claims) = req.extensions().get::<Claims>() {
+        return Some(claims.user_id.clone());
+    }
+
+    let bearer = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+    let protocol = req
+        .headers()
+        .get(header::SEC_WEBSOCKET_PROTOCOL)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(',').map(str::trim).next());
+
+    let state = req.extensions().get::<Arc<AppState>>()?;
+    bearer
+        .or(protocol)
+        .and_then(|token| state.tokens.verify_cached(token))
+        .map(|claims| claims.user_id)
 }
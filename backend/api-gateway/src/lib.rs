@@ -1,3 +1,5 @@
+use std::net::SocketAddr;
+
 use axum::{
     Router,
     http::{HeaderValue, Method},
@@ -15,7 +17,7 @@ use crate::{
         auth::AuthLayer, load_balancer::LoadBalancerLayer, parser::ParserLayer,
         rate_limit::RateLimitLayer, router::RouterLayer,
     },
-    state::AppState,
+    state::SharedState,
 };
 
 pub mod config;
@@ -23,14 +25,32 @@ pub mod config;
 pub(crate) mod circuit_breaker;
 pub(crate) mod error;
 pub(crate) mod handler;
+pub(crate) mod health_check;
+pub(crate) mod identity;
+pub(crate) mod jwks;
 pub(crate) mod jwt;
 pub(crate) mod load_balancer;
 pub(crate) mod middleware;
+pub(crate) mod rate_limit;
+pub(crate) mod reload;
+pub(crate) mod retry;
+pub(crate) mod revocation;
+pub(crate) mod routing;
 pub(crate) mod state;
 
 pub fn app(config: Config) -> Router {
-    let state = AppState::new(config.clone());
+    router(SharedState::new(config))
+}
+
+/// Like `app`, but reloading the config at `config_path` when it changes or on SIGHUP.
+pub fn reloading_app(config_path: &str) -> anyhow::Result<Router> {
+    let config = config::load_from_path(config_path)?;
+    let state = SharedState::new(config);
+    reload::watch(state.clone(), config_path.to_owned())?;
+    Ok(router(state))
+}
 
+fn router(state: SharedState) -> Router {
     let origins: Vec<HeaderValue> = var("CORS_ORIGIN")
         .expect("CORS_ORIGIN env not set")
         .split(",")
@@ -62,17 +82,13 @@ pub fn app(config: Config) -> Router {
         .route("/api/{*path}", any(handler::http_handler))
         .route("/ws/{*path}", any(handler::ws_handler))
         .layer(TraceLayer::new_for_http())
-        .layer(RateLimitLayer::from_config(config.rate_limit))
-        .layer(LoadBalancerLayer::with_circuit_breaker())
-        .layer(AuthLayer {
-            state: state.clone(),
-        })
-        .layer(RouterLayer {
-            state: state.clone(),
-        })
+        .layer(LoadBalancerLayer)
+        // Before picking an instance, so rejected requests don't count against it
+        .layer(RateLimitLayer)
+        .layer(AuthLayer)
+        .layer(RouterLayer { state })
         .layer(ParserLayer)
         .layer(cors_layer)
-        .with_state(state)
 }
 
 pub async fn run() -> anyhow::Result<()> {
@@ -80,14 +96,17 @@ pub async fn run() -> anyhow::Result<()> {
         .with(tracing_subscriber::fmt::layer())
         .init();
 
-    let config = config::load()?;
-    let app = app(config);
+    let app = reloading_app(&config::path())?;
 
     let port: String = dotenv::var("PORT").unwrap_or("3000".to_owned());
     let addr = format!("0.0.0.0:{port}");
     tracing::info!("API Gateway listening on port {addr}");
     let listener = tokio::net::TcpListener::bind(addr).await?;
-    axum::serve(listener, app).await?;
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await?;
 
     Ok(())
 }
@@ -1,28 +1,92 @@
-use crate::{config::Strategy, load_balancer::strategies::random::RandomStrategy};
+use std::{fmt::Debug, sync::Arc, time::Duration};
+
+use axum::{body::Body, extract::Request};
+use futures_util::StreamExt;
+
+use crate::{
+    config::{Instance, Strategy},
+    load_balancer::strategies::{
+        consistent_hash::ConsistentHashStrategy, least_connections::LeastConnectionsStrategy,
+        p2c_ewma::P2cEwmaStrategy, random::RandomStrategy, round_robin::RoundRobinStrategy,
+        weighted_round_robin::WeightedRoundRobinStrategy,
+    },
+};
 
 pub mod strategies;
 
-pub struct LoadBalancer(Strategy);
+/// Per-service load balancer. It is built once from the service config and
+/// lives in `AppState`, so strategies can keep state across requests.
+#[derive(Debug)]
+pub struct LoadBalancer(Box<dyn LoadBalancingStrategy>);
 
 impl LoadBalancer {
-    pub fn new(s: Strategy) -> Self {
-        LoadBalancer(s)
+    pub fn new(s: &Strategy, instances: &[Instance]) -> Self {
+        LoadBalancer(s.build(instances))
     }
 
-    pub fn select_instance<T: Clone>(&self, instances: &[T]) -> Option<T> {
-        let mut strategy = self.0.build();
-        strategy.select_instance(instances)
+    pub fn select_instance(&self, instances: &[Instance], req: &Request) -> Option<Instance> {
+        self.0.select_instance(instances, req)
+    }
+
+    /// Feeds the time an upstream took to answer back into the strategy.
+    pub fn record_latency(&self, instance: &Instance, latency: Duration) {
+        self.0.on_response(instance, latency);
+    }
+
+    /// Marks a request to `instance` as in-flight until the returned guard is dropped.
+    pub fn track(self: &Arc<Self>, instance: &Instance) -> InFlightGuard {
+        self.0.on_request_start(instance);
+        InFlightGuard {
+            lb: self.clone(),
+            instance: instance.clone(),
+        }
     }
 }
 
 impl Strategy {
-    pub fn build<T: Clone>(&self) -> Box<dyn LoadBalancingStrategy<T>> {
+    /// `instances` are all the ones configured, requests are then balanced
+    /// among the ones available at the time.
+    pub fn build(&self, instances: &[Instance]) -> Box<dyn LoadBalancingStrategy> {
         match self {
             Strategy::Random => Box::new(RandomStrategy),
+            Strategy::RoundRobin => Box::new(RoundRobinStrategy::default()),
+            Strategy::WeightedRoundRobin => Box::new(WeightedRoundRobinStrategy::default()),
+            Strategy::LeastConnections => Box::new(LeastConnectionsStrategy::default()),
+            Strategy::P2cEwma => Box::new(P2cEwmaStrategy::default()),
+            Strategy::ConsistentHash { key, virtual_nodes } => Box::new(
+                ConsistentHashStrategy::new(key.clone(), *virtual_nodes, instances),
+            ),
         }
     }
 }
 
-pub trait LoadBalancingStrategy<T: Clone> {
-    fn select_instance(&mut self, instances: &[T]) -> Option<T>;
+pub trait LoadBalancingStrategy: Debug + Send + Sync {
+    fn select_instance(&self, instances: &[Instance], req: &Request) -> Option<Instance>;
+
+    fn on_request_start(&self, _instance: &Instance) {}
+
+    fn on_request_end(&self, _instance: &Instance) {}
+
+    fn on_response(&self, _instance: &Instance, _latency: Duration) {}
+}
+
+pub struct InFlightGuard {
+    lb: Arc<LoadBalancer>,
+    instance: Instance,
+}
+
+impl InFlightGuard {
+    /// Keeps the request in-flight until `body` has been fully streamed or dropped.
+    pub fn attach(self, body: Body) -> Body {
+        Body::from_stream(body.into_data_stream().map(move |chunk| {
+            let _ = &self;
+            chunk
+        }))
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.lb.0.on_request_end(&self.instance);
+    }
 }
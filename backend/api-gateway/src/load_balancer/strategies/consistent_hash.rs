@@ -0,0 +1,93 @@
+use std::{collections::BTreeMap, net::SocketAddr};
+
+use axum::{
+    extract::{ConnectInfo, Request},
+    http::header,
+};
+use xxhash_rust::xxh3::xxh3_64;
+
+use crate::{
+    config::{HashKey, Instance},
+    jwt::user_id,
+    load_balancer::{LoadBalancingStrategy, strategies::random::RandomStrategy},
+    routing::RouteParams,
+};
+
+/// Hash ring with virtual nodes: every instance is placed `virtual_nodes` times
+/// on the ring, and a key goes to the first instance found clockwise from its
+/// hash. Adding or removing an instance only remaps the keys next to its nodes.
+///
+/// The ring holds every configured instance and is built once. Instances that
+/// can't take the request (unhealthy, open circuit, already tried) are skipped
+/// while walking it, which maps their keys as a ring without them would.
+#[derive(Debug)]
+pub struct ConsistentHashStrategy {
+    key: HashKey,
+    ring: BTreeMap<u64, String>,
+}
+
+impl ConsistentHashStrategy {
+    pub fn new(key: HashKey, virtual_nodes: usize, instances: &[Instance]) -> Self {
+        let ring = instances
+            .iter()
+            .flat_map(|instance| {
+                (0..virtual_nodes.max(1)).map(move |i| {
+                    (
+                        xxh3_64(format!("{}#{i}", instance.address).as_bytes()),
+                        instance.address.clone(),
+                    )
+                })
+            })
+            .collect();
+
+        Self { key, ring }
+    }
+
+    /// The first node clockwise from `hash` that is one of `instances`
+    fn lookup<'a>(&self, hash: u64, instances: &'a [Instance]) -> Option<&'a Instance> {
+        self.ring
+            .range(hash..)
+            .chain(self.ring.range(..hash))
+            .find_map(|(_, address)| instances.iter().find(|i| &i.address == address))
+    }
+
+    fn routing_key(&self, req: &Request) -> Option<String> {
+        match &self.key {
+            HashKey::UserId => user_id(req),
+            HashKey::Header(name) => req
+                .headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_owned),
+            HashKey::Cookie(name) => req
+                .headers()
+                .get_all(header::COOKIE)
+                .iter()
+                .filter_map(|v| v.to_str().ok())
+                .flat_map(|v| v.split(';'))
+                .filter_map(|c| c.trim().split_once('='))
+                .find(|(k, _)| k == name)
+                .map(|(_, v)| v.to_owned()),
+            HashKey::ClientIp => req
+                .extensions()
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(addr)| addr.ip().to_string()),
+            HashKey::Param(name) => req
+                .extensions()
+                .get::<RouteParams>()
+                .and_then(|params| params.get(name))
+                .map(str::to_owned),
+        }
+    }
+}
+
+impl LoadBalancingStrategy for ConsistentHashStrategy {
+    fn select_instance(&self, instances: &[Instance], req: &Request) -> Option<Instance> {
+        let Some(key) = self.routing_key(req) else {
+            // Nothing to be sticky on
+            return RandomStrategy.select_instance(instances, req);
+        };
+
+        self.lookup(xxh3_64(key.as_bytes()), instances).cloned()
+    }
+}
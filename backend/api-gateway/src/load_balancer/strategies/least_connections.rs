@@ -0,0 +1,42 @@
+use axum::extract::Request;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use dashmap::DashMap;
+
+use crate::{config::Instance, load_balancer::LoadBalancingStrategy};
+
+#[derive(Debug, Default)]
+pub struct LeastConnectionsStrategy {
+    in_flight: DashMap<String, AtomicUsize>,
+}
+
+impl LeastConnectionsStrategy {
+    fn in_flight(&self, instance: &Instance) -> usize {
+        self.in_flight
+            .get(&instance.address)
+            .map(|count| count.load(Ordering::Relaxed))
+            .unwrap_or(0)
+    }
+}
+
+impl LoadBalancingStrategy for LeastConnectionsStrategy {
+    fn select_instance(&self, instances: &[Instance], _req: &Request) -> Option<Instance> {
+        instances
+            .iter()
+            .min_by_key(|instance| self.in_flight(instance))
+            .cloned()
+    }
+
+    fn on_request_start(&self, instance: &Instance) {
+        self.in_flight
+            .entry(instance.address.clone())
+            .or_default()
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn on_request_end(&self, instance: &Instance) {
+        if let Some(count) = self.in_flight.get(&instance.address) {
+            let _ = count.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |c| c.checked_sub(1));
+        }
+    }
+}
@@ -1 +1,6 @@
+pub mod consistent_hash;
+pub mod least_connections;
+pub mod p2c_ewma;
 pub mod random;
+pub mod round_robin;
+pub mod weighted_round_robin;
@@ -0,0 +1,116 @@
+use axum::extract::Request;
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+use rand::seq::index;
+
+use crate::{config::Instance, load_balancer::LoadBalancingStrategy};
+
+/// Time it takes for an observed latency to lose most of its weight.
+const DECAY: Duration = Duration::from_secs(10);
+
+/// Power of two choices: pick two random instances and keep the one with the
+/// lowest `latency * (pending + 1)`, where latency is a decaying moving average
+/// of the observed response times.
+#[derive(Debug, Default)]
+pub struct P2cEwmaStrategy {
+    loads: DashMap<String, InstanceLoad>,
+}
+
+#[derive(Debug)]
+struct InstanceLoad {
+    pending: usize,
+    // Nanoseconds
+    rtt_estimate: f64,
+    last_update: Instant,
+}
+
+impl Default for InstanceLoad {
+    fn default() -> Self {
+        Self {
+            pending: 0,
+            rtt_estimate: 0.0,
+            last_update: Instant::now(),
+        }
+    }
+}
+
+impl InstanceLoad {
+    /// Decays the estimate towards zero while there are no new samples,
+    /// so an instance that was slow in the past eventually gets retried.
+    fn decayed_rtt(&self, now: Instant) -> f64 {
+        let elapsed = now
+            .saturating_duration_since(self.last_update)
+            .as_secs_f64();
+        self.rtt_estimate * (-elapsed / DECAY.as_secs_f64()).exp()
+    }
+
+    fn observe(&mut self, rtt: Duration, now: Instant) {
+        let rtt = rtt.as_nanos() as f64;
+        let decayed = self.decayed_rtt(now);
+
+        // Peak-sensitive: latency spikes are taken as-is, recoveries are smoothed
+        self.rtt_estimate = if rtt > decayed {
+            rtt
+        } else {
+            let elapsed = now
+                .saturating_duration_since(self.last_update)
+                .as_secs_f64();
+            let weight = (-elapsed / DECAY.as_secs_f64()).exp();
+            self.rtt_estimate * weight + rtt * (1.0 - weight)
+        };
+        self.last_update = now;
+    }
+
+    fn score(&self, now: Instant) -> f64 {
+        self.decayed_rtt(now) * (self.pending + 1) as f64
+    }
+}
+
+impl P2cEwmaStrategy {
+    fn score(&self, instance: &Instance, now: Instant) -> f64 {
+        self.loads
+            .get(&instance.address)
+            .map(|load| load.score(now))
+            .unwrap_or(0.0)
+    }
+}
+
+impl LoadBalancingStrategy for P2cEwmaStrategy {
+    fn select_instance(&self, instances: &[Instance], _req: &Request) -> Option<Instance> {
+        if instances.len() < 2 {
+            return instances.first().cloned();
+        }
+
+        let mut rng = rand::rng();
+        let picked = index::sample(&mut rng, instances.len(), 2);
+        let (a, b) = (&instances[picked.index(0)], &instances[picked.index(1)]);
+
+        let now = Instant::now();
+        if self.score(a, now) <= self.score(b, now) {
+            Some(a.clone())
+        } else {
+            Some(b.clone())
+        }
+    }
+
+    fn on_request_start(&self, instance: &Instance) {
+        self.loads
+            .entry(instance.address.clone())
+            .or_default()
+            .pending += 1;
+    }
+
+    fn on_request_end(&self, instance: &Instance) {
+        if let Some(mut load) = self.loads.get_mut(&instance.address) {
+            load.pending = load.pending.saturating_sub(1);
+        }
+    }
+
+    fn on_response(&self, instance: &Instance, latency: Duration) {
+        self.loads
+            .entry(instance.address.clone())
+            .or_default()
+            .observe(latency, Instant::now());
+    }
+}
@@ -1,11 +1,13 @@
+use axum::extract::Request;
 use rand::seq::IndexedRandom;
 
-use crate::load_balancer::LoadBalancingStrategy;
+use crate::{config::Instance, load_balancer::LoadBalancingStrategy};
 
+#[derive(Debug)]
 pub struct RandomStrategy;
 
-impl<T: Clone> LoadBalancingStrategy<T> for RandomStrategy {
-    fn select_instance(&mut self, instances: &[T]) -> Option<T> {
+impl LoadBalancingStrategy for RandomStrategy {
+    fn select_instance(&self, instances: &[Instance], _req: &Request) -> Option<Instance> {
         let mut rng = rand::rng();
         instances.choose(&mut rng).cloned()
     }
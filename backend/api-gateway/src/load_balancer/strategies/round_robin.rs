@@ -0,0 +1,20 @@
+use axum::extract::Request;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{config::Instance, load_balancer::LoadBalancingStrategy};
+
+#[derive(Debug, Default)]
+pub struct RoundRobinStrategy {
+    next: AtomicUsize,
+}
+
+impl LoadBalancingStrategy for RoundRobinStrategy {
+    fn select_instance(&self, instances: &[Instance], _req: &Request) -> Option<Instance> {
+        if instances.is_empty() {
+            return None;
+        }
+
+        let idx = self.next.fetch_add(1, Ordering::Relaxed) % instances.len();
+        instances.get(idx).cloned()
+    }
+}
@@ -0,0 +1,39 @@
+use axum::extract::Request;
+use std::{collections::HashMap, sync::Mutex};
+
+use crate::{config::Instance, load_balancer::LoadBalancingStrategy};
+
+/// Smooth weighted round-robin (the nginx variant): every pick, each instance
+/// gains its weight, the highest one is chosen and pays back the total weight.
+/// Instances get picked proportionally to their weight without bursts.
+#[derive(Debug, Default)]
+pub struct WeightedRoundRobinStrategy {
+    current_weights: Mutex<HashMap<String, i64>>,
+}
+
+impl LoadBalancingStrategy for WeightedRoundRobinStrategy {
+    fn select_instance(&self, instances: &[Instance], _req: &Request) -> Option<Instance> {
+        let mut current_weights = self.current_weights.lock().unwrap();
+
+        let mut total = 0;
+        let mut best: Option<(&Instance, i64)> = None;
+
+        for instance in instances.iter().filter(|i| i.weight > 0) {
+            let weight = i64::from(instance.weight);
+            let current = current_weights.entry(instance.address.clone()).or_default();
+            *current += weight;
+            total += weight;
+
+            if best.is_none_or(|(_, w)| *current > w) {
+                best = Some((instance, *current));
+            }
+        }
+
+        let (instance, _) = best?;
+        if let Some(current) = current_weights.get_mut(&instance.address) {
+            *current -= total;
+        }
+
+        Some(instance.clone())
+    }
+}
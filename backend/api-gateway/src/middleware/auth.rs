@@ -1,5 +1,6 @@
 use std::{
     pin::Pin,
+    sync::Arc,
     task::{Context, Poll},
 };
 
@@ -13,31 +14,31 @@ use axum_extra::{
     headers::{Authorization, authorization::Bearer},
 };
 use hyper::StatusCode;
-use jsonwebtoken::{DecodingKey, Validation, decode};
 use tower::{Layer, Service};
 
-use crate::{error::ErrorResponse, jwt::Claims, middleware::parser::ParsedURI, state::AppState};
+use crate::{
+    config::Route,
+    error::{ErrorResponse, json::JsonError},
+    identity,
+    jwt::Claims,
+    middleware::parser::ParsedURI,
+    state::AppState,
+};
 
 #[derive(Clone)]
-pub(crate) struct AuthLayer {
-    pub(crate) state: AppState,
-}
+pub(crate) struct AuthLayer;
 
 impl<S> Layer<S> for AuthLayer {
     type Service = AuthMiddleware<S>;
 
     fn layer(&self, inner: S) -> Self::Service {
-        AuthMiddleware {
-            inner,
-            state: self.state.clone(),
-        }
+        AuthMiddleware { inner }
     }
 }
 
 #[derive(Clone)]
 pub struct AuthMiddleware<S> {
     inner: S,
-    state: AppState,
 }
 
 impl<S> Service<Request> for AuthMiddleware<S>
@@ -55,37 +56,35 @@ where
 
     fn call(&mut self, mut req: Request) -> Self::Future {
         let mut inner = self.inner.clone();
-        let AppState { config } = self.state.clone();
 
         Box::pin(async move {
-            let ParsedURI { prefix, subpath } = match req.extensions().get::<ParsedURI>() {
-                Some(uri) => uri,
-                None => {
-                    return Ok(StatusCode::INTERNAL_SERVER_ERROR
-                        .with_debug("Could not get `ParsedURI` extension at auth middleware")
-                        .into_response());
-                }
+            // Matched by the router middleware
+            let (Some(route), Some(state), Some(ParsedURI { subpath, .. })) = (
+                req.extensions().get::<Route>().cloned(),
+                req.extensions().get::<Arc<AppState>>().cloned(),
+                req.extensions().get::<ParsedURI>(),
+            ) else {
+                return Ok(StatusCode::INTERNAL_SERVER_ERROR
+                    .with_debug("Could not get request extensions at auth middleware")
+                    .into_response());
             };
-
-            let route = match config
-                .services
-                .get(prefix)
-                .unwrap()
-                .routes
-                .iter()
-                .find(|r| r.path == *subpath)
-            {
-                Some(r) => r,
-                None => return Ok(StatusCode::NOT_FOUND
-                    .with_debug(
-                        "This is a bug! Unexpected route should be handled by router middleware.",
-                    )
-                    .into_response()),
+            // As sent upstream, the signature covers it
+            let path_and_query = match req.uri().query() {
+                Some(query) => format!("{subpath}?{query}"),
+                None => subpath.clone(),
             };
 
             if !route.protected {
                 // If route isn't protected, we don't require the client to send
                 // `Authorization: Bearer {TOKEN}` header, just continue
+                let method = req.method().clone();
+                identity::forward(
+                    req.headers_mut(),
+                    &state.config.identity,
+                    None,
+                    &method,
+                    &path_and_query,
+                );
                 let response = inner.call(req).await?;
                 return Ok(response);
             }
@@ -103,23 +102,80 @@ where
                 }
             };
 
-            let secret = std::env::var("JWT_SECRET").expect("env variable JWT_SECRET is not set");
-
-            if decode::<Claims>(
-                bearer.token(),
-                &DecodingKey::from_secret(secret.as_ref()),
-                &Validation::default(),
-            )
-            .is_err()
-            {
-                return Ok(StatusCode::UNAUTHORIZED
-                    .with_debug("Invalid Authorization header. Could not decode into claim")
-                    .into_response());
+            let claims = match state.tokens.verify(bearer.token()).await {
+                Some(claims) => claims,
+                None => {
+                    return Ok(StatusCode::UNAUTHORIZED
+                        .with_debug("Invalid Authorization header. Could not decode into claim")
+                        .into_response());
+                }
             };
 
-            // Valid JWT token, pass
+            if let Some(revocations) = &state.revocations {
+                // Fails closed, the token may have been revoked since
+                if !revocations.is_current().await {
+                    return Ok(StatusCode::SERVICE_UNAVAILABLE
+                        .with_debug("Revoked tokens could not be fetched")
+                        .into_response());
+                }
+
+                // Sessions are revoked alongside tokens, so either id may be listed
+                let revoked = [&claims.jti, &claims.sid]
+                    .into_iter()
+                    .flatten()
+                    .any(|id| revocations.is_revoked(id));
+                if revoked {
+                    return Ok(StatusCode::UNAUTHORIZED
+                        .with_debug("Token has been revoked")
+                        .into_response());
+                }
+            }
+
+            if let Err(e) = authorize(&route, &claims) {
+                return Ok(e.into_response());
+            }
+
+            // Valid JWT token, pass it on to upstreams
+            let method = req.method().clone();
+            identity::forward(
+                req.headers_mut(),
+                &state.config.identity,
+                Some(&claims),
+                &method,
+                &path_and_query,
+            );
+            req.extensions_mut().insert(claims);
             let response = inner.call(req).await?;
             Ok(response)
         })
     }
 }
+
+/// Checks the roles and scopes required by the route against the claims.
+/// Tokens of OAuth apps, which have a `client_id`, only get into routes granting them a scope.
+fn authorize(route: &Route, claims: &Claims) -> Result<(), JsonError> {
+    if claims.extra.contains_key("client_id") && route.scopes.is_empty() {
+        return Err(JsonError::new(
+            StatusCode::FORBIDDEN,
+            "Route isn't available to apps",
+        ));
+    }
+
+    let roles = claims.roles();
+    if let Some(role) = route.requires.iter().find(|r| !roles.contains(&r.as_str())) {
+        return Err(JsonError::new(
+            StatusCode::FORBIDDEN,
+            format!("Missing required role `{role}`"),
+        ));
+    }
+
+    let scopes = claims.scopes();
+    if let Some(scope) = route.scopes.iter().find(|s| !scopes.contains(&s.as_str())) {
+        return Err(JsonError::new(
+            StatusCode::FORBIDDEN,
+            format!("Missing required scope `{scope}`"),
+        ));
+    }
+
+    Ok(())
+}
@@ -2,58 +2,39 @@ use std::{
     pin::Pin,
     sync::Arc,
     task::{Context, Poll},
-    time::Instant,
 };
 
 use axum::{
     extract::Request,
     response::{IntoResponse, Response},
 };
-use dashmap::DashMap;
 use hyper::StatusCode;
 use tower::{Layer, Service};
 
 use crate::{
-    circuit_breaker::{self, CircuitBreaker},
-    config::Instance,
+    circuit_breaker::{self, CircuitPermit},
+    config::{self, Instance},
     error::ErrorResponse,
+    health_check::is_healthy,
     load_balancer::LoadBalancer,
+    middleware::parser::ParsedURI,
+    state::AppState,
 };
 
 #[derive(Clone)]
-pub struct LoadBalancerLayer {
-    circuit_breaker: Arc<DashMap<String, CircuitBreaker>>,
-}
-
-impl LoadBalancerLayer {
-    pub fn with_circuit_breaker() -> Self {
-        Self {
-            circuit_breaker: Arc::new(DashMap::new()),
-        }
-    }
-}
+pub(crate) struct LoadBalancerLayer;
 
 impl<S> Layer<S> for LoadBalancerLayer {
     type Service = LoadBalancerMiddleware<S>;
 
     fn layer(&self, inner: S) -> Self::Service {
-        LoadBalancerMiddleware::new(inner, self.circuit_breaker.clone())
+        LoadBalancerMiddleware { inner }
     }
 }
 
 #[derive(Clone)]
 pub struct LoadBalancerMiddleware<S> {
     inner: S,
-    circuit_breaker: Arc<DashMap<String, CircuitBreaker>>,
-}
-
-impl<S> LoadBalancerMiddleware<S> {
-    pub fn new(inner: S, circuit_breaker: Arc<DashMap<String, CircuitBreaker>>) -> Self {
-        Self {
-            inner,
-            circuit_breaker,
-        }
-    }
 }
 
 impl<S> Service<Request> for LoadBalancerMiddleware<S>
@@ -71,10 +52,40 @@ where
 
     fn call(&mut self, mut req: Request) -> Self::Future {
         let mut inner = self.inner.clone();
-        let circuit_breaker = self.circuit_breaker.clone();
 
         Box::pin(async move {
-            let service = match req.extensions().get::<crate::config::Service>() {
+            let state = match req.extensions().get::<Arc<AppState>>() {
+                Some(state) => state.clone(),
+                None => {
+                    return Ok(StatusCode::INTERNAL_SERVER_ERROR
+                        .with_debug(
+                            "Could not get `AppState` extension at load balancer middleware",
+                        )
+                        .into_response());
+                }
+            };
+
+            let ParsedURI { prefix, .. } = match req.extensions().get::<ParsedURI>() {
+                Some(uri) => uri,
+                None => {
+                    return Ok(StatusCode::INTERNAL_SERVER_ERROR
+                        .with_debug(
+                            "Could not get `ParsedURI` extension at load balancer middleware",
+                        )
+                        .into_response());
+                }
+            };
+
+            let lb = match state.load_balancers.get(prefix) {
+                Some(lb) => lb.clone(),
+                None => {
+                    return Ok(StatusCode::INTERNAL_SERVER_ERROR
+                        .with_debug("Could not get load balancer for service")
+                        .into_response());
+                }
+            };
+
+            let service = match req.extensions().get::<config::Service>() {
                 Some(svc) => svc,
                 None => {
                     return Ok(StatusCode::INTERNAL_SERVER_ERROR
@@ -83,37 +94,64 @@ where
                 }
             };
 
-            let lb = LoadBalancer::new(service.strategy.clone());
-            let healthy_instances: Vec<_> = service
-                .instances
-                .iter()
-                .filter(|Instance(uri)| match circuit_breaker.get(uri) {
-                    Some(cb) => match cb.state {
-                        circuit_breaker::CircuitState::Closed => true,
-                        circuit_breaker::CircuitState::HalfOpen => true,
-                        circuit_breaker::CircuitState::Open { retry_after } => {
-                            Instant::now() >= retry_after
-                        }
-                    },
-                    None => true,
-                })
-                .cloned()
-                .collect();
-
-            let instance = match lb.select_instance(&healthy_instances[..]) {
+            let (instance, permit) = match pick_instance(&state, service, &lb, &req, &[]) {
                 Some(val) => val,
                 None => {
-                    return Ok(StatusCode::INTERNAL_SERVER_ERROR
-                        .with_debug("Could not get an instance following load balancer strategy")
+                    return Ok(StatusCode::SERVICE_UNAVAILABLE
+                        .with_debug("Could not get an available instance for the service")
                         .into_response());
                 }
             };
 
+            // Keep the instance marked as in-flight until the response body is streamed
+            let guard = lb.track(&instance);
+
             req.extensions_mut().insert(instance);
-            req.extensions_mut().insert(circuit_breaker);
+            req.extensions_mut().insert(state.circuit_breakers.clone());
+            req.extensions_mut().insert(lb.clone());
 
+            // Holds a half-open trial slot until the outcome has been reported
             let response = inner.call(req).await?;
-            Ok(response)
+            drop(permit);
+            Ok(response.map(|body| guard.attach(body)))
         })
     }
 }
+
+/// Selects an instance following the service strategy among the ones that are
+/// healthy, not in `exclude` and admitted by their circuit breaker.
+pub(crate) fn pick_instance(
+    state: &AppState,
+    service: &config::Service,
+    lb: &LoadBalancer,
+    req: &Request,
+    exclude: &[Instance],
+) -> Option<(Instance, CircuitPermit)> {
+    let mut candidates: Vec<_> = service
+        .instances
+        .iter()
+        .filter(|instance| !exclude.contains(instance))
+        .filter(|instance| is_healthy(&state.health, instance))
+        .filter(
+            |instance| match state.circuit_breakers.get(&instance.address) {
+                Some(cb) => cb.is_available(),
+                None => true,
+            },
+        )
+        .cloned()
+        .collect();
+
+    // A half-open breaker may have no trial slot left, the others still can
+    loop {
+        let instance = lb.select_instance(&candidates[..], req)?;
+        let permit = circuit_breaker::acquire(
+            &state.circuit_breakers,
+            &instance.address,
+            &state.config.circuit_breaker,
+        );
+        match permit {
+            Some(permit) => return Some((instance, permit)),
+            None => candidates.retain(|candidate| *candidate != instance),
+        }
+    }
+}
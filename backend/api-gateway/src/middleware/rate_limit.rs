@@ -2,78 +2,41 @@ use std::{
     pin::Pin,
     sync::Arc,
     task::{Context, Poll},
-    time::{Duration, Instant},
+    time::Duration,
 };
 
 use axum::{
     extract::Request,
-    http::HeaderValue,
+    http::{HeaderMap, HeaderValue},
     response::{IntoResponse, Response},
 };
-use dashmap::DashMap;
 use hyper::StatusCode;
 use tower::{Layer, Service};
 
-use crate::config::RateLimitConfig;
+use crate::{
+    config::Route,
+    error::ErrorResponse,
+    middleware::parser::ParsedURI,
+    rate_limit::{Decision, client_key},
+    state::AppState,
+};
 
+/// The policies and the limiter are read from the state of every request,
+/// the limiter is carried over config reloads.
 #[derive(Clone)]
-pub struct RateLimitLayer {
-    pub(crate) storage: Arc<DashMap<String, ClientRecord>>,
-    pub(crate) max_requests: u32,
-    pub(crate) window: Duration,
-}
-
-impl RateLimitLayer {
-    pub fn from_config(config: RateLimitConfig) -> RateLimitLayer {
-        RateLimitLayer {
-            storage: Arc::new(DashMap::new()),
-            max_requests: config.max_requests,
-            window: Duration::from_secs(config.window_seconds),
-        }
-    }
-}
-
-#[derive(Debug)]
-pub struct ClientRecord {
-    count: u32,
-    window_start: Instant,
-}
+pub struct RateLimitLayer;
 
 impl<S> Layer<S> for RateLimitLayer {
     type Service = RateLimitMiddleware<S>;
 
     fn layer(&self, inner: S) -> Self::Service {
-        RateLimitMiddleware::new(
-            inner,
-            Arc::clone(&self.storage),
-            self.max_requests,
-            self.window,
-        )
+        RateLimitMiddleware { inner }
     }
 }
 
 #[derive(Clone)]
 pub struct RateLimitMiddleware<S> {
     inner: S,
-    storage: Arc<DashMap<String, ClientRecord>>,
-    max_requests: u32,
-    window: Duration,
-}
-
-impl<S> RateLimitMiddleware<S> {
-    pub fn new(
-        inner: S,
-        storage: Arc<DashMap<String, ClientRecord>>,
-        max_requests: u32,
-        window: Duration,
-    ) -> Self {
-        Self {
-            inner,
-            storage,
-            max_requests,
-            window,
-        }
-    }
 }
 
 impl<S> Service<Request> for RateLimitMiddleware<S>
@@ -90,44 +53,64 @@ where
     }
 
     fn call(&mut self, req: Request) -> Self::Future {
-        let client_ip = req
-            .headers()
-            .get("x-forwarded-for")
-            .and_then(|v| v.to_str().ok())
-            .unwrap_or("unknown")
-            .to_string();
-
-        let storage = Arc::clone(&self.storage);
-        let max_requests = self.max_requests;
-        let window = self.window;
-
         let mut inner = self.inner.clone();
 
         Box::pin(async move {
-            let now = Instant::now();
-            let mut record = storage.entry(client_ip).or_insert(ClientRecord {
-                count: 0,
-                window_start: now,
-            });
-
-            if now.duration_since(record.window_start) > window {
-                record.count = 1;
-                record.window_start = now;
-            } else {
-                if record.count >= max_requests {
-                    let retry_after = (window - now.duration_since(record.window_start)).as_secs();
-                    let mut response = StatusCode::TOO_MANY_REQUESTS.into_response();
-                    response.headers_mut().insert(
-                        "Retry-After",
-                        HeaderValue::from_str(&retry_after.to_string()).unwrap(),
-                    );
-                    return Ok(response);
+            let (Some(state), Some(ParsedURI { prefix, .. }), Some(route)) = (
+                req.extensions().get::<Arc<AppState>>().cloned(),
+                req.extensions().get::<ParsedURI>(),
+                req.extensions().get::<Route>(),
+            ) else {
+                return Ok(StatusCode::INTERNAL_SERVER_ERROR
+                    .with_debug("Could not get request extensions at rate limit middleware")
+                    .into_response());
+            };
+
+            let config = &state.config.rate_limit;
+            // Routes with their own policy get their own quota
+            let (scope, policy) = match &route.rate_limit {
+                Some(policy) => (format!("{prefix}{}", route.path), policy),
+                None => ("*".to_owned(), &config.policy),
+            };
+            let key = format!("{scope}|{}", client_key(&req, &policy.key, config));
+
+            let decision = match state.rate_limiter.check(&key, policy).await {
+                Ok(Some(decision)) => decision,
+                // The store is unreachable and the limiter fails open
+                Ok(None) => return inner.call(req).await,
+                Err(e) => {
+                    tracing::error!("Rate limit store unavailable, rejecting the request: {e}");
+                    return Ok(StatusCode::SERVICE_UNAVAILABLE
+                        .with_debug("Rate limit store unavailable")
+                        .into_response());
                 }
-                record.count += 1
+            };
+
+            if !decision.allowed {
+                let mut response = StatusCode::TOO_MANY_REQUESTS
+                    .with_debug("Rate limit exceeded")
+                    .into_response();
+                insert_headers(response.headers_mut(), &decision);
+                response
+                    .headers_mut()
+                    .insert("Retry-After", seconds(decision.retry_after));
+                return Ok(response);
             }
 
-            let response = inner.call(req).await?;
+            let mut response = inner.call(req).await?;
+            insert_headers(response.headers_mut(), &decision);
             Ok(response)
         })
     }
 }
+
+fn insert_headers(headers: &mut HeaderMap, decision: &Decision) {
+    headers.insert("RateLimit-Limit", HeaderValue::from(decision.limit));
+    headers.insert("RateLimit-Remaining", HeaderValue::from(decision.remaining));
+    headers.insert("RateLimit-Reset", seconds(decision.reset));
+}
+
+/// Rounded up, so clients never retry too early.
+fn seconds(duration: Duration) -> HeaderValue {
+    HeaderValue::from(duration.as_secs() + u64::from(duration.subsec_nanos() > 0))
+}
@@ -10,11 +10,13 @@ use axum::{
 use hyper::StatusCode;
 use tower::{Layer, Service};
 
-use crate::{error::ErrorResponse, middleware::parser::ParsedURI, state::AppState};
+use crate::{
+    error::ErrorResponse, middleware::parser::ParsedURI, routing::RouteMatch, state::SharedState,
+};
 
 #[derive(Clone)]
 pub(crate) struct RouterLayer {
-    pub(crate) state: AppState,
+    pub(crate) state: SharedState,
 }
 
 impl<S> Layer<S> for RouterLayer {
@@ -31,7 +33,7 @@ impl<S> Layer<S> for RouterLayer {
 #[derive(Clone)]
 pub struct RouterMiddleware<S> {
     inner: S,
-    state: AppState,
+    state: SharedState,
 }
 
 impl<S> Service<Request> for RouterMiddleware<S>
@@ -49,7 +51,8 @@ where
 
     fn call(&mut self, mut req: Request) -> Self::Future {
         let mut inner = self.inner.clone();
-        let AppState { config } = self.state.clone();
+        // Loaded once, so the whole request is handled with the same config
+        let state = self.state.load();
 
         Box::pin(async move {
             let ParsedURI { prefix, subpath } = match req.extensions().get::<ParsedURI>() {
@@ -61,7 +64,7 @@ where
                 }
             };
 
-            let service = match config.services.get(prefix) {
+            let service = match state.config.services.get(prefix) {
                 Some(svc) => svc,
                 None => {
                     return Ok(StatusCode::NOT_FOUND
@@ -70,22 +73,23 @@ where
                 }
             };
 
-            let route = match service.routes.iter().find(|r| r.path == *subpath) {
-                Some(r) => r,
+            let route_table = match state.route_tables.get(prefix) {
+                Some(table) => table,
                 None => {
-                    return Ok(StatusCode::NOT_FOUND
-                        .with_debug("Could not get route. Route not found")
+                    return Ok(StatusCode::INTERNAL_SERVER_ERROR
+                        .with_debug("Could not get route table for service")
                         .into_response());
                 }
             };
 
-            match route
-                .allow_methods
-                .iter()
-                .find(|m| m.eq_ignore_ascii_case(req.method().as_str()))
-            {
-                Some(_) => {}
-                None => {
+            let (route, params) = match route_table.at(subpath, req.method()) {
+                RouteMatch::Found(route, params) => (route.clone(), params),
+                RouteMatch::NotFound => {
+                    return Ok(StatusCode::NOT_FOUND
+                        .with_debug("Could not get route. Route not found")
+                        .into_response());
+                }
+                RouteMatch::MethodNotAllowed => {
                     return Ok(StatusCode::METHOD_NOT_ALLOWED
                         .with_debug("Method not allowed")
                         .into_response());
@@ -93,6 +97,9 @@ where
             };
 
             req.extensions_mut().insert(service.clone());
+            req.extensions_mut().insert(route);
+            req.extensions_mut().insert(params);
+            req.extensions_mut().insert(state.clone());
 
             let response = inner.call(req).await?;
             Ok(response)
@@ -0,0 +1,188 @@
+use std::{
+    collections::VecDeque,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Weak},
+    time::{Duration, Instant},
+};
+
+use dashmap::DashMap;
+
+use crate::{
+    config::{RateLimitAlgorithm, RateLimitPolicy},
+    rate_limit::{Decision, RateLimitStore},
+};
+
+/// How often records that no longer limit anyone are dropped.
+const EVICTION_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Records kept in the gateway process. Exact, but every replica has its own.
+#[derive(Debug)]
+pub(crate) struct InMemoryStore {
+    records: Arc<DashMap<String, ClientRecord>>,
+}
+
+impl InMemoryStore {
+    /// Creates a store whose stale records are evicted in the background
+    /// for as long as it's alive.
+    pub(crate) fn new() -> Self {
+        let records = Arc::new(DashMap::new());
+        tokio::spawn(evict(Arc::downgrade(&records)));
+        Self { records }
+    }
+}
+
+impl RateLimitStore for InMemoryStore {
+    fn check<'a>(
+        &'a self,
+        key: &'a str,
+        policy: &'a RateLimitPolicy,
+    ) -> Pin<Box<dyn Future<Output = anyhow::Result<Decision>> + Send + 'a>> {
+        let now = Instant::now();
+        let mut record = self
+            .records
+            .entry(key.to_owned())
+            .or_insert_with(|| ClientRecord::new(policy, now));
+        let decision = record.check(policy, now);
+        Box::pin(std::future::ready(Ok(decision)))
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct ClientRecord {
+    usage: Usage,
+    /// From then on the record is equivalent to a fresh one
+    expires_at: Instant,
+}
+
+#[derive(Debug)]
+enum Usage {
+    FixedWindow { count: u32, started_at: Instant },
+    SlidingLog(VecDeque<Instant>),
+    TokenBucket { tokens: f64, refilled_at: Instant },
+}
+
+impl ClientRecord {
+    fn new(policy: &RateLimitPolicy, now: Instant) -> Self {
+        let usage = match policy.algorithm {
+            RateLimitAlgorithm::FixedWindow => Usage::FixedWindow {
+                count: 0,
+                started_at: now,
+            },
+            RateLimitAlgorithm::SlidingLog => Usage::SlidingLog(VecDeque::new()),
+            RateLimitAlgorithm::TokenBucket => Usage::TokenBucket {
+                tokens: f64::from(policy.max_requests),
+                refilled_at: now,
+            },
+        };
+
+        Self {
+            usage,
+            expires_at: now,
+        }
+    }
+
+    fn algorithm(&self) -> RateLimitAlgorithm {
+        match self.usage {
+            Usage::FixedWindow { .. } => RateLimitAlgorithm::FixedWindow,
+            Usage::SlidingLog(_) => RateLimitAlgorithm::SlidingLog,
+            Usage::TokenBucket { .. } => RateLimitAlgorithm::TokenBucket,
+        }
+    }
+
+    fn check(&mut self, policy: &RateLimitPolicy, now: Instant) -> Decision {
+        // The policy changed with a config reload
+        if self.algorithm() != policy.algorithm {
+            *self = ClientRecord::new(policy, now);
+        }
+
+        let limit = policy.max_requests;
+        let window = Duration::from_secs(policy.window_seconds.max(1));
+
+        match &mut self.usage {
+            Usage::FixedWindow { count, started_at } => {
+                if now.duration_since(*started_at) >= window {
+                    *count = 0;
+                    *started_at = now;
+                }
+
+                let allowed = *count < limit;
+                if allowed {
+                    *count += 1;
+                }
+                let reset = (*started_at + window).duration_since(now);
+                self.expires_at = *started_at + window;
+
+                Decision {
+                    allowed,
+                    limit,
+                    remaining: limit.saturating_sub(*count),
+                    reset,
+                    retry_after: reset,
+                }
+            }
+            Usage::SlidingLog(log) => {
+                while log
+                    .front()
+                    .is_some_and(|t| now.duration_since(*t) >= window)
+                {
+                    log.pop_front();
+                }
+
+                let allowed = log.len() < limit as usize;
+                if allowed {
+                    log.push_back(now);
+                }
+                let oldest = log.front().copied().unwrap_or(now);
+                let newest = log.back().copied().unwrap_or(now);
+                self.expires_at = newest + window;
+
+                Decision {
+                    allowed,
+                    limit,
+                    remaining: limit.saturating_sub(log.len() as u32),
+                    reset: (newest + window).duration_since(now),
+                    retry_after: (oldest + window).duration_since(now),
+                }
+            }
+            Usage::TokenBucket {
+                tokens,
+                refilled_at,
+            } => {
+                let capacity = f64::from(limit);
+                let rate = capacity / window.as_secs_f64();
+                let elapsed = now.duration_since(*refilled_at).as_secs_f64();
+                *tokens = (*tokens + elapsed * rate).min(capacity);
+                *refilled_at = now;
+
+                let allowed = *tokens >= 1.0;
+                if allowed {
+                    *tokens -= 1.0;
+                }
+                let reset = Duration::from_secs_f64((capacity - *tokens) / rate);
+                self.expires_at = now + reset;
+
+                Decision {
+                    allowed,
+                    limit,
+                    remaining: *tokens as u32,
+                    reset,
+                    retry_after: Duration::from_secs_f64((1.0 - *tokens).max(0.0) / rate),
+                }
+            }
+        }
+    }
+}
+
+async fn evict(records: Weak<DashMap<String, ClientRecord>>) {
+    let mut interval = tokio::time::interval(EVICTION_INTERVAL);
+    loop {
+        interval.tick().await;
+        let Some(records) = records.upgrade() else {
+            return;
+        };
+
+        let now = Instant::now();
+        records.retain(|_, record| record.expires_at > now);
+    }
+}
@@ -0,0 +1,144 @@
+use std::{
+    fmt::Debug,
+    future::Future,
+    net::{IpAddr, SocketAddr},
+    pin::Pin,
+    sync::Arc,
+    time::Duration,
+};
+
+use axum::extract::{ConnectInfo, Request};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    config::{RateLimitConfig, RateLimitKey, RateLimitPolicy, RateLimitStoreConfig},
+    jwt,
+    rate_limit::{memory::InMemoryStore, redis::RedisStore},
+};
+
+pub mod memory;
+pub mod redis;
+
+/// Where the usage of every client is kept. Implementations must apply a
+/// request atomically, as several gateway replicas can share the store.
+pub(crate) trait RateLimitStore: Debug + Send + Sync {
+    /// Counts a request of `key` against `policy`.
+    fn check<'a>(
+        &'a self,
+        key: &'a str,
+        policy: &'a RateLimitPolicy,
+    ) -> Pin<Box<dyn Future<Output = anyhow::Result<Decision>> + Send + 'a>>;
+}
+
+/// Checks requests against the configured store, keyed by scope and client key.
+#[derive(Debug, Clone)]
+pub(crate) struct RateLimiter {
+    store: Arc<dyn RateLimitStore>,
+    fail_open: bool,
+}
+
+/// Outcome of a request against a policy.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Decision {
+    pub(crate) allowed: bool,
+    pub(crate) limit: u32,
+    pub(crate) remaining: u32,
+    /// Until the whole quota is available again
+    pub(crate) reset: Duration,
+    /// Until the next request would be allowed
+    pub(crate) retry_after: Duration,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(config: &RateLimitStoreConfig) -> anyhow::Result<Self> {
+        let limiter = match config {
+            RateLimitStoreConfig::Memory => RateLimiter {
+                store: Arc::new(InMemoryStore::new()),
+                fail_open: true,
+            },
+            RateLimitStoreConfig::Redis {
+                url,
+                fail_open,
+                timeout_ms,
+            } => RateLimiter {
+                store: Arc::new(RedisStore::new(url, Duration::from_millis(*timeout_ms))?),
+                fail_open: *fail_open,
+            },
+        };
+        Ok(limiter)
+    }
+
+    /// Returns `None` when the store can't be reached and the limiter fails open.
+    pub(crate) async fn check(
+        &self,
+        key: &str,
+        policy: &RateLimitPolicy,
+    ) -> Result<Option<Decision>, anyhow::Error> {
+        match self.store.check(key, policy).await {
+            Ok(decision) => Ok(Some(decision)),
+            Err(e) if self.fail_open => {
+                tracing::warn!("Rate limit store unavailable, letting the request through: {e}");
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Identifies the client of `req` according to `key`.
+pub(crate) fn client_key(req: &Request, key: &RateLimitKey, config: &RateLimitConfig) -> String {
+    let peer = req
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(addr)| addr.ip());
+
+    let forwarded_for = || {
+        peer.map(|peer| forwarded_for(req, peer, &config.trusted_proxies))
+            .map(|ip| format!("ip:{ip}"))
+    };
+
+    let key = match key {
+        RateLimitKey::PeerIp => None,
+        RateLimitKey::ForwardedFor => forwarded_for(),
+        RateLimitKey::UserId => jwt::user_id(req).map(|id| format!("user:{id}")),
+        RateLimitKey::ApiKey(header) => req
+            .headers()
+            .get(header)
+            .map(|v| hex::encode(Sha256::digest(v.as_bytes())))
+            .filter(|hash| {
+                config
+                    .api_keys
+                    .iter()
+                    .any(|key| key.eq_ignore_ascii_case(hash))
+            })
+            .map(|hash| format!("key:{hash}"))
+            .or_else(forwarded_for),
+    };
+
+    key.or_else(|| peer.map(|ip| format!("ip:{ip}")))
+        .unwrap_or_else(|| "unknown".to_owned())
+}
+
+/// Walks `X-Forwarded-For` from the closest hop, skipping trusted proxies.
+/// Only a trusted peer can vouch for the header.
+fn forwarded_for(req: &Request, peer: IpAddr, trusted_proxies: &[IpAddr]) -> IpAddr {
+    if !trusted_proxies.contains(&peer) {
+        return peer;
+    }
+
+    let hops: Vec<IpAddr> = req
+        .headers()
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .flat_map(|v| v.split(','))
+        .filter_map(|hop| hop.trim().parse().ok())
+        .collect();
+
+    hops.iter()
+        .rev()
+        .find(|hop| !trusted_proxies.contains(hop))
+        .or(hops.first())
+        .copied()
+        .unwrap_or(peer)
+}
@@ -0,0 +1,169 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use redis::{AsyncConnectionConfig, Client, aio::MultiplexedConnection};
+use tokio::sync::Mutex;
+
+use crate::{
+    config::{RateLimitAlgorithm, RateLimitPolicy},
+    rate_limit::{Decision, RateLimitStore},
+};
+
+const KEY_PREFIX: &str = "ratelimit:";
+
+/// Counters kept in a Redis compatible server, so every gateway replica
+/// enforces the same quota. Every request is a single `MULTI` transaction
+/// incrementing a counter that expires with its window.
+#[derive(Debug)]
+pub(crate) struct RedisStore {
+    client: Client,
+    timeout: Duration,
+    /// Opened on first use and reopened after any error
+    connection: Mutex<Option<MultiplexedConnection>>,
+}
+
+impl RedisStore {
+    pub(crate) fn new(url: &str, timeout: Duration) -> redis::RedisResult<Self> {
+        Ok(Self {
+            client: Client::open(url)?,
+            timeout,
+            connection: Mutex::new(None),
+        })
+    }
+
+    async fn connection(&self) -> redis::RedisResult<MultiplexedConnection> {
+        let mut connection = self.connection.lock().await;
+        if let Some(connection) = connection.as_ref() {
+            return Ok(connection.clone());
+        }
+
+        let config = AsyncConnectionConfig::new()
+            .set_connection_timeout(self.timeout)
+            .set_response_timeout(self.timeout);
+        let new = self
+            .client
+            .get_multiplexed_async_connection_with_config(&config)
+            .await?;
+        *connection = Some(new.clone());
+        Ok(new)
+    }
+
+    async fn apply(&self, key: &str, policy: &RateLimitPolicy) -> redis::RedisResult<Decision> {
+        let mut con = self.connection().await?;
+        let key = format!("{KEY_PREFIX}{key}");
+        let window_ms = policy.window_seconds.max(1) * 1000;
+
+        match policy.algorithm {
+            RateLimitAlgorithm::FixedWindow => {
+                fixed_window(&mut con, &key, policy, window_ms).await
+            }
+            RateLimitAlgorithm::SlidingLog => {
+                sliding_window(&mut con, &key, policy, window_ms).await
+            }
+            // Rejected when loading the config
+            RateLimitAlgorithm::TokenBucket => Err(redis::RedisError::from((
+                redis::ErrorKind::ClientError,
+                "TokenBucket isn't supported by the Redis store",
+            ))),
+        }
+    }
+}
+
+impl RateLimitStore for RedisStore {
+    fn check<'a>(
+        &'a self,
+        key: &'a str,
+        policy: &'a RateLimitPolicy,
+    ) -> Pin<Box<dyn Future<Output = anyhow::Result<Decision>> + Send + 'a>> {
+        Box::pin(async move {
+            match self.apply(key, policy).await {
+                Ok(decision) => Ok(decision),
+                Err(e) => {
+                    *self.connection.lock().await = None;
+                    Err(e.into())
+                }
+            }
+        })
+    }
+}
+
+/// The window starts with the first request, like the in-memory counter.
+async fn fixed_window(
+    con: &mut MultiplexedConnection,
+    key: &str,
+    policy: &RateLimitPolicy,
+    window_ms: u64,
+) -> redis::RedisResult<Decision> {
+    let (count, ttl_ms): (u32, i64) = redis::pipe()
+        .atomic()
+        .cmd("SET")
+        .arg(key)
+        .arg(0)
+        .arg("PX")
+        .arg(window_ms)
+        .arg("NX")
+        .ignore()
+        .incr(key, 1)
+        .pttl(key)
+        .query_async(con)
+        .await?;
+
+    let limit = policy.max_requests;
+    let reset = Duration::from_millis(u64::try_from(ttl_ms).unwrap_or(window_ms));
+    Ok(Decision {
+        allowed: count <= limit,
+        limit,
+        remaining: limit.saturating_sub(count),
+        reset,
+        retry_after: reset,
+    })
+}
+
+/// Approximates a sliding log by weighting the previous fixed window by how
+/// much of it still overlaps the sliding one.
+async fn sliding_window(
+    con: &mut MultiplexedConnection,
+    key: &str,
+    policy: &RateLimitPolicy,
+    window_ms: u64,
+) -> redis::RedisResult<Decision> {
+    let now_ms = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64;
+    let index = now_ms / window_ms;
+    let elapsed_ms = now_ms % window_ms;
+    let current_key = format!("{key}:{index}");
+    let previous_key = format!("{key}:{}", index.saturating_sub(1));
+
+    let (current, previous): (u32, Option<u32>) = redis::pipe()
+        .atomic()
+        .incr(&current_key, 1)
+        .pexpire(&current_key, (window_ms * 2) as i64)
+        .ignore()
+        .get(&previous_key)
+        .query_async(con)
+        .await?;
+
+    let limit = policy.max_requests;
+    let overlap = (window_ms - elapsed_ms) as f64 / window_ms as f64;
+    let estimate = f64::from(previous.unwrap_or(0)) * overlap + f64::from(current);
+    let allowed = estimate <= f64::from(limit);
+    if !allowed {
+        // Rejected requests don't take up quota
+        redis::cmd("DECR").arg(&current_key).exec_async(con).await?;
+    }
+
+    let until_next_window = Duration::from_millis(window_ms - elapsed_ms);
+    Ok(Decision {
+        allowed,
+        limit,
+        remaining: (f64::from(limit) - estimate.ceil()).max(0.0) as u32,
+        reset: until_next_window + Duration::from_millis(window_ms),
+        // At the latest, the current window becomes the previous one
+        retry_after: until_next_window,
+    })
+}
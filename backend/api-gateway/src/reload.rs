@@ -0,0 +1,70 @@
+use std::{
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::{
+    signal::unix::{SignalKind, signal},
+    sync::mpsc,
+};
+
+use crate::{config, state::SharedState};
+
+/// Editors usually write a file in several steps, wait for them to settle.
+const DEBOUNCE: Duration = Duration::from_millis(200);
+
+/// Reloads the config whenever the file at `path` changes or the process
+/// receives SIGHUP. An invalid config is logged and the current one is kept.
+pub(crate) fn watch(state: SharedState, path: String) -> anyhow::Result<()> {
+    let (tx, mut rx) = mpsc::unbounded_channel();
+
+    // The parent directory is watched, as editors often replace the file
+    // instead of writing to it, which would end a watch on the file itself
+    let file = Path::new(&path).canonicalize()?;
+    let dir = file.parent().map(Path::to_path_buf).unwrap_or_default();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        if let Ok(event) = event
+            && !event.kind.is_access()
+            && event.paths.iter().any(|p| same_file(p, &file))
+        {
+            let _ = tx.send(());
+        }
+    })?;
+    watcher.watch(&dir, RecursiveMode::NonRecursive)?;
+
+    let mut hangup = signal(SignalKind::hangup())?;
+
+    tokio::spawn(async move {
+        // Dropping the watcher would stop the notifications
+        let _watcher: RecommendedWatcher = watcher;
+
+        loop {
+            tokio::select! {
+                Some(()) = rx.recv() => {
+                    tokio::time::sleep(DEBOUNCE).await;
+                    while rx.try_recv().is_ok() {}
+                    tracing::info!(path, "Config file changed, reloading");
+                }
+                Some(()) = hangup.recv() => {
+                    tracing::info!(path, "SIGHUP received, reloading config");
+                }
+                else => break,
+            }
+
+            match config::load_from_path(&path) {
+                Ok(config) => {
+                    state.reload(config);
+                    tracing::info!(path, "Config reloaded");
+                }
+                Err(e) => tracing::error!(path, "Invalid config, keeping the current one: {e}"),
+            }
+        }
+    });
+
+    Ok(())
+}
+
+fn same_file(path: &Path, file: &PathBuf) -> bool {
+    path == file || path.canonicalize().is_ok_and(|p| p == *file)
+}
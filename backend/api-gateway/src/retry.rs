@@ -0,0 +1,70 @@
+use std::{sync::Mutex, time::Duration};
+
+use axum::http::{Method, StatusCode};
+use rand::Rng;
+
+use crate::{config::RetryConfig, handler::UpstreamError};
+
+impl RetryConfig {
+    pub(crate) fn allows_method(&self, method: &Method) -> bool {
+        self.methods
+            .iter()
+            .any(|m| m.eq_ignore_ascii_case(method.as_str()))
+    }
+
+    pub(crate) fn retries_error(&self, err: &UpstreamError) -> bool {
+        match err {
+            UpstreamError::Timeout => self.retry_on_timeout,
+            UpstreamError::Request(e) => self.retry_on_connect_error && e.is_connect(),
+            UpstreamError::BodyTooLarge => false,
+        }
+    }
+
+    pub(crate) fn retries_status(&self, status: StatusCode) -> bool {
+        self.retry_on_status.contains(&status.as_u16())
+    }
+
+    /// Exponential backoff with full jitter for the `retry`-th retry (starting at 1).
+    pub(crate) fn backoff(&self, retry: u32) -> Duration {
+        let exp = self
+            .backoff_base_ms
+            .saturating_mul(1 << retry.saturating_sub(1).min(16));
+        let max = exp.min(self.backoff_max_ms);
+        Duration::from_millis(rand::rng().random_range(0..=max))
+    }
+}
+
+/// Token bucket shared by all the requests of a service. Every request deposits
+/// `budget_ratio` tokens and every retry withdraws one, so retries can't
+/// amplify an outage beyond that ratio once the initial reserve is spent.
+#[derive(Debug)]
+pub(crate) struct RetryBudget {
+    balance: Mutex<f64>,
+    ratio: f64,
+    max_balance: f64,
+}
+
+impl RetryBudget {
+    pub(crate) fn new(config: &RetryConfig) -> Self {
+        let max_balance = f64::from(config.budget_min_retries.max(1));
+        Self {
+            balance: Mutex::new(max_balance),
+            ratio: config.budget_ratio.max(0.0),
+            max_balance,
+        }
+    }
+
+    pub(crate) fn deposit(&self) {
+        let mut balance = self.balance.lock().unwrap();
+        *balance = (*balance + self.ratio).min(self.max_balance);
+    }
+
+    pub(crate) fn try_withdraw(&self) -> bool {
+        let mut balance = self.balance.lock().unwrap();
+        if *balance < 1.0 {
+            return false;
+        }
+        *balance -= 1.0;
+        true
+    }
+}
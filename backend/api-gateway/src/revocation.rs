@@ -0,0 +1,158 @@
+use std::{
+    sync::{Arc, Weak},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use dashmap::DashMap;
+use reqwest::Client;
+use serde::Deserialize;
+use tokio::sync::watch;
+
+const FETCH_TIMEOUT: Duration = Duration::from_secs(5);
+/// Sent as a bearer token, auth-service only lists revocations to the holders of its `REVOCATIONS_TOKEN`
+const TOKEN_ENV: &str = "REVOCATIONS_TOKEN";
+
+/// Tokens logged out before they expire, by `jti`, or by `sid` when their whole
+/// session was revoked. Kept in sync with the revocations auth-service records,
+/// on logout or whenever a session ends.
+#[derive(Debug)]
+pub(crate) struct RevocationList {
+    /// `jti` or `sid` to the expiration of its tokens, as unix time
+    revoked: Arc<DashMap<String, u64>>,
+    synced: watch::Receiver<SyncStatus>,
+    max_stale: Duration,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct SyncStatus {
+    attempted: bool,
+    /// Last time every revocation was fetched
+    caught_up_at: Option<Instant>,
+}
+
+#[derive(Deserialize)]
+struct Page {
+    revoked: Vec<Revoked>,
+    cursor: i64,
+}
+
+#[derive(Deserialize)]
+struct Revoked {
+    jti: String,
+    expires_at: u64,
+}
+
+impl RevocationList {
+    /// Creates a list polling `url` in the background for as long as it's alive.
+    pub(crate) fn new(url: String, interval: Duration, max_stale: Duration) -> Self {
+        let revoked = Arc::new(DashMap::new());
+        let (synced_tx, synced) = watch::channel(SyncStatus::default());
+        let token = std::env::var(TOKEN_ENV).ok();
+        if token.is_none() {
+            tracing::warn!("{TOKEN_ENV} is unset, auth-service won't list revocations");
+        }
+        tokio::spawn(sync(
+            Arc::downgrade(&revoked),
+            synced_tx,
+            url,
+            token,
+            interval,
+        ));
+        Self {
+            revoked,
+            synced,
+            max_stale,
+        }
+    }
+
+    /// Whether the list caught up within `max_stale`. Otherwise tokens revoked since
+    /// can't be told apart, so they all are rejected. On startup, the first fetch is
+    /// waited for.
+    pub(crate) async fn is_current(&self) -> bool {
+        let mut synced = self.synced.clone();
+        if !synced.borrow().attempted {
+            let _ = tokio::time::timeout(FETCH_TIMEOUT, synced.wait_for(|s| s.attempted)).await;
+        }
+        let caught_up_at = synced.borrow().caught_up_at;
+        caught_up_at.is_some_and(|at| at.elapsed() <= self.max_stale)
+    }
+
+    pub(crate) fn is_revoked(&self, jti: &str) -> bool {
+        self.revoked.contains_key(jti)
+    }
+}
+
+async fn sync(
+    revoked: Weak<DashMap<String, u64>>,
+    synced: watch::Sender<SyncStatus>,
+    url: String,
+    token: Option<String>,
+    interval: Duration,
+) {
+    let client = Client::new();
+    let mut cursor = 0;
+    let mut interval = tokio::time::interval(interval);
+    loop {
+        interval.tick().await;
+        let Some(revoked) = revoked.upgrade() else {
+            return;
+        };
+
+        // Until caught up, revocations come in pages. Those that may be followed by
+        // earlier ones still committing are listed again, the cursor stays put then.
+        let mut caught_up = false;
+        loop {
+            match fetch(&client, &url, token.as_deref(), cursor).await {
+                Ok(page) => {
+                    for token in page.revoked {
+                        revoked.insert(token.jti, token.expires_at);
+                    }
+                    if page.cursor == cursor {
+                        caught_up = true;
+                        break;
+                    }
+                    cursor = page.cursor;
+                }
+                Err(e) => {
+                    tracing::warn!(url, "Could not fetch revoked tokens: {e}");
+                    break;
+                }
+            }
+        }
+
+        // Expired tokens are rejected anyway
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        revoked.retain(|_, expires_at| *expires_at > now);
+
+        synced.send_modify(|synced| {
+            synced.attempted = true;
+            if caught_up {
+                synced.caught_up_at = Some(Instant::now());
+            }
+        });
+    }
+}
+
+async fn fetch(
+    client: &Client,
+    url: &str,
+    token: Option<&str>,
+    since: i64,
+) -> anyhow::Result<Page> {
+    let mut request = client.get(url).query(&[("since", since)]);
+    if let Some(token) = token {
+        request = request.bearer_auth(token);
+    }
+    let body = request
+        .timeout(FETCH_TIMEOUT)
+        .send()
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?;
+
+    Ok(serde_json::from_slice(&body)?)
+}
@@ -0,0 +1,89 @@
+use std::collections::HashMap;
+
+use axum::http::Method;
+use matchit::{InsertError, Router};
+
+use crate::config::{Route, Service};
+
+/// Compiled routes of a service. Paths can contain parameters (`/users/{id}`)
+/// and a trailing wildcard (`/files/{*rest}`). Several routes may share a path
+/// with different methods, the first one allowing the method wins.
+#[derive(Debug, Clone)]
+pub(crate) struct RouteTable(Router<Vec<Route>>);
+
+/// Parameters captured while matching the route, e.g. `id` for `/users/{id}`.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct RouteParams(pub(crate) Vec<(String, String)>);
+
+impl RouteParams {
+    pub(crate) fn get(&self, name: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+pub(crate) enum RouteMatch<'a> {
+    Found(&'a Route, RouteParams),
+    NotFound,
+    MethodNotAllowed,
+}
+
+impl RouteTable {
+    pub(crate) fn new(routes: &[Route]) -> Result<Self, InsertError> {
+        let mut by_path: Vec<(&str, Vec<Route>)> = Vec::new();
+        for route in routes {
+            match by_path.iter_mut().find(|(path, _)| *path == route.path) {
+                Some((_, entries)) => entries.push(route.clone()),
+                None => by_path.push((&route.path, vec![route.clone()])),
+            }
+        }
+
+        let mut router = Router::new();
+        for (path, entries) in by_path {
+            router.insert(path, entries)?;
+        }
+
+        Ok(RouteTable(router))
+    }
+
+    pub(crate) fn at(&self, path: &str, method: &Method) -> RouteMatch<'_> {
+        let Ok(matched) = self.0.at(path) else {
+            return RouteMatch::NotFound;
+        };
+
+        let route = matched.value.iter().find(|route| {
+            route
+                .allow_methods
+                .iter()
+                .any(|m| m.eq_ignore_ascii_case(method.as_str()))
+        });
+
+        match route {
+            Some(route) => {
+                let params = matched
+                    .params
+                    .iter()
+                    .map(|(key, value)| (key.to_string(), value.to_string()))
+                    .collect();
+                RouteMatch::Found(route, RouteParams(params))
+            }
+            None => RouteMatch::MethodNotAllowed,
+        }
+    }
+}
+
+/// Compiles the routes of every service, failing on invalid or conflicting paths.
+pub(crate) fn compile(
+    services: &HashMap<String, Service>,
+) -> Result<HashMap<String, RouteTable>, anyhow::Error> {
+    services
+        .iter()
+        .map(|(name, service)| {
+            let table = RouteTable::new(&service.routes)
+                .map_err(|e| anyhow::anyhow!("Invalid route in service `{name}`: {e}"))?;
+            Ok((name.clone(), table))
+        })
+        .collect()
+}
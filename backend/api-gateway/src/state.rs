@@ -1,12 +1,251 @@
-use crate::config::Config;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
+use arc_swap::ArcSwap;
+use dashmap::DashMap;
+use reqwest::Client;
+use tokio::task::JoinHandle;
+
+use crate::{
+    circuit_breaker::CircuitBreaker,
+    config::{Config, JwtConfig, Service},
+    health_check::{self, HealthRegistry},
+    jwt::TokenVerifier,
+    load_balancer::LoadBalancer,
+    rate_limit::RateLimiter,
+    retry::RetryBudget,
+    revocation::RevocationList,
+    routing::{self, RouteTable},
+};
+
+/// Everything built from one version of the config. State about instances
+/// (health, circuit breakers, balancing, connections) is shared with the next versions.
 #[derive(Debug, Clone)]
 pub struct AppState {
     pub(crate) config: Config,
+    pub(crate) route_tables: Arc<HashMap<String, RouteTable>>,
+    pub(crate) load_balancers: Arc<HashMap<String, Arc<LoadBalancer>>>,
+    pub(crate) retry_budgets: Arc<HashMap<String, Arc<RetryBudget>>>,
+    pub(crate) health: HealthRegistry,
+    pub(crate) circuit_breakers: Arc<DashMap<String, CircuitBreaker>>,
+    pub(crate) clients: UpstreamClients,
+    pub(crate) rate_limiter: RateLimiter,
+    pub(crate) tokens: TokenVerifier,
+    pub(crate) revocations: Option<Arc<RevocationList>>,
 }
 
 impl AppState {
     pub(crate) fn new(config: Config) -> AppState {
-        AppState { config }
+        let rate_limiter = RateLimiter::new(&config.rate_limit.store)
+            .expect("Rate limit store is validated when loading config");
+        let tokens = TokenVerifier::new(&config.jwt);
+        let revocations = revocation_list(&config.jwt);
+        let load_balancers = load_balancers(&config, None);
+        let retry_budgets = retry_budgets(&config, None);
+
+        Self::build(
+            config,
+            load_balancers,
+            retry_budgets,
+            HealthRegistry::default(),
+            Arc::new(DashMap::new()),
+            UpstreamClients::default(),
+            rate_limiter,
+            tokens,
+            revocations,
+        )
+    }
+
+    /// Builds the state for `config`, keeping the instance state of the ones still present.
+    pub(crate) fn reconfigure(&self, config: Config) -> AppState {
+        let instances: HashSet<_> = config
+            .services
+            .values()
+            .flat_map(|service| service.instances.iter().map(|i| &i.address))
+            .collect();
+        let probed: HashSet<_> = config
+            .services
+            .values()
+            .filter(|service| service.health_check.is_some())
+            .flat_map(|service| service.instances.iter().map(|i| &i.address))
+            .collect();
+
+        self.health.retain(|address, _| probed.contains(address));
+        self.circuit_breakers
+            .retain(|address, _| instances.contains(address));
+        for mut cb in self.circuit_breakers.iter_mut() {
+            cb.config = config.circuit_breaker.clone();
+        }
+
+        // Usage is kept unless it moves to another store
+        let rate_limiter = if config.rate_limit.store == self.config.rate_limit.store {
+            self.rate_limiter.clone()
+        } else {
+            RateLimiter::new(&config.rate_limit.store)
+                .expect("Rate limit store is validated when loading config")
+        };
+
+        // Cached keys and revocations are kept unless they come from elsewhere
+        let (tokens, revocations) = if config.jwt == self.config.jwt {
+            (self.tokens.clone(), self.revocations.clone())
+        } else {
+            (
+                TokenVerifier::new(&config.jwt),
+                revocation_list(&config.jwt),
+            )
+        };
+
+        let load_balancers = load_balancers(&config, Some(self));
+        let retry_budgets = retry_budgets(&config, Some(self));
+
+        Self::build(
+            config,
+            load_balancers,
+            retry_budgets,
+            self.health.clone(),
+            self.circuit_breakers.clone(),
+            self.clients.clone(),
+            rate_limiter,
+            tokens,
+            revocations,
+        )
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn build(
+        config: Config,
+        load_balancers: HashMap<String, Arc<LoadBalancer>>,
+        retry_budgets: HashMap<String, Arc<RetryBudget>>,
+        health: HealthRegistry,
+        circuit_breakers: Arc<DashMap<String, CircuitBreaker>>,
+        clients: UpstreamClients,
+        rate_limiter: RateLimiter,
+        tokens: TokenVerifier,
+        revocations: Option<Arc<RevocationList>>,
+    ) -> AppState {
+        let route_tables =
+            routing::compile(&config.services).expect("Routes are validated when loading config");
+
+        AppState {
+            route_tables: Arc::new(route_tables),
+            config,
+            load_balancers: Arc::new(load_balancers),
+            retry_budgets: Arc::new(retry_budgets),
+            health,
+            circuit_breakers,
+            clients,
+            rate_limiter,
+            tokens,
+            revocations,
+        }
+    }
+}
+
+/// Strategies keep their state (rotation, latencies, in-flight requests)
+/// while the instances and strategy of their service stay the same.
+fn load_balancers(
+    config: &Config,
+    previous: Option<&AppState>,
+) -> HashMap<String, Arc<LoadBalancer>> {
+    config
+        .services
+        .iter()
+        .map(|(name, service)| {
+            let unchanged = |old: &Service| {
+                old.instances == service.instances && old.strategy == service.strategy
+            };
+            let lb = previous
+                .filter(|previous| previous.config.services.get(name).is_some_and(unchanged))
+                .and_then(|previous| previous.load_balancers.get(name).cloned())
+                .unwrap_or_else(|| {
+                    Arc::new(LoadBalancer::new(&service.strategy, &service.instances))
+                });
+            (name.clone(), lb)
+        })
+        .collect()
+}
+
+/// Budgets aren't refilled by a reload that leaves their retry config as it was
+fn retry_budgets(
+    config: &Config,
+    previous: Option<&AppState>,
+) -> HashMap<String, Arc<RetryBudget>> {
+    config
+        .services
+        .iter()
+        .map(|(name, service)| {
+            let unchanged = |old: &Service| old.retry == service.retry;
+            let budget = previous
+                .filter(|previous| previous.config.services.get(name).is_some_and(unchanged))
+                .and_then(|previous| previous.retry_budgets.get(name).cloned())
+                .unwrap_or_else(|| {
+                    let retry = service.retry.clone().unwrap_or_default();
+                    Arc::new(RetryBudget::new(&retry))
+                });
+            (name.clone(), budget)
+        })
+        .collect()
+}
+
+fn revocation_list(config: &JwtConfig) -> Option<Arc<RevocationList>> {
+    let url = config.revocations_url.clone()?;
+    let interval = Duration::from_secs(config.revocations_poll_seconds.max(1));
+    let max_stale = Duration::from_secs(config.revocations_max_stale_seconds);
+    Some(Arc::new(RevocationList::new(url, interval, max_stale)))
+}
+
+/// The current `AppState`, swapped as a whole when the config is reloaded.
+/// Requests load it once, so in-flight ones finish on the config they started with.
+#[derive(Debug, Clone)]
+pub(crate) struct SharedState {
+    current: Arc<ArcSwap<AppState>>,
+    health_checkers: Arc<Mutex<Vec<JoinHandle<()>>>>,
+}
+
+impl SharedState {
+    pub(crate) fn new(config: Config) -> Self {
+        let state = AppState::new(config);
+        let health_checkers = health_check::spawn(&state);
+
+        Self {
+            current: Arc::new(ArcSwap::from_pointee(state)),
+            health_checkers: Arc::new(Mutex::new(health_checkers)),
+        }
+    }
+
+    pub(crate) fn load(&self) -> Arc<AppState> {
+        self.current.load_full()
+    }
+
+    /// Swaps in a state built from `config` and restarts the health checkers.
+    pub(crate) fn reload(&self, config: Config) {
+        let mut health_checkers = self.health_checkers.lock().unwrap();
+        for checker in health_checkers.drain(..) {
+            checker.abort();
+        }
+
+        let state = self.load().reconfigure(config);
+        *health_checkers = health_check::spawn(&state);
+        self.current.store(Arc::new(state));
+    }
+}
+
+/// Pooled upstream clients, shared by every request so connections are kept alive.
+/// `reqwest` only supports the connect timeout per client, so there is one client
+/// per distinct connect timeout, usually a single one.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct UpstreamClients(Arc<DashMap<Duration, Client>>);
+
+impl UpstreamClients {
+    pub(crate) fn get(&self, connect_timeout: Duration) -> reqwest::Result<Client> {
+        if let Some(client) = self.0.get(&connect_timeout) {
+            return Ok(client.clone());
+        }
+
+        let client = Client::builder().connect_timeout(connect_timeout).build()?;
+        Ok(self.0.entry(connect_timeout).or_insert(client).clone())
     }
 }
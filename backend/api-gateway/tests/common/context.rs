@@ -1,20 +1,29 @@
 use api_gateway::config::{self, Config};
 use tokio::sync::OnceCell;
 
-use crate::common::helpers::launch_instance;
+use crate::common::{
+    helpers::launch_instance,
+    jwks::{launch_jwks, publish},
+};
 
-struct TestContext {
-    config: Config,
+pub(crate) const TEST_KID: &str = "test-key";
+
+pub(crate) struct TestContext {
+    pub(crate) config: Config,
 }
 
 static TEST_CONTEXT: OnceCell<TestContext> = OnceCell::const_new();
 
-async fn tcx() -> &'static TestContext {
+pub(crate) async fn tcx() -> &'static TestContext {
     TEST_CONTEXT
         .get_or_init(|| async {
-            let config = config::load().expect("Cannot load config");
-            launch_instance(3001).await;
-            launch_instance(3002).await;
+            dotenv::from_filename(".env.example").ok();
+            let config =
+                config::load_from_path("config/config.test.toml").expect("Cannot load config");
+            launch_instance(3001);
+            launch_instance(3002);
+            launch_jwks(3013);
+            publish(TEST_KID);
             TestContext { config }
         })
         .await
@@ -1,9 +1,103 @@
-use axum::{Router, routing::get};
-
-pub(crate) async fn launch_instance(port: usize) {
-    let app = Router::new().route("/", get(|| async { "Hello World!" }));
-    let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{port}"))
-        .await
-        .unwrap();
-    axum::serve(listener, app).await.unwrap();
+use std::time::Duration;
+
+use axum::response::Response;
+use axum::{
+    Router,
+    body::Body,
+    extract::{
+        Path, WebSocketUpgrade,
+        ws::{Message, WebSocket},
+    },
+    http::{HeaderMap, Request, Uri},
+    routing,
+};
+use http_body_util::BodyExt;
+use hyper::StatusCode;
+use tower::ServiceExt;
+
+/// Spawns a dummy upstream answering with its own port on a dedicated runtime,
+/// so it outlives the runtime of the test that launched it.
+pub(crate) fn launch_instance(port: u16) {
+    let listener = std::net::TcpListener::bind(format!("0.0.0.0:{port}")).unwrap();
+    listener.set_nonblocking(true).unwrap();
+
+    std::thread::spawn(move || {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async move {
+            let app = Router::new()
+                .route("/", routing::get(move || async move { port.to_string() }))
+                .route(
+                    "/slow",
+                    routing::get(|| async {
+                        tokio::time::sleep(Duration::from_secs(1)).await;
+                        "Finally"
+                    }),
+                )
+                // Only the second instance is slow
+                .route(
+                    "/latency",
+                    routing::get(move || async move {
+                        if port == 3002 {
+                            tokio::time::sleep(Duration::from_millis(500)).await;
+                        }
+                        port.to_string()
+                    }),
+                )
+                .route(
+                    "/status/{code}",
+                    routing::get(|Path(code): Path<u16>| async move {
+                        StatusCode::from_u16(code).unwrap()
+                    }),
+                )
+                .route("/echo", routing::post(|body: String| async move { body }))
+                .route(
+                    "/users/{id}",
+                    routing::get(|Path(id): Path<String>| async { id })
+                        .delete(|Path(id): Path<String>| async { id }),
+                )
+                .route(
+                    "/files/{*rest}",
+                    routing::get(|Path(rest): Path<String>| async { rest }),
+                )
+                .route("/headers", routing::get(identity_headers))
+                .route("/socket", routing::get(socket_headers));
+            let listener = tokio::net::TcpListener::from_std(listener).unwrap();
+            axum::serve(listener, app).await.unwrap();
+        });
+    });
+}
+
+/// Answers with the identity and forwarding headers it received, one `name: value` per line
+async fn identity_headers(headers: HeaderMap) -> String {
+    let mut lines: Vec<String> = headers
+        .iter()
+        .filter(|(name, _)| {
+            name.as_str().starts_with("x-user-")
+                || name.as_str().starts_with("x-gateway-")
+                || name.as_str() == "x-forwarded-for"
+        })
+        .map(|(name, value)| format!("{name}: {}", value.to_str().unwrap()))
+        .collect();
+    lines.sort();
+    lines.join("\n")
+}
+
+/// Sends, as its only message, the path and query it was opened with and then
+/// the headers `identity_headers` answers with
+async fn socket_headers(ws: WebSocketUpgrade, uri: Uri, headers: HeaderMap) -> Response {
+    let message = format!("{uri}\n{}", identity_headers(headers).await);
+    ws.on_upgrade(|mut socket: WebSocket| async move {
+        let _ = socket.send(Message::Text(message.into())).await;
+    })
+}
+
+pub(crate) async fn send(app: &Router, request: Request<Body>) -> (StatusCode, String) {
+    let response = app.clone().oneshot(request).await.unwrap();
+    let status = response.status();
+    let body = response.into_body().collect().await.unwrap().to_bytes();
+    (status, String::from_utf8_lossy(&body).to_string())
+}
+
+pub(crate) async fn get(app: &Router, uri: &str) -> (StatusCode, String) {
+    send(app, Request::get(uri).body(Body::empty()).unwrap()).await
 }
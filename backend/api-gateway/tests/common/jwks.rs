@@ -0,0 +1,105 @@
+use std::sync::Mutex;
+
+use axum::{
+    Json, Router,
+    extract::Query,
+    http::{HeaderMap, StatusCode, header},
+    response::{IntoResponse, Response},
+    routing,
+};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use ed25519_dalek::{SigningKey, pkcs8::EncodePrivateKey};
+use jsonwebtoken::{
+    Algorithm, EncodingKey, Header, encode,
+    jwk::{
+        AlgorithmParameters, CommonParameters, EllipticCurve, Jwk, JwkSet, KeyAlgorithm,
+        OctetKeyPairParameters, OctetKeyPairType, PublicKeyUse,
+    },
+};
+use sha2::{Digest, Sha256};
+
+static PUBLISHED: Mutex<Vec<Jwk>> = Mutex::new(Vec::new());
+/// `jti` and expiration of the tokens passed to `revoke`
+static REVOKED: Mutex<Vec<(String, u64)>> = Mutex::new(Vec::new());
+
+/// Spawns an issuer publishing, at `/.well-known/jwks.json`, the keys passed to
+/// `publish`, and at `/revocations` the tokens passed to `revoke`.
+pub(crate) fn launch_jwks(port: u16) {
+    let listener = std::net::TcpListener::bind(format!("0.0.0.0:{port}")).unwrap();
+    listener.set_nonblocking(true).unwrap();
+
+    std::thread::spawn(move || {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async move {
+            let app = Router::new()
+                .route(
+                    "/.well-known/jwks.json",
+                    routing::get(|| async {
+                        Json(JwkSet {
+                            keys: PUBLISHED.lock().unwrap().clone(),
+                        })
+                    }),
+                )
+                .route("/revocations", routing::get(revocations));
+            let listener = tokio::net::TcpListener::from_std(listener).unwrap();
+            axum::serve(listener, app).await.unwrap();
+        });
+    });
+}
+
+/// Every `kid` gets its own Ed25519 key
+fn signing_key(kid: &str) -> SigningKey {
+    SigningKey::from_bytes(&Sha256::digest(kid).into())
+}
+
+pub(crate) fn publish(kid: &str) {
+    let jwk = Jwk {
+        common: CommonParameters {
+            public_key_use: Some(PublicKeyUse::Signature),
+            key_algorithm: Some(KeyAlgorithm::EdDSA),
+            key_id: Some(kid.to_string()),
+            ..Default::default()
+        },
+        algorithm: AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+            key_type: OctetKeyPairType::OctetKeyPair,
+            curve: EllipticCurve::Ed25519,
+            x: URL_SAFE_NO_PAD.encode(signing_key(kid).verifying_key().as_bytes()),
+        }),
+    };
+    PUBLISHED.lock().unwrap().push(jwk);
+}
+
+/// Signs `claims` with the key of `kid`, published or not
+pub(crate) fn token(kid: &str, claims: serde_json::Value) -> String {
+    let der = signing_key(kid).to_pkcs8_der().unwrap();
+    let mut header = Header::new(Algorithm::EdDSA);
+    header.kid = Some(kid.to_string());
+    encode(&header, &claims, &EncodingKey::from_ed_der(der.as_bytes())).unwrap()
+}
+
+/// The cursor is the index in `REVOKED`. Like auth-service, only listed with `REVOCATIONS_TOKEN`
+async fn revocations(
+    headers: HeaderMap,
+    Query(query): Query<std::collections::HashMap<String, usize>>,
+) -> Response {
+    let expected = format!("Bearer {}", std::env::var("REVOCATIONS_TOKEN").unwrap());
+    if headers
+        .get(header::AUTHORIZATION)
+        .is_none_or(|v| *v != expected)
+    {
+        return StatusCode::UNAUTHORIZED.into_response();
+    }
+
+    let since = query.get("since").copied().unwrap_or(0);
+    let revoked = REVOKED.lock().unwrap();
+    let page: Vec<_> = revoked
+        .iter()
+        .skip(since)
+        .map(|(jti, expires_at)| serde_json::json!({ "jti": jti, "expires_at": expires_at }))
+        .collect();
+    Json(serde_json::json!({ "revoked": page, "cursor": revoked.len() })).into_response()
+}
+
+pub(crate) fn revoke(jti: &str, expires_at: u64) {
+    REVOKED.lock().unwrap().push((jti.to_string(), expires_at));
+}
@@ -1,2 +1,4 @@
 pub mod context;
 pub mod helpers;
+pub mod jwks;
+pub mod redis;
@@ -0,0 +1,130 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpStream, tcp::OwnedReadHalf},
+};
+
+type Data = Arc<Mutex<HashMap<String, (i64, Option<Instant>)>>>;
+
+/// Spawns a Redis stand-in speaking just enough RESP for the rate limit store,
+/// and returns its port.
+pub(crate) fn launch_redis() -> u16 {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.set_nonblocking(true).unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    std::thread::spawn(move || {
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async move {
+            let listener = tokio::net::TcpListener::from_std(listener).unwrap();
+            let data = Data::default();
+            loop {
+                let (socket, _) = listener.accept().await.unwrap();
+                tokio::spawn(serve(socket, data.clone()));
+            }
+        });
+    });
+
+    port
+}
+
+async fn serve(socket: TcpStream, data: Data) {
+    let (read, mut write) = socket.into_split();
+    let mut read = BufReader::new(read);
+    let mut transaction: Option<Vec<Vec<String>>> = None;
+
+    while let Some(args) = read_command(&mut read).await {
+        let reply = match (args[0].to_uppercase().as_str(), &mut transaction) {
+            ("MULTI", _) => {
+                transaction = Some(Vec::new());
+                "+OK\r\n".to_owned()
+            }
+            ("EXEC", Some(queued)) => {
+                let mut data = data.lock().unwrap();
+                let replies: String = queued.iter().map(|cmd| execute(&mut data, cmd)).collect();
+                let reply = format!("*{}\r\n{replies}", queued.len());
+                transaction = None;
+                reply
+            }
+            (_, Some(queued)) => {
+                queued.push(args);
+                "+QUEUED\r\n".to_owned()
+            }
+            _ => execute(&mut data.lock().unwrap(), &args),
+        };
+
+        if write.write_all(reply.as_bytes()).await.is_err() {
+            return;
+        }
+    }
+}
+
+async fn read_command(read: &mut BufReader<OwnedReadHalf>) -> Option<Vec<String>> {
+    let mut line = String::new();
+    read.read_line(&mut line).await.ok()?;
+    let count: usize = line.trim().strip_prefix('*')?.parse().ok()?;
+
+    let mut args = Vec::with_capacity(count);
+    for _ in 0..count {
+        line.clear();
+        read.read_line(&mut line).await.ok()?;
+        let len: usize = line.trim().strip_prefix('$')?.parse().ok()?;
+        let mut arg = vec![0; len + 2];
+        read.read_exact(&mut arg).await.ok()?;
+        arg.truncate(len);
+        args.push(String::from_utf8(arg).ok()?);
+    }
+    Some(args)
+}
+
+fn execute(data: &mut HashMap<String, (i64, Option<Instant>)>, args: &[String]) -> String {
+    let now = Instant::now();
+    data.retain(|_, (_, expires_at)| expires_at.is_none_or(|at| at > now));
+    let key = args.get(1).cloned().unwrap_or_default();
+    let flag = |name: &str| args.iter().position(|a| a.eq_ignore_ascii_case(name));
+
+    match args[0].to_uppercase().as_str() {
+        "SET" => {
+            if flag("NX").is_some() && data.contains_key(&key) {
+                return "$-1\r\n".to_owned();
+            }
+            let expires_at =
+                flag("PX").map(|i| now + Duration::from_millis(args[i + 1].parse().unwrap()));
+            data.insert(key, (args[2].parse().unwrap(), expires_at));
+            "+OK\r\n".to_owned()
+        }
+        "INCR" | "INCRBY" | "DECR" => {
+            let delta = match args[0].to_uppercase().as_str() {
+                "INCR" => 1,
+                "INCRBY" => args[2].parse().unwrap(),
+                _ => -1,
+            };
+            let (value, _) = data.entry(key).or_insert((0, None));
+            *value += delta;
+            format!(":{value}\r\n")
+        }
+        "GET" => match data.get(&key) {
+            Some((value, _)) => format!("${}\r\n{value}\r\n", value.to_string().len()),
+            None => "$-1\r\n".to_owned(),
+        },
+        "PEXPIRE" => match data.get_mut(&key) {
+            Some((_, expires_at)) => {
+                *expires_at = Some(now + Duration::from_millis(args[2].parse().unwrap()));
+                ":1\r\n".to_owned()
+            }
+            None => ":0\r\n".to_owned(),
+        },
+        "PTTL" => match data.get(&key) {
+            Some((_, Some(expires_at))) => format!(":{}\r\n", (*expires_at - now).as_millis()),
+            Some((_, None)) => ":-1\r\n".to_owned(),
+            None => ":-2\r\n".to_owned(),
+        },
+        "CLIENT" => "+OK\r\n".to_owned(),
+        _ => "-ERR unknown command\r\n".to_owned(),
+    }
+}
@@ -1,6 +1,888 @@
 mod common;
 
+use api_gateway::{
+    app,
+    config::{self, Config},
+    reloading_app,
+};
+use hyper::StatusCode;
+
+use std::{net::SocketAddr, time::Duration};
+
+use axum::{body::Body, extract::ConnectInfo, http::Request};
+use futures_util::StreamExt;
+use hmac::{Hmac, Mac};
+use jsonwebtoken::{EncodingKey, Header, encode};
+use sha2::Sha256;
+use tokio_tungstenite::{connect_async, tungstenite::client::IntoClientRequest};
+use tower::ServiceExt;
+
+use crate::common::{
+    context::{TEST_KID, tcx},
+    helpers::{get, send},
+    jwks,
+    redis::launch_redis,
+};
+
 #[tokio::test]
 async fn test() {
     assert_eq!(2 + 2, 4);
 }
+
+#[tokio::test]
+async fn round_robin_alternates_between_instances() {
+    let app = app(tcx().await.config.clone());
+
+    let mut responses = Vec::new();
+    for _ in 0..4 {
+        let (status, body) = get(&app, "/api/round-robin/").await;
+        assert_eq!(status, StatusCode::OK);
+        responses.push(body);
+    }
+
+    assert_ne!(responses[0], responses[1]);
+    assert_eq!(responses[0], responses[2]);
+    assert_eq!(responses[1], responses[3]);
+}
+
+#[tokio::test]
+async fn weighted_round_robin_follows_weights() {
+    let app = app(tcx().await.config.clone());
+
+    let mut heavy = 0;
+    for _ in 0..8 {
+        let (status, body) = get(&app, "/api/weighted/").await;
+        assert_eq!(status, StatusCode::OK);
+        if body == "3001" {
+            heavy += 1;
+        }
+    }
+
+    assert_eq!(heavy, 6);
+}
+
+#[tokio::test]
+async fn least_connections_avoids_busy_instances() {
+    let app = app(tcx().await.config.clone());
+
+    // Ties go to the first instance, which stays busy for a second
+    let slow = tokio::spawn({
+        let app = app.clone();
+        async move { get(&app, "/api/least/slow").await }
+    });
+    tokio::time::sleep(Duration::from_millis(200)).await;
+
+    for _ in 0..3 {
+        let (status, body) = get(&app, "/api/least/").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "3002");
+    }
+
+    assert_eq!(slow.await.unwrap().0, StatusCode::OK);
+    assert_eq!(get(&app, "/api/least/").await.1, "3001");
+}
+
+#[tokio::test]
+async fn p2c_ewma_prefers_faster_instances() {
+    let app = app(tcx().await.config.clone());
+
+    // Unobserved instances score lowest, so each gets a first request
+    let mut slow = 0;
+    for _ in 0..10 {
+        let (status, body) = get(&app, "/api/p2c/latency").await;
+        assert_eq!(status, StatusCode::OK);
+        if body == "3002" {
+            slow += 1;
+        }
+    }
+
+    assert!((1..=3).contains(&slow), "{slow}");
+}
+
+#[tokio::test]
+async fn consistent_hash_is_sticky_per_key() {
+    let app = app(tcx().await.config.clone());
+
+    for session in ["alice", "bob", "carol"] {
+        let request = || {
+            Request::get("/api/sticky/")
+                .header("x-session", session)
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let (status, first) = send(&app, request()).await;
+        assert_eq!(status, StatusCode::OK);
+        for _ in 0..3 {
+            assert_eq!(send(&app, request()).await.1, first);
+        }
+    }
+}
+
+#[tokio::test]
+async fn consistent_hash_skips_unavailable_instances() {
+    let app = app(tcx().await.config.clone());
+
+    // Let the first round of probes complete
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    for session in ["alice", "bob", "carol", "dave"] {
+        let request = Request::get("/api/sticky-failover/")
+            .header("x-session", session)
+            .body(Body::empty())
+            .unwrap();
+        let (status, body) = send(&app, request).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "3001");
+    }
+}
+
+#[tokio::test]
+async fn health_check_removes_unhealthy_instances() {
+    let app = app(tcx().await.config.clone());
+
+    // Let the first round of probes complete
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    for _ in 0..4 {
+        let (status, body) = get(&app, "/api/health/").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "3001");
+    }
+}
+
+#[tokio::test]
+async fn circuit_breaker_opens_after_failure_threshold() {
+    let app = app(tcx().await.config.clone());
+
+    // Default `failure_threshold`
+    for _ in 0..3 {
+        let (status, _) = get(&app, "/api/unreachable/").await;
+        assert_eq!(status, StatusCode::BAD_GATEWAY);
+    }
+
+    let (status, _) = get(&app, "/api/unreachable/").await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+}
+
+fn breaker_config() -> Config {
+    config::parse(
+        r#"
+        [circuit_breaker]
+        failure_threshold = 2
+        failure_window_seconds = 60
+        open_window_seconds = 1
+        half_open_max_requests = 1
+
+        [services.breaker]
+        instances = ["localhost:3001"]
+
+        [[services.breaker.routes]]
+        path = "/status/{code}"
+        allow_methods = ["GET"]
+        protected = false
+
+        [[services.breaker.routes]]
+        path = "/slow"
+        allow_methods = ["GET"]
+        protected = false
+
+        [[services.breaker.routes]]
+        path = "/echo"
+        allow_methods = ["POST"]
+        protected = false
+        max_body_bytes = 16
+        "#,
+    )
+    .unwrap()
+}
+
+/// Opens the breaker of the only instance and waits until it lets a trial through
+async fn open_breaker(app: &axum::Router) {
+    for _ in 0..2 {
+        assert_eq!(
+            get(app, "/api/breaker/status/500").await.0,
+            StatusCode::INTERNAL_SERVER_ERROR
+        );
+    }
+    assert_eq!(
+        get(app, "/api/breaker/status/200").await.0,
+        StatusCode::SERVICE_UNAVAILABLE
+    );
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+}
+
+#[tokio::test]
+async fn circuit_breaker_closes_after_successful_trial() {
+    tcx().await;
+    let app = app(breaker_config());
+    open_breaker(&app).await;
+
+    assert_eq!(get(&app, "/api/breaker/status/200").await.0, StatusCode::OK);
+
+    // Closed with its failures forgotten, one more doesn't reopen it
+    assert_eq!(
+        get(&app, "/api/breaker/status/500").await.0,
+        StatusCode::INTERNAL_SERVER_ERROR
+    );
+    assert_eq!(get(&app, "/api/breaker/status/200").await.0, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn circuit_breaker_reopens_after_failed_trial() {
+    tcx().await;
+    let app = app(breaker_config());
+    open_breaker(&app).await;
+
+    assert_eq!(
+        get(&app, "/api/breaker/status/500").await.0,
+        StatusCode::INTERNAL_SERVER_ERROR
+    );
+    assert_eq!(
+        get(&app, "/api/breaker/status/200").await.0,
+        StatusCode::SERVICE_UNAVAILABLE
+    );
+}
+
+#[tokio::test]
+async fn circuit_breaker_trials_without_outcome_free_their_slot() {
+    tcx().await;
+    let app = app(breaker_config());
+    open_breaker(&app).await;
+
+    // Rejected by the gateway before the instance could answer
+    let chunks = ["x".repeat(10), "x".repeat(10)].map(Ok::<_, std::io::Error>);
+    let request = Request::post("/api/breaker/echo")
+        .body(Body::from_stream(futures_util::stream::iter(chunks)))
+        .unwrap();
+    assert_eq!(send(&app, request).await.0, StatusCode::PAYLOAD_TOO_LARGE);
+
+    // Cancelled by the client
+    let cancelled =
+        tokio::time::timeout(Duration::from_millis(100), get(&app, "/api/breaker/slow")).await;
+    assert!(cancelled.is_err());
+
+    assert_eq!(get(&app, "/api/breaker/status/200").await.0, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn idempotent_requests_are_retried_on_another_instance() {
+    let app = app(tcx().await.config.clone());
+
+    for _ in 0..4 {
+        let (status, body) = get(&app, "/api/retry/").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "3001");
+    }
+}
+
+#[tokio::test]
+async fn non_idempotent_requests_are_not_retried() {
+    let app = app(tcx().await.config.clone());
+
+    let mut statuses = Vec::new();
+    for _ in 0..2 {
+        let request = Request::post("/api/retry/").body(Body::empty()).unwrap();
+        statuses.push(send(&app, request).await.0);
+    }
+
+    assert!(statuses.contains(&StatusCode::BAD_GATEWAY), "{statuses:?}");
+}
+
+#[tokio::test]
+async fn hung_upstream_returns_gateway_timeout() {
+    let app = app(tcx().await.config.clone());
+
+    let (status, body) = get(&app, "/api/slow/slow").await;
+    assert_eq!(status, StatusCode::GATEWAY_TIMEOUT);
+    assert!(body.contains("\"message\""));
+}
+
+#[tokio::test]
+async fn request_body_is_streamed_to_upstream() {
+    let app = app(tcx().await.config.clone());
+
+    let chunks = ["hello ", "world"].map(Ok::<_, std::io::Error>);
+    let request = Request::post("/api/upload/echo")
+        .body(Body::from_stream(futures_util::stream::iter(chunks)))
+        .unwrap();
+    let (status, body) = send(&app, request).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "hello world");
+}
+
+#[tokio::test]
+async fn oversized_body_returns_payload_too_large() {
+    let app = app(tcx().await.config.clone());
+
+    let request = Request::post("/api/upload/echo")
+        .body(Body::from("x".repeat(17)))
+        .unwrap();
+    let (status, _) = send(&app, request).await;
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+
+    // Without a known size, the limit applies while streaming
+    let chunks = ["x".repeat(10), "x".repeat(10)].map(Ok::<_, std::io::Error>);
+    let request = Request::post("/api/upload/echo")
+        .body(Body::from_stream(futures_util::stream::iter(chunks)))
+        .unwrap();
+    let (status, _) = send(&app, request).await;
+    assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+}
+
+#[tokio::test]
+async fn routes_match_params_and_wildcards() {
+    let app = app(tcx().await.config.clone());
+
+    let (status, body) = get(&app, "/api/params/users/42").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "42");
+
+    let (status, body) = get(&app, "/api/params/files/a/b.txt").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "a/b.txt");
+
+    let (status, _) = get(&app, "/api/params/users/42/posts").await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn routes_sharing_a_path_are_matched_by_method() {
+    let app = app(tcx().await.config.clone());
+
+    let request = Request::delete("/api/params/users/42")
+        .body(Body::empty())
+        .unwrap();
+    assert_eq!(send(&app, request).await.0, StatusCode::UNAUTHORIZED);
+
+    let request = Request::post("/api/params/users/42")
+        .body(Body::empty())
+        .unwrap();
+    assert_eq!(send(&app, request).await.0, StatusCode::METHOD_NOT_ALLOWED);
+}
+
+#[tokio::test]
+async fn config_changes_are_reloaded() {
+    tcx().await;
+    let path = std::env::temp_dir().join(format!("gateway-reload-{}.toml", std::process::id()));
+    let service = |name: &str| {
+        format!(
+            "[services.{name}]\ninstances = [\"localhost:3001\"]\n\n\
+             [[services.{name}.routes]]\npath = \"/\"\nallow_methods = [\"GET\"]\nprotected = false\n"
+        )
+    };
+    std::fs::write(&path, service("before")).unwrap();
+
+    let app = reloading_app(path.to_str().unwrap()).unwrap();
+    assert_eq!(get(&app, "/api/before/").await.0, StatusCode::OK);
+    assert_eq!(get(&app, "/api/after/").await.0, StatusCode::NOT_FOUND);
+
+    // An invalid config is ignored
+    std::fs::write(&path, "[services.after").unwrap();
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert_eq!(get(&app, "/api/before/").await.0, StatusCode::OK);
+
+    std::fs::write(&path, service("after")).unwrap();
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert_eq!(get(&app, "/api/after/").await.0, StatusCode::OK);
+    assert_eq!(get(&app, "/api/before/").await.0, StatusCode::NOT_FOUND);
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn reloads_keep_balancing_state() {
+    tcx().await;
+    let path = std::env::temp_dir().join(format!("gateway-balancing-{}.toml", std::process::id()));
+    let service = |name: &str| {
+        format!(
+            "[services.{name}]\ninstances = [\"localhost:3001\", \"localhost:3002\"]\n\
+             strategy = \"RoundRobin\"\n\n\
+             [[services.{name}.routes]]\npath = \"/\"\nallow_methods = [\"GET\"]\nprotected = false\n"
+        )
+    };
+    std::fs::write(&path, service("balanced")).unwrap();
+
+    let app = reloading_app(path.to_str().unwrap()).unwrap();
+    let (_, first) = get(&app, "/api/balanced/").await;
+
+    std::fs::write(&path, service("balanced") + &service("other")).unwrap();
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    assert_eq!(get(&app, "/api/other/").await.0, StatusCode::OK);
+
+    // The rotation of the unchanged service goes on where it was
+    let (status, second) = get(&app, "/api/balanced/").await;
+    assert_eq!(status, StatusCode::OK);
+    assert_ne!(first, second);
+
+    std::fs::remove_file(path).unwrap();
+}
+
+#[tokio::test]
+async fn token_bucket_limits_each_api_key() {
+    let app = app(tcx().await.config.clone());
+    let request = |key: &str| {
+        Request::get("/api/limited/")
+            .header("x-api-key", key)
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    let response = app.clone().oneshot(request("a")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    assert_eq!(response.headers()["RateLimit-Limit"], "2");
+    assert_eq!(response.headers()["RateLimit-Remaining"], "1");
+
+    assert_eq!(send(&app, request("a")).await.0, StatusCode::OK);
+
+    let response = app.clone().oneshot(request("a")).await.unwrap();
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(response.headers()["RateLimit-Remaining"], "0");
+    assert!(response.headers().contains_key("Retry-After"));
+
+    assert_eq!(send(&app, request("b")).await.0, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn unknown_api_keys_share_the_client_quota() {
+    let app = app(tcx().await.config.clone());
+    let request = |key: &str| {
+        Request::get("/api/limited/")
+            .header("x-api-key", key)
+            .extension(ConnectInfo(SocketAddr::from(([192, 0, 2, 7], 4000))))
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    // A new key on every request doesn't get a new quota
+    assert_eq!(send(&app, request("made-up-1")).await.0, StatusCode::OK);
+    assert_eq!(send(&app, request("made-up-2")).await.0, StatusCode::OK);
+    assert_eq!(
+        send(&app, request("made-up-3")).await.0,
+        StatusCode::TOO_MANY_REQUESTS
+    );
+}
+
+#[tokio::test]
+async fn route_policies_have_their_own_quota() {
+    let app = app(tcx().await.config.clone());
+
+    assert_eq!(get(&app, "/api/limited/users/1").await.0, StatusCode::OK);
+    assert_eq!(
+        get(&app, "/api/limited/users/1").await.0,
+        StatusCode::TOO_MANY_REQUESTS
+    );
+
+    // The global policy is untouched
+    assert_eq!(get(&app, "/api/example/").await.0, StatusCode::OK);
+}
+
+fn shared_store_config(store: &str) -> Config {
+    config::parse(&format!(
+        r#"
+        [rate_limit]
+        max_requests = 2
+        store = {{ Redis = {store} }}
+
+        [services.example]
+        instances = ["localhost:3001"]
+
+        [[services.example.routes]]
+        path = "/"
+        allow_methods = ["GET"]
+        protected = false
+
+        [[services.example.routes]]
+        path = "/users/{{id}}"
+        allow_methods = ["GET"]
+        protected = false
+        rate_limit = {{ algorithm = "SlidingLog", max_requests = 1 }}
+        "#
+    ))
+    .unwrap()
+}
+
+#[tokio::test]
+async fn shared_store_limits_across_replicas() {
+    tcx().await;
+    let port = launch_redis();
+    let config = shared_store_config(&format!(
+        r#"{{ url = "redis://127.0.0.1:{port}", fail_open = false }}"#
+    ));
+    let (first, second) = (app(config.clone()), app(config));
+
+    assert_eq!(get(&first, "/api/example/").await.0, StatusCode::OK);
+    assert_eq!(get(&second, "/api/example/").await.0, StatusCode::OK);
+    assert_eq!(
+        get(&first, "/api/example/").await.0,
+        StatusCode::TOO_MANY_REQUESTS
+    );
+
+    assert_eq!(get(&second, "/api/example/users/1").await.0, StatusCode::OK);
+    assert_eq!(
+        get(&first, "/api/example/users/1").await.0,
+        StatusCode::TOO_MANY_REQUESTS
+    );
+}
+
+#[tokio::test]
+async fn unreachable_store_fails_open_or_closed() {
+    tcx().await;
+
+    let config = shared_store_config(r#"{ url = "redis://127.0.0.1:3012", fail_open = true }"#);
+    assert_eq!(get(&app(config), "/api/example/").await.0, StatusCode::OK);
+
+    let config = shared_store_config(r#"{ url = "redis://127.0.0.1:3012", fail_open = false }"#);
+    assert_eq!(
+        get(&app(config), "/api/example/").await.0,
+        StatusCode::SERVICE_UNAVAILABLE
+    );
+}
+
+fn token(claims: serde_json::Value) -> String {
+    jwks::token(TEST_KID, claims)
+}
+
+#[tokio::test]
+async fn verified_identity_is_forwarded_signed() {
+    let app = app(tcx().await.config.clone());
+    let token = token(serde_json::json!({
+        "exp": u64::MAX / 2,
+        "user_id": "42",
+        "role": "admin",
+    }));
+
+    let request = Request::get("/api/identity/headers")
+        .header("Authorization", format!("Bearer {token}"))
+        .header("X-User-Id", "spoofed")
+        .body(Body::empty())
+        .unwrap();
+    let (status, body) = send(&app, request).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let headers: Vec<(&str, &str)> = body
+        .lines()
+        .map(|line| line.split_once(": ").unwrap())
+        .collect();
+    let header = |name: &str| headers.iter().find(|(n, _)| *n == name).unwrap().1;
+    assert_eq!(header("x-user-id"), "42");
+    assert_eq!(header("x-user-role"), "admin");
+
+    let secret = std::env::var("GATEWAY_SIGNING_SECRET").unwrap();
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(
+        format!(
+            "{}\nGET\n/headers\nx-user-id:42\nx-user-role:admin\n",
+            header("x-gateway-timestamp")
+        )
+        .as_bytes(),
+    );
+    mac.verify_slice(&hex::decode(header("x-gateway-signature")).unwrap())
+        .unwrap();
+}
+
+#[tokio::test]
+async fn websocket_query_is_forwarded_signed() {
+    let app = app(tcx().await.config.clone());
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        let app = app.into_make_service_with_connect_info::<SocketAddr>();
+        axum::serve(listener, app).await.unwrap();
+    });
+
+    let token = token(serde_json::json!({ "exp": u64::MAX / 2, "user_id": "42" }));
+    let mut request = format!("ws://127.0.0.1:{port}/ws/live/socket?room=1")
+        .into_client_request()
+        .unwrap();
+    request
+        .headers_mut()
+        .insert("Authorization", format!("Bearer {token}").parse().unwrap());
+    let (mut socket, _) = connect_async(request).await.unwrap();
+    let message = socket.next().await.unwrap().unwrap().into_text().unwrap();
+
+    let mut lines = message.lines();
+    assert_eq!(lines.next(), Some("/socket?room=1"));
+    let headers: Vec<(&str, &str)> = lines.map(|line| line.split_once(": ").unwrap()).collect();
+    let header = |name: &str| headers.iter().find(|(n, _)| *n == name).unwrap().1;
+
+    let secret = std::env::var("GATEWAY_SIGNING_SECRET").unwrap();
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(
+        format!(
+            "{}\nGET\n/socket?room=1\nx-user-id:42\n",
+            header("x-gateway-timestamp")
+        )
+        .as_bytes(),
+    );
+    mac.verify_slice(&hex::decode(header("x-gateway-signature")).unwrap())
+        .unwrap();
+}
+
+#[tokio::test]
+async fn client_identity_headers_are_dropped() {
+    let app = app(tcx().await.config.clone());
+
+    let request = Request::get("/api/anonymous/headers")
+        .header("X-User-Id", "spoofed")
+        .header("X-User-Role", "admin")
+        .header("X-Gateway-Signature", "forged")
+        .body(Body::empty())
+        .unwrap();
+    let (status, body) = send(&app, request).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(!body.contains("x-user-"));
+    assert!(!body.contains("forged"));
+    assert!(body.contains("x-gateway-timestamp"));
+}
+
+#[tokio::test]
+async fn client_address_is_appended_to_forwarded_for() {
+    let app = app(tcx().await.config.clone());
+    let peer: SocketAddr = "198.51.100.7:40000".parse().unwrap();
+
+    let request = Request::get("/api/anonymous/headers")
+        .header("X-Forwarded-For", "203.0.113.9")
+        .extension(ConnectInfo(peer))
+        .body(Body::empty())
+        .unwrap();
+    let (status, body) = send(&app, request).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.contains("x-forwarded-for: 203.0.113.9, 198.51.100.7"));
+}
+
+#[tokio::test]
+async fn routes_require_roles_and_scopes() {
+    let app = app(tcx().await.config.clone());
+    let request = |claims: serde_json::Value| {
+        Request::delete("/api/identity/users/42")
+            .header("Authorization", format!("Bearer {}", token(claims)))
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    let (status, body) = send(
+        &app,
+        request(serde_json::json!({
+            "exp": u64::MAX / 2,
+            "user_id": "42",
+            "roles": ["admin"],
+            "scope": "users:read users:delete",
+        })),
+    )
+    .await;
+    assert_eq!((status, body.as_str()), (StatusCode::OK, "42"));
+
+    let (status, body) = send(
+        &app,
+        request(serde_json::json!({
+            "exp": u64::MAX / 2,
+            "user_id": "42",
+            "roles": ["user"],
+            "scope": "users:delete",
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(body["message"], "Missing required role `admin`");
+
+    let (status, _) = send(
+        &app,
+        request(serde_json::json!({
+            "exp": u64::MAX / 2,
+            "user_id": "42",
+            "roles": ["admin"],
+            "scope": "users:read",
+        })),
+    )
+    .await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn app_tokens_only_get_into_scoped_routes() {
+    let app = app(tcx().await.config.clone());
+    let claims = serde_json::json!({
+        "exp": u64::MAX / 2,
+        "user_id": "42",
+        "client_id": "some-app",
+        "roles": ["admin"],
+        "scope": "users:delete",
+    });
+    let request = |method: &str, path: &str| {
+        Request::builder()
+            .method(method)
+            .uri(path)
+            .header("Authorization", format!("Bearer {}", token(claims.clone())))
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    let (status, body) = send(&app, request("GET", "/api/identity/headers")).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let body: serde_json::Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(body["message"], "Route isn't available to apps");
+
+    let (status, _) = send(&app, request("DELETE", "/api/identity/users/42")).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn unprotected_routes_cannot_require_roles() {
+    let config = config::parse(
+        r#"
+        [services.example]
+        instances = ["localhost:3001"]
+
+        [[services.example.routes]]
+        path = "/"
+        allow_methods = ["GET"]
+        protected = false
+        requires = ["admin"]
+        "#,
+    );
+    assert!(config.is_err());
+}
+
+#[tokio::test]
+async fn tokens_are_verified_with_rotated_keys() {
+    let app = app(tcx().await.config.clone());
+    let claims = serde_json::json!({ "exp": u64::MAX / 2, "user_id": "42" });
+    let request = |token: String| {
+        Request::get("/api/identity/headers")
+            .header("Authorization", format!("Bearer {token}"))
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    let old = jwks::token(TEST_KID, claims.clone());
+    assert_eq!(send(&app, request(old.clone())).await.0, StatusCode::OK);
+
+    // Unknown until published, then fetched on first use
+    let rotated = jwks::token("rotated-key", claims.clone());
+    assert_eq!(
+        send(&app, request(rotated.clone())).await.0,
+        StatusCode::UNAUTHORIZED
+    );
+    jwks::publish("rotated-key");
+    assert_eq!(send(&app, request(rotated)).await.0, StatusCode::OK);
+    assert_eq!(send(&app, request(old)).await.0, StatusCode::OK);
+
+    // The shared secret isn't trusted once keys are published
+    let secret = std::env::var("JWT_SECRET").unwrap();
+    let header = Header {
+        kid: Some(TEST_KID.to_string()),
+        ..Default::default()
+    };
+    let forged = encode(
+        &header,
+        &claims,
+        &EncodingKey::from_secret(secret.as_bytes()),
+    )
+    .unwrap();
+    assert_eq!(
+        send(&app, request(forged)).await.0,
+        StatusCode::UNAUTHORIZED
+    );
+}
+
+#[tokio::test]
+async fn revoked_tokens_are_rejected() {
+    let app = app(tcx().await.config.clone());
+    let claims =
+        |jti: &str| serde_json::json!({ "exp": u64::MAX / 2, "user_id": "42", "jti": jti });
+    let request = |jti: &str| {
+        Request::get("/api/identity/headers")
+            .header("Authorization", format!("Bearer {}", token(claims(jti))))
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    assert_eq!(send(&app, request("logged-out")).await.0, StatusCode::OK);
+
+    jwks::revoke("logged-out", u64::MAX / 2);
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    assert_eq!(
+        send(&app, request("logged-out")).await.0,
+        StatusCode::UNAUTHORIZED
+    );
+    assert_eq!(send(&app, request("still-in")).await.0, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn revoked_sessions_are_rejected() {
+    let app = app(tcx().await.config.clone());
+    let request = |jti: &str, sid: &str| {
+        let claims = serde_json::json!({
+            "exp": u64::MAX / 2, "user_id": "42", "jti": jti, "sid": sid,
+        });
+        Request::get("/api/identity/headers")
+            .header("Authorization", format!("Bearer {}", token(claims)))
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    assert_eq!(
+        send(&app, request("first", "signed-out-device")).await.0,
+        StatusCode::OK
+    );
+
+    jwks::revoke("signed-out-device", u64::MAX / 2);
+    tokio::time::sleep(Duration::from_millis(1500)).await;
+    // Every token of the session, whatever its `jti`
+    for jti in ["first", "second"] {
+        assert_eq!(
+            send(&app, request(jti, "signed-out-device")).await.0,
+            StatusCode::UNAUTHORIZED
+        );
+    }
+    assert_eq!(
+        send(&app, request("third", "other-device")).await.0,
+        StatusCode::OK
+    );
+}
+
+#[tokio::test]
+async fn unfetched_revocations_fail_closed() {
+    tcx().await;
+    // Nothing listens on 3014
+    let config = config::parse(
+        r#"
+        [jwt]
+        jwks_url = "http://localhost:3013/.well-known/jwks.json"
+        revocations_url = "http://localhost:3014/revocations"
+
+        [services.identity]
+        instances = ["localhost:3001"]
+
+        [[services.identity.routes]]
+        path = "/headers"
+        allow_methods = ["GET"]
+        protected = true
+
+        [[services.identity.routes]]
+        path = "/public"
+        allow_methods = ["GET"]
+        protected = false
+        "#,
+    )
+    .unwrap();
+    let app = app(config);
+    let request = || {
+        let claims = serde_json::json!({ "exp": u64::MAX / 2, "user_id": "42", "jti": "any" });
+        Request::get("/api/identity/headers")
+            .header("Authorization", format!("Bearer {}", token(claims)))
+            .body(Body::empty())
+            .unwrap()
+    };
+
+    assert_eq!(
+        send(&app, request()).await.0,
+        StatusCode::SERVICE_UNAVAILABLE
+    );
+    assert_ne!(
+        get(&app, "/api/identity/public").await.0,
+        StatusCode::SERVICE_UNAVAILABLE
+    );
+}
@@ -0,0 +1,123 @@
+use axum::extract::State;
+use axum::http::HeaderMap;
+use axum::{Json, response::IntoResponse};
+use chrono::Utc;
+use serde::Deserialize;
+use serde_json::to_vec;
+use topic_structs::UserDeleted;
+use tracing::error;
+
+use crate::api_utils::responses::{INTERNAL_SERVER_ERROR, INVALID_TOKEN};
+use crate::db::operations::{
+    change_password, delete_user, get_user_by_id, revoke_all_sessions, revoke_other_sessions,
+};
+use crate::db::password_hasher::hash_password;
+use crate::lockout::check_account_password;
+use crate::models::app_state::AppState;
+use crate::password_policy::check_password;
+use crate::sessions::{ClientInfo, authenticate, tokens_expire_at};
+
+#[derive(Deserialize)]
+pub struct ChangePasswordData {
+    current_password: String,
+    new_password: String,
+}
+
+pub async fn change_user_password(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    client: ClientInfo,
+    Json(data): Json<ChangePasswordData>,
+) -> impl IntoResponse {
+    let claims = match authenticate(&state.db, &headers).await {
+        Ok(claims) => claims,
+        Err(response) => return response,
+    };
+
+    let user = match get_user_by_id(&state.db, &claims.user_id).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return INVALID_TOKEN.into_response(),
+        Err(e) => {
+            error!("Failed to get user: {}", e);
+            return INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    // A stolen access token alone isn't enough to take the account over
+    if let Err(response) =
+        check_account_password(&state, &user, &data.current_password, &client).await
+    {
+        return response;
+    }
+
+    if let Err(e) = check_password(&data.new_password) {
+        return e.into_response();
+    }
+
+    let hashed_password = match hash_password(&data.new_password).await {
+        Ok(p) => p,
+        Err(_) => return INTERNAL_SERVER_ERROR.into_response(),
+    };
+
+    if let Err(e) = change_password(&state.db, &user.username, &hashed_password).await {
+        error!("Failed to change password: {}", e);
+        return INTERNAL_SERVER_ERROR.into_response();
+    }
+
+    // Whoever knew the old password is signed out everywhere but here
+    if let Err(e) =
+        revoke_other_sessions(&state.db, &user.id, &claims.sid, tokens_expire_at()).await
+    {
+        error!("Failed to revoke sessions: {}", e);
+        return INTERNAL_SERVER_ERROR.into_response();
+    }
+
+    Json("Password changed").into_response()
+}
+
+pub async fn delete_account(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let claims = match authenticate(&state.db, &headers).await {
+        Ok(claims) => claims,
+        Err(response) => return response,
+    };
+
+    let user = match get_user_by_id(&state.db, &claims.user_id).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return INVALID_TOKEN.into_response(),
+        Err(e) => {
+            error!("Failed to get user: {}", e);
+            return INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    // Sessions and refresh tokens are deleted with the user, but access tokens
+    // already issued must be rejected until they expire
+    if let Err(e) = revoke_all_sessions(&state.db, &user.id, tokens_expire_at()).await {
+        error!("Failed to revoke sessions: {}", e);
+        return INTERNAL_SERVER_ERROR.into_response();
+    }
+
+    if let Err(e) = delete_user(&state.db, &user.username).await {
+        error!("Failed to delete user: {}", e);
+        return INTERNAL_SERVER_ERROR.into_response();
+    }
+
+    let event = UserDeleted {
+        id: user.id.clone(),
+        deletion_time: Utc::now().timestamp(),
+    };
+
+    let Ok(event_bytes) = to_vec(&event) else {
+        return INTERNAL_SERVER_ERROR.into_response();
+    };
+
+    // The account is gone whether or not other services hear about it
+    if let Err(e) = state.delete_producer.send(user.id, event_bytes).await {
+        error!("Failed to send UserDeleted event to Fluvio: {}", e);
+    }
+
+    Json("Account deleted").into_response()
+}
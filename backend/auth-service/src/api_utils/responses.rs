@@ -15,9 +15,114 @@ pub static USERNAME_ALREADY_USED: ApiResponse = (
     }),
 );
 
+pub static EMAIL_ALREADY_USED: ApiResponse = (
+    StatusCode::BAD_REQUEST,
+    Json(ApiResponseMessage {
+        message: "Email is already used",
+    }),
+);
+
 pub static INTERNAL_SERVER_ERROR: ApiResponse = (
     StatusCode::INTERNAL_SERVER_ERROR,
     Json(ApiResponseMessage {
         message: "Internal server error",
     }),
 );
+
+pub static INVALID_REFRESH_TOKEN: ApiResponse = (
+    StatusCode::UNAUTHORIZED,
+    Json(ApiResponseMessage {
+        message: "Invalid refresh token",
+    }),
+);
+
+pub static INVALID_TOKEN: ApiResponse = (
+    StatusCode::UNAUTHORIZED,
+    Json(ApiResponseMessage {
+        message: "Invalid token",
+    }),
+);
+
+pub static SESSION_NOT_FOUND: ApiResponse = (
+    StatusCode::NOT_FOUND,
+    Json(ApiResponseMessage {
+        message: "Session not found",
+    }),
+);
+
+pub static WRONG_PASSWORD: ApiResponse = (
+    StatusCode::FORBIDDEN,
+    Json(ApiResponseMessage {
+        message: "Current password is incorrect",
+    }),
+);
+
+pub static INVALID_EMAIL_TOKEN: ApiResponse = (
+    StatusCode::BAD_REQUEST,
+    Json(ApiResponseMessage {
+        message: "Invalid or expired link",
+    }),
+);
+
+pub static EMAIL_ALREADY_VERIFIED: ApiResponse = (
+    StatusCode::CONFLICT,
+    Json(ApiResponseMessage {
+        message: "Email already verified",
+    }),
+);
+
+pub static TWO_FACTOR_ALREADY_ENABLED: ApiResponse = (
+    StatusCode::CONFLICT,
+    Json(ApiResponseMessage {
+        message: "Two-factor authentication already enabled",
+    }),
+);
+
+pub static TWO_FACTOR_NOT_STARTED: ApiResponse = (
+    StatusCode::BAD_REQUEST,
+    Json(ApiResponseMessage {
+        message: "Two-factor setup hasn't been started",
+    }),
+);
+
+pub static INVALID_TWO_FACTOR_CODE: ApiResponse = (
+    StatusCode::UNAUTHORIZED,
+    Json(ApiResponseMessage {
+        message: "Invalid code",
+    }),
+);
+
+pub static INVALID_LOGIN_CHALLENGE: ApiResponse = (
+    StatusCode::UNAUTHORIZED,
+    Json(ApiResponseMessage {
+        message: "Invalid or expired login challenge",
+    }),
+);
+
+pub static TOO_MANY_LOGIN_ATTEMPTS: ApiResponse = (
+    StatusCode::TOO_MANY_REQUESTS,
+    Json(ApiResponseMessage {
+        message: "Too many failed login attempts, try again later",
+    }),
+);
+
+pub static TOO_MANY_RESET_REQUESTS: ApiResponse = (
+    StatusCode::TOO_MANY_REQUESTS,
+    Json(ApiResponseMessage {
+        message: "Too many password reset requests, try again later",
+    }),
+);
+
+pub static CLIENT_NOT_FOUND: ApiResponse = (
+    StatusCode::NOT_FOUND,
+    Json(ApiResponseMessage {
+        message: "OAuth client not found",
+    }),
+);
+
+pub static CONSENT_NOT_FOUND: ApiResponse = (
+    StatusCode::NOT_FOUND,
+    Json(ApiResponseMessage {
+        message: "The client hasn't been authorized",
+    }),
+);
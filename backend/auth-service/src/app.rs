@@ -1,17 +1,40 @@
+use crate::account::{change_user_password, delete_account};
 use crate::db::init::init;
+use crate::db::password_hasher::ARGON2_SETTINGS;
+use crate::jwks::get_jwks;
+use crate::jwt::KEYS;
 use crate::log_out::log_user_out;
+use crate::mail;
 use crate::models::app_state::AppState;
+use crate::oauth::authorize::{decide_authorization, get_authorization};
+use crate::oauth::clients::{delete_client, list_clients, register_client};
+use crate::oauth::consents::{list_consents, revoke_client_consent};
+use crate::oauth::token::issue_token;
+use crate::password_policy::PASSWORD_POLICY;
+use crate::password_reset::{forgot_password, reset_password};
+use crate::refresh::refresh_tokens;
 use crate::register::register_user;
+use crate::revocations::list_revocations;
+use crate::sessions::{list_sessions, sign_out_other_sessions, sign_out_session};
 use crate::sign_in::sign_in_user;
+use crate::two_factor::{
+    confirm_two_factor_setup, disable_two_factor, sign_in_with_two_factor, start_two_factor_setup,
+};
+use crate::verify_email::{resend_verification_email, verify_email};
 
 use anyhow::Result;
 use axum::http::{HeaderValue, Method, header};
-use axum::{Router, routing::post};
+use axum::{
+    Router,
+    routing::{delete, get, post},
+};
 use dotenvy::dotenv;
 use fluvio::FluvioConfig;
 use fluvio::metadata::topic::TopicSpec;
 use sqlx::postgres::PgPoolOptions;
 use std::env::var;
+use std::net::SocketAddr;
+use std::sync::LazyLock;
 use std::{env, time::Duration};
 use tower_http::cors::CorsLayer;
 use tower_http::trace::TraceLayer;
@@ -32,7 +55,7 @@ pub async fn run() -> Result<()> {
     let cors_layer = CorsLayer::new()
         .allow_origin(origins)
         .allow_credentials(true)
-        .allow_methods([Method::GET, Method::POST])
+        .allow_methods([Method::GET, Method::POST, Method::DELETE])
         .allow_headers([
             header::CONTENT_TYPE,
             header::AUTHORIZATION,
@@ -46,6 +69,12 @@ pub async fn run() -> Result<()> {
 
     let trace_layer = TraceLayer::new_for_http();
 
+    // Fail on startup rather than on the first login
+    LazyLock::force(&KEYS);
+    info!("JWT signing keys loaded");
+    LazyLock::force(&ARGON2_SETTINGS);
+    LazyLock::force(&PASSWORD_POLICY);
+
     let database_url = env::var("AUTH_DATABASE_URL")
         .map_err(|_| anyhow::anyhow!("AUTH_DATABASE_URL must be set in .env"))?;
 
@@ -87,6 +116,16 @@ pub async fn run() -> Result<()> {
         .trim()
         .to_string();
 
+    let delete_topic = var("AUTH_DELETE_TOPIC")
+        .unwrap_or("auth-delete".to_owned())
+        .trim()
+        .to_string();
+
+    let lockout_topic = var("AUTH_LOCKOUT_TOPIC")
+        .unwrap_or("auth-lockout".to_owned())
+        .trim()
+        .to_string();
+
     let admin = fluvio.admin().await;
 
     let topics = admin
@@ -117,22 +156,70 @@ pub async fn run() -> Result<()> {
             .await?;
     }
 
+    if !topic_names.contains(&delete_topic) {
+        let topic_spec = TopicSpec::new_computed(1, 1, None);
+        admin
+            .create(delete_topic.clone(), false, topic_spec)
+            .await?;
+    }
+
+    if !topic_names.contains(&lockout_topic) {
+        let topic_spec = TopicSpec::new_computed(1, 1, None);
+        admin
+            .create(lockout_topic.clone(), false, topic_spec)
+            .await?;
+    }
+
     let register_producer = fluvio.topic_producer(register_topic).await?;
     let login_producer = fluvio.topic_producer(login_topic).await?;
     let logout_producer = fluvio.topic_producer(logout_topic).await?;
+    let delete_producer = fluvio.topic_producer(delete_topic).await?;
+    let lockout_producer = fluvio.topic_producer(lockout_topic).await?;
     info!("Connected to Fluvio");
 
+    let mailer = mail::from_env()?;
+
     let state = AppState {
         db: pool,
         register_producer,
         login_producer,
         logout_producer,
+        delete_producer,
+        lockout_producer,
+        mailer,
     };
 
     let app = Router::new()
         .route("/register", post(register_user))
         .route("/login", post(sign_in_user))
+        .route("/login/2fa", post(sign_in_with_two_factor))
+        .route("/2fa", delete(disable_two_factor))
+        .route("/2fa/setup", post(start_two_factor_setup))
+        .route("/2fa/confirm", post(confirm_two_factor_setup))
+        .route("/refresh", post(refresh_tokens))
         .route("/logout", post(log_user_out))
+        .route("/password", post(change_user_password))
+        .route("/account", delete(delete_account))
+        .route("/verify-email", post(verify_email))
+        .route("/verify-email/resend", post(resend_verification_email))
+        .route("/forgot-password", post(forgot_password))
+        .route("/reset-password", post(reset_password))
+        .route(
+            "/sessions",
+            get(list_sessions).delete(sign_out_other_sessions),
+        )
+        .route("/sessions/{id}", delete(sign_out_session))
+        .route("/revocations", get(list_revocations))
+        .route("/.well-known/jwks.json", get(get_jwks))
+        .route("/oauth/clients", get(list_clients).post(register_client))
+        .route("/oauth/clients/{id}", delete(delete_client))
+        .route(
+            "/oauth/authorize",
+            get(get_authorization).post(decide_authorization),
+        )
+        .route("/oauth/token", post(issue_token))
+        .route("/oauth/consents", get(list_consents))
+        .route("/oauth/consents/{client_id}", delete(revoke_client_consent))
         .layer(cors_layer)
         .layer(trace_layer)
         .with_state(state);
@@ -140,7 +227,11 @@ pub async fn run() -> Result<()> {
     let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await?;
     info!("Server listening on 0.0.0.0:3000");
 
-    axum::serve(listener, app).await?;
+    axum::serve(
+        listener,
+        app.into_make_service_with_connect_info::<SocketAddr>(),
+    )
+    .await?;
 
     Ok(())
 }
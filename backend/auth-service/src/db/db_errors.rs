@@ -5,19 +5,28 @@ const UNIQUE_VIOLATED: &str = "23505";
 
 #[derive(Debug, Error)]
 pub enum UserInsertError {
-    #[error("The email or the username is already in used")]
+    #[error("The username is already in use")]
     UsernameTaken,
+    #[error("The email is already in use")]
+    EmailTaken,
     #[error("Database not working properly")]
     Database(SqlxError),
 }
 
 impl From<SqlxError> for UserInsertError {
     fn from(err: SqlxError) -> Self {
-        if let SqlxError::Database(db_err) = &err {
-            if db_err.code().as_deref() == Some(UNIQUE_VIOLATED) {
-                return UserInsertError::UsernameTaken;
-            }
+        let SqlxError::Database(db_err) = &err else {
+            return UserInsertError::Database(err);
+        };
+        if db_err.code().as_deref() != Some(UNIQUE_VIOLATED) {
+            return UserInsertError::Database(err);
+        }
+
+        // Told apart by the unique constraint or index that was violated
+        match db_err.constraint() {
+            Some(constraint) if constraint.contains("username") => UserInsertError::UsernameTaken,
+            Some(constraint) if constraint.contains("email") => UserInsertError::EmailTaken,
+            _ => UserInsertError::Database(err),
         }
-        UserInsertError::Database(err)
     }
 }
@@ -1,3 +1,9 @@
+use std::collections::HashSet;
+
+use tracing::warn;
+
+use crate::identifiers::fold;
+
 pub async fn init(db: &sqlx::PgPool) -> anyhow::Result<()> {
     sqlx::query(
         "
@@ -13,5 +19,344 @@ pub async fn init(db: &sqlx::PgPool) -> anyhow::Result<()> {
     .execute(db)
     .await?;
 
+    // Granted by hand for now, the gateway checks them on routes requiring them
+    sqlx::query(
+        "
+        ALTER TABLE users
+        ADD COLUMN IF NOT EXISTS roles TEXT[] NOT NULL DEFAULT '{}',
+        ADD COLUMN IF NOT EXISTS scopes TEXT[] NOT NULL DEFAULT '{}'
+        ",
+    )
+    .execute(db)
+    .await?;
+
+    // One per login, revoking it revokes its refresh tokens
+    sqlx::query(
+        "
+        CREATE TABLE IF NOT EXISTS sessions (
+        id TEXT PRIMARY KEY,
+        user_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+        device TEXT,
+        ip TEXT,
+        created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+        last_used_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+        revoked_at TIMESTAMPTZ
+        )
+        ",
+    )
+    .execute(db)
+    .await?;
+
+    // Only hashes are stored. Tokens rotated from the same login share a family,
+    // which is the id of its session
+    sqlx::query(
+        "
+        CREATE TABLE IF NOT EXISTS refresh_tokens (
+        id TEXT PRIMARY KEY,
+        user_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+        family_id TEXT NOT NULL,
+        token_hash TEXT NOT NULL UNIQUE,
+        expires_at TIMESTAMPTZ NOT NULL,
+        used_at TIMESTAMPTZ,
+        revoked BOOLEAN NOT NULL DEFAULT FALSE
+        )
+        ",
+    )
+    .execute(db)
+    .await?;
+
+    sqlx::query("CREATE INDEX IF NOT EXISTS refresh_tokens_family ON refresh_tokens (family_id)")
+        .execute(db)
+        .await?;
+
+    // Access tokens logged out before they expire. `jti` is the id of a session too,
+    // when all of its access tokens are revoked
+    sqlx::query(
+        "
+        CREATE TABLE IF NOT EXISTS revoked_tokens (
+        id BIGSERIAL PRIMARY KEY,
+        jti TEXT NOT NULL UNIQUE,
+        user_id TEXT NOT NULL,
+        expires_at TIMESTAMPTZ NOT NULL
+        )
+        ",
+    )
+    .execute(db)
+    .await?;
+
+    // The transaction revoking them orders them for the consumers. Unlike `id`, it
+    // tells which ones may still commit: those of transactions still in progress
+    sqlx::query(
+        "
+        ALTER TABLE revoked_tokens
+        ADD COLUMN IF NOT EXISTS xact_id XID8 NOT NULL DEFAULT pg_current_xact_id()
+        ",
+    )
+    .execute(db)
+    .await?;
+
+    sqlx::query("CREATE INDEX IF NOT EXISTS revoked_tokens_xact ON revoked_tokens (xact_id)")
+        .execute(db)
+        .await?;
+
+    sqlx::query(
+        "
+        ALTER TABLE users
+        ADD COLUMN IF NOT EXISTS email_verified BOOLEAN NOT NULL DEFAULT FALSE
+        ",
+    )
+    .execute(db)
+    .await?;
+
+    // Single use tokens mailed to the user, only their hash is stored
+    sqlx::query(
+        "
+        CREATE TABLE IF NOT EXISTS email_tokens (
+        token_hash TEXT PRIMARY KEY,
+        user_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+        purpose TEXT NOT NULL, -- verify_email | reset_password
+        expires_at TIMESTAMPTZ NOT NULL,
+        used_at TIMESTAMPTZ
+        )
+        ",
+    )
+    .execute(db)
+    .await?;
+
+    // Unlike passwords, the secret is needed in clear to compute the codes.
+    // The last step used keeps a code from being used twice
+    sqlx::query(
+        "
+        ALTER TABLE users
+        ADD COLUMN IF NOT EXISTS totp_secret TEXT,
+        ADD COLUMN IF NOT EXISTS totp_enabled BOOLEAN NOT NULL DEFAULT FALSE,
+        ADD COLUMN IF NOT EXISTS totp_last_step BIGINT
+        ",
+    )
+    .execute(db)
+    .await?;
+
+    sqlx::query(
+        "
+        CREATE TABLE IF NOT EXISTS recovery_codes (
+        code_hash TEXT PRIMARY KEY,
+        user_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+        used_at TIMESTAMPTZ
+        )
+        ",
+    )
+    .execute(db)
+    .await?;
+
+    // Passwords checked, waiting on the second factor
+    sqlx::query(
+        "
+        CREATE TABLE IF NOT EXISTS login_challenges (
+        token_hash TEXT PRIMARY KEY,
+        user_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+        expires_at TIMESTAMPTZ NOT NULL,
+        attempts INT NOT NULL DEFAULT 0
+        )
+        ",
+    )
+    .execute(db)
+    .await?;
+
+    // What the user signed in with, the second factor's failures count against it too
+    sqlx::query(
+        "
+        ALTER TABLE login_challenges
+        ADD COLUMN IF NOT EXISTS identifier TEXT
+        ",
+    )
+    .execute(db)
+    .await?;
+
+    // Keyed by `user:{identifier}`, normalized, and `ip:{address}`
+    sqlx::query(
+        "
+        CREATE TABLE IF NOT EXISTS login_failures (
+        key TEXT PRIMARY KEY,
+        failures INT NOT NULL,
+        last_failure_at TIMESTAMPTZ NOT NULL,
+        blocked_until TIMESTAMPTZ
+        )
+        ",
+    )
+    .execute(db)
+    .await?;
+
+    // Usernames and emails are unique however they're written: their case
+    // folded NFKC forms are compared, the ones entered are kept to be shown
+    sqlx::query(
+        "
+        ALTER TABLE users
+        ADD COLUMN IF NOT EXISTS username_normalized TEXT,
+        ADD COLUMN IF NOT EXISTS email_normalized TEXT
+        ",
+    )
+    .execute(db)
+    .await?;
+
+    normalize_existing_users(db).await?;
+
+    sqlx::query(
+        "
+        ALTER TABLE users
+        ALTER COLUMN username_normalized SET NOT NULL,
+        ALTER COLUMN email_normalized SET NOT NULL
+        ",
+    )
+    .execute(db)
+    .await?;
+
+    sqlx::query(
+        "CREATE UNIQUE INDEX IF NOT EXISTS users_username_normalized_idx ON users (username_normalized)",
+    )
+    .execute(db)
+    .await?;
+
+    sqlx::query(
+        "CREATE UNIQUE INDEX IF NOT EXISTS users_email_normalized_idx ON users (email_normalized)",
+    )
+    .execute(db)
+    .await?;
+
+    // Apps and bots getting tokens through OAuth 2.0, the secret is random so
+    // it's hashed like refresh tokens
+    sqlx::query(
+        "
+        CREATE TABLE IF NOT EXISTS oauth_clients (
+        id TEXT PRIMARY KEY,
+        owner_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+        name TEXT NOT NULL,
+        secret_hash TEXT,
+        redirect_uris TEXT[] NOT NULL,
+        scopes TEXT[] NOT NULL,
+        created_at TIMESTAMPTZ NOT NULL DEFAULT NOW()
+        )
+        ",
+    )
+    .execute(db)
+    .await?;
+
+    // Apps get a session per authorization, limited to the scopes granted
+    sqlx::query(
+        "
+        ALTER TABLE sessions
+        ADD COLUMN IF NOT EXISTS client_id TEXT REFERENCES oauth_clients(id) ON DELETE CASCADE,
+        ADD COLUMN IF NOT EXISTS scopes TEXT[]
+        ",
+    )
+    .execute(db)
+    .await?;
+
+    sqlx::query(
+        "
+        CREATE TABLE IF NOT EXISTS oauth_consents (
+        user_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+        client_id TEXT NOT NULL REFERENCES oauth_clients(id) ON DELETE CASCADE,
+        scopes TEXT[] NOT NULL,
+        granted_at TIMESTAMPTZ NOT NULL DEFAULT NOW(),
+        PRIMARY KEY (user_id, client_id)
+        )
+        ",
+    )
+    .execute(db)
+    .await?;
+
+    // The session a code was exchanged for is revoked if the code comes back
+    sqlx::query(
+        "
+        CREATE TABLE IF NOT EXISTS oauth_codes (
+        code_hash TEXT PRIMARY KEY,
+        client_id TEXT NOT NULL REFERENCES oauth_clients(id) ON DELETE CASCADE,
+        user_id TEXT NOT NULL REFERENCES users(id) ON DELETE CASCADE,
+        redirect_uri TEXT NOT NULL,
+        scopes TEXT[] NOT NULL,
+        code_challenge TEXT NOT NULL,
+        expires_at TIMESTAMPTZ NOT NULL,
+        used_at TIMESTAMPTZ,
+        session_id TEXT
+        )
+        ",
+    )
+    .execute(db)
+    .await?;
+
+    Ok(())
+}
+
+/// Users from before are folded like new ones. Those clashing with an earlier one,
+/// by id, can't keep theirs: usernames get a numbered suffix, and emails are kept
+/// out of lookups until sorted out by hand.
+async fn normalize_existing_users(db: &sqlx::PgPool) -> anyhow::Result<()> {
+    let pending = sqlx::query_as::<_, (String, String, String)>(
+        "SELECT id, username, email FROM users WHERE username_normalized IS NULL ORDER BY id",
+    )
+    .fetch_all(db)
+    .await?;
+    if pending.is_empty() {
+        return Ok(());
+    }
+
+    let existing = sqlx::query_as::<_, (String, Option<String>, Option<String>)>(
+        "SELECT username, username_normalized, email_normalized FROM users",
+    )
+    .fetch_all(db)
+    .await?;
+    let mut usernames = HashSet::new();
+    let mut usernames_normalized = HashSet::new();
+    let mut emails_normalized = HashSet::new();
+    for (username, username_normalized, email_normalized) in existing {
+        usernames.insert(username);
+        usernames_normalized.extend(username_normalized);
+        emails_normalized.extend(email_normalized);
+    }
+
+    for (id, original, email) in pending {
+        let mut username = original.clone();
+        let mut suffix = 2;
+        while usernames_normalized.contains(&fold(&username))
+            || (username != original && usernames.contains(&username))
+        {
+            username = format!("{original}-{suffix}");
+            suffix += 1;
+        }
+        if username != original {
+            warn!(
+                id,
+                original, username, "Username clashes with another, renamed"
+            );
+            usernames.insert(username.clone());
+        }
+        let username_normalized = fold(&username);
+        usernames_normalized.insert(username_normalized.clone());
+
+        let mut email_normalized = fold(&email);
+        if !emails_normalized.insert(email_normalized.clone()) {
+            warn!(
+                id,
+                email, "Email clashes with another, it can't be signed in with"
+            );
+            // Nobody types that in, and it can't clash itself
+            email_normalized = format!("clash:{id}:{email_normalized}");
+        }
+
+        sqlx::query(
+            "
+            UPDATE users
+            SET username = $2, username_normalized = $3, email_normalized = $4
+            WHERE id = $1
+            ",
+        )
+        .bind(&id)
+        .bind(&username)
+        .bind(&username_normalized)
+        .bind(&email_normalized)
+        .execute(db)
+        .await?;
+    }
+
     Ok(())
 }
@@ -1,30 +1,46 @@
 use crate::db::db_errors::UserInsertError;
-use crate::db::password_hasher::verify_password;
-use crate::models::{auth_info::AuthInfo, user_info::UserInfo};
+use crate::db::password_hasher::{verify_dummy_password, verify_password};
+use crate::identifiers::Identifier;
+use crate::models::{
+    auth_info::AuthInfo,
+    authorization_code::{AuthorizationCode, StoredAuthorizationCode},
+    email_token::EmailTokenPurpose,
+    oauth_client::OAuthClient,
+    oauth_consent::OAuthConsent,
+    refresh_token::RefreshToken,
+    revoked_token::RevokedToken,
+    session::Session,
+    user_info::UserInfo,
+};
 
-use sqlx::PgPool;
+use chrono::{DateTime, Duration, Utc};
+use sqlx::{PgConnection, PgExecutor, PgPool};
 use uuid::Uuid;
 
 // For inserting users there's no need to specify the id (it's autogenerated)
 pub async fn insert_user(
     pool: &PgPool,
     username: &str,
+    username_normalized: &str,
     hashed_password: &str,
     email: &str,
+    email_normalized: &str,
     telephone: Option<&str>,
 ) -> Result<UserInfo, UserInsertError> {
     let id = Uuid::new_v4();
     let user = sqlx::query_as::<_, UserInfo>(
         r#"
-        INSERT INTO users (id, username, hashed_password, email, telephone)
-        VALUES ($1, $2, $3, $4, $5)
-        RETURNING id, username, hashed_password, email, telephone
+        INSERT INTO users (id, username, username_normalized, hashed_password, email, email_normalized, telephone)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        RETURNING id, username, hashed_password, email, email_verified, telephone, roles, scopes
         "#,
     )
     .bind(id.to_string())
     .bind(username)
+    .bind(username_normalized)
     .bind(hashed_password)
     .bind(email)
+    .bind(email_normalized)
     .bind(telephone)
     .fetch_one(pool)
     .await?;
@@ -66,31 +82,1165 @@ pub async fn delete_user(pool: &PgPool, username: &str) -> Result<bool, sqlx::Er
     Ok(result.rows_affected() > 0)
 }
 
-pub async fn get_user_by_username(
+pub async fn get_user_by_identifier(
     pool: &PgPool,
-    username: &str,
+    identifier: &Identifier,
 ) -> Result<Option<AuthInfo>, sqlx::Error> {
+    let query = match identifier {
+        Identifier::Username(_) => {
+            r#"
+            SELECT id, username, hashed_password, roles, scopes, totp_enabled
+            FROM users
+            WHERE username_normalized = $1
+            "#
+        }
+        Identifier::Email(_) => {
+            r#"
+            SELECT id, username, hashed_password, roles, scopes, totp_enabled
+            FROM users
+            WHERE email_normalized = $1
+            "#
+        }
+    };
+    let result = sqlx::query_as::<_, AuthInfo>(query)
+        .bind(identifier.as_str())
+        .fetch_optional(pool)
+        .await?;
+
+    Ok(result)
+}
+
+pub async fn get_user_by_id(pool: &PgPool, id: &str) -> Result<Option<AuthInfo>, sqlx::Error> {
     let result = sqlx::query_as::<_, AuthInfo>(
         r#"
-        SELECT id, username, hashed_password
+        SELECT id, username, hashed_password, roles, scopes, totp_enabled
         FROM users
-        WHERE username = $1
+        WHERE id = $1
         "#,
     )
-    .bind(username)
+    .bind(id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(result)
+}
+
+pub async fn get_user_info(pool: &PgPool, id: &str) -> Result<Option<UserInfo>, sqlx::Error> {
+    let result = sqlx::query_as::<_, UserInfo>(
+        r#"
+        SELECT id, username, hashed_password, email, email_verified, telephone, roles, scopes
+        FROM users
+        WHERE id = $1
+        "#,
+    )
+    .bind(id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(result)
+}
+
+/// By the normalized email
+pub async fn get_user_by_email(
+    pool: &PgPool,
+    email: &str,
+) -> Result<Option<UserInfo>, sqlx::Error> {
+    let result = sqlx::query_as::<_, UserInfo>(
+        r#"
+        SELECT id, username, hashed_password, email, email_verified, telephone, roles, scopes
+        FROM users
+        WHERE email_normalized = $1
+        "#,
+    )
+    .bind(email)
     .fetch_optional(pool)
     .await?;
 
     Ok(result)
 }
 
+pub async fn set_email_verified(pool: &PgPool, user_id: &str) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        UPDATE users
+        SET email_verified = TRUE
+        WHERE id = $1
+        "#,
+    )
+    .bind(user_id)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
 pub async fn verify_user_credentials(
     pool: &PgPool,
-    username: &str,
+    identifier: &Identifier,
     password: &str,
 ) -> Option<AuthInfo> {
-    match get_user_by_username(pool, username).await {
-        Ok(Some(user)) if verify_password(password, &user.hashed_password) => Some(user),
-        _ => None,
+    match get_user_by_identifier(pool, identifier).await {
+        Ok(Some(user)) if verify_password(password, &user.hashed_password).await => Some(user),
+        Ok(Some(_)) => None,
+        _ => {
+            verify_dummy_password(password).await;
+            None
+        }
     }
 }
+
+pub async fn insert_refresh_token(
+    executor: impl PgExecutor<'_>,
+    user_id: &str,
+    family_id: &str,
+    token_hash: &str,
+    expires_at: DateTime<Utc>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO refresh_tokens (id, user_id, family_id, token_hash, expires_at)
+        VALUES ($1, $2, $3, $4, $5)
+        "#,
+    )
+    .bind(Uuid::new_v4().to_string())
+    .bind(user_id)
+    .bind(family_id)
+    .bind(token_hash)
+    .bind(expires_at)
+    .execute(executor)
+    .await?;
+
+    Ok(())
+}
+
+pub enum RefreshOutcome {
+    /// The token was exchanged for `new_hash`, in the family of its session
+    Rotated {
+        user: AuthInfo,
+        session_id: String,
+        /// Granted to the app the session belongs to, if any
+        scopes: Option<Vec<String>>,
+    },
+    /// The token had already been exchanged, so it leaked: its session is revoked
+    Reused,
+    Invalid,
+}
+
+/// Exchanges the token of a session of `client_id`, or of a first-party one if `None`.
+/// A reused token revokes its session like `revoke_session`, until `tokens_expire_at`.
+pub async fn rotate_refresh_token(
+    pool: &PgPool,
+    token_hash: &str,
+    new_hash: &str,
+    expires_at: DateTime<Utc>,
+    client_id: Option<&str>,
+    tokens_expire_at: DateTime<Utc>,
+) -> Result<RefreshOutcome, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    // Locked, so the same token can't be exchanged twice concurrently
+    let token = sqlx::query_as::<_, RefreshToken>(
+        r#"
+        SELECT id, user_id, family_id, expires_at, used_at, revoked
+        FROM refresh_tokens
+        WHERE token_hash = $1
+        FOR UPDATE
+        "#,
+    )
+    .bind(token_hash)
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    let Some(token) = token else {
+        return Ok(RefreshOutcome::Invalid);
+    };
+
+    // Tokens of an app are only good for that app, and first-party ones for no app
+    let session = sqlx::query_as::<_, (Option<String>, Option<Vec<String>>)>(
+        r#"
+        SELECT client_id, scopes
+        FROM sessions
+        WHERE id = $1
+        "#,
+    )
+    .bind(&token.family_id)
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    let Some((session_client_id, scopes)) = session else {
+        return Ok(RefreshOutcome::Invalid);
+    };
+    if session_client_id.as_deref() != client_id {
+        return Ok(RefreshOutcome::Invalid);
+    }
+
+    if token.used_at.is_some() {
+        sqlx::query(
+            r#"
+            UPDATE sessions
+            SET revoked_at = COALESCE(revoked_at, NOW())
+            WHERE id = $1
+            "#,
+        )
+        .bind(&token.family_id)
+        .execute(&mut *tx)
+        .await?;
+
+        let family = [token.family_id];
+        revoke_session_tokens(&mut tx, &token.user_id, &family, tokens_expire_at).await?;
+        tx.commit().await?;
+        return Ok(RefreshOutcome::Reused);
+    }
+
+    // Revoked along with its session
+    if token.revoked || token.expires_at <= Utc::now() {
+        return Ok(RefreshOutcome::Invalid);
+    }
+
+    sqlx::query(
+        r#"
+        UPDATE refresh_tokens
+        SET used_at = NOW()
+        WHERE id = $1
+        "#,
+    )
+    .bind(&token.id)
+    .execute(&mut *tx)
+    .await?;
+
+    insert_refresh_token(
+        &mut *tx,
+        &token.user_id,
+        &token.family_id,
+        new_hash,
+        expires_at,
+    )
+    .await?;
+
+    let user = sqlx::query_as::<_, AuthInfo>(
+        r#"
+        SELECT id, username, hashed_password, roles, scopes, totp_enabled
+        FROM users
+        WHERE id = $1
+        "#,
+    )
+    .bind(&token.user_id)
+    .fetch_one(&mut *tx)
+    .await?;
+
+    sqlx::query(
+        r#"
+        UPDATE sessions
+        SET last_used_at = NOW()
+        WHERE id = $1
+        "#,
+    )
+    .bind(&token.family_id)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(RefreshOutcome::Rotated {
+        user,
+        session_id: token.family_id,
+        scopes,
+    })
+}
+
+pub async fn revoke_token(
+    pool: &PgPool,
+    jti: &str,
+    user_id: &str,
+    expires_at: DateTime<Utc>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO revoked_tokens (jti, user_id, expires_at)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (jti) DO NOTHING
+        "#,
+    )
+    .bind(jti)
+    .bind(user_id)
+    .bind(expires_at)
+    .execute(pool)
+    .await?;
+
+    // Expired tokens are rejected anyway, no need to keep them around
+    sqlx::query(
+        r#"
+        DELETE FROM revoked_tokens
+        WHERE expires_at < NOW()
+        "#,
+    )
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Tokens revoked by the `limit` transactions after `since`, in the order they began
+pub async fn get_revoked_tokens_since(
+    pool: &PgPool,
+    since: i64,
+    limit: i64,
+) -> Result<Vec<RevokedToken>, sqlx::Error> {
+    sqlx::query_as::<_, RevokedToken>(
+        r#"
+        SELECT
+            jti,
+            EXTRACT(EPOCH FROM expires_at)::BIGINT AS expires_at,
+            xact_id::TEXT::BIGINT AS xact_id,
+            pg_snapshot_xmin(pg_current_snapshot())::TEXT::BIGINT AS in_progress_from
+        FROM revoked_tokens
+        WHERE expires_at > NOW() AND xact_id IN (
+            SELECT DISTINCT xact_id
+            FROM revoked_tokens
+            WHERE xact_id > $1::TEXT::XID8 AND expires_at > NOW()
+            ORDER BY xact_id
+            LIMIT $2
+        )
+        ORDER BY xact_id, id
+        "#,
+    )
+    .bind(since.max(0))
+    .bind(limit)
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn insert_session(
+    pool: &PgPool,
+    id: &str,
+    user_id: &str,
+    device: Option<&str>,
+    ip: Option<&str>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO sessions (id, user_id, device, ip)
+        VALUES ($1, $2, $3, $4)
+        "#,
+    )
+    .bind(id)
+    .bind(user_id)
+    .bind(device)
+    .bind(ip)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn get_active_sessions(
+    pool: &PgPool,
+    user_id: &str,
+) -> Result<Vec<Session>, sqlx::Error> {
+    sqlx::query_as::<_, Session>(
+        r#"
+        SELECT id, device, ip, client_id, created_at, last_used_at
+        FROM sessions
+        WHERE user_id = $1 AND revoked_at IS NULL
+        ORDER BY last_used_at DESC
+        "#,
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn is_session_active(pool: &PgPool, id: &str) -> Result<bool, sqlx::Error> {
+    let active = sqlx::query_scalar::<_, bool>(
+        r#"
+        SELECT EXISTS (SELECT 1 FROM sessions WHERE id = $1 AND revoked_at IS NULL)
+        "#,
+    )
+    .bind(id)
+    .fetch_one(pool)
+    .await?;
+
+    Ok(active)
+}
+
+/// Revokes the session, if it belongs to the user. Its access tokens are
+/// rejected until `tokens_expire_at`, by the time all of them have expired.
+pub async fn revoke_session(
+    pool: &PgPool,
+    user_id: &str,
+    session_id: &str,
+    tokens_expire_at: DateTime<Utc>,
+) -> Result<bool, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let revoked = sqlx::query_scalar::<_, String>(
+        r#"
+        UPDATE sessions
+        SET revoked_at = NOW()
+        WHERE id = $1 AND user_id = $2 AND revoked_at IS NULL
+        RETURNING id
+        "#,
+    )
+    .bind(session_id)
+    .bind(user_id)
+    .fetch_all(&mut *tx)
+    .await?;
+
+    revoke_session_tokens(&mut tx, user_id, &revoked, tokens_expire_at).await?;
+    tx.commit().await?;
+
+    Ok(!revoked.is_empty())
+}
+
+/// Revokes every session of the user but `current_session_id`, returning how many.
+pub async fn revoke_other_sessions(
+    pool: &PgPool,
+    user_id: &str,
+    current_session_id: &str,
+    tokens_expire_at: DateTime<Utc>,
+) -> Result<usize, sqlx::Error> {
+    revoke_sessions_except(pool, user_id, Some(current_session_id), tokens_expire_at).await
+}
+
+/// Revokes every session of the user, returning how many.
+pub async fn revoke_all_sessions(
+    pool: &PgPool,
+    user_id: &str,
+    tokens_expire_at: DateTime<Utc>,
+) -> Result<usize, sqlx::Error> {
+    revoke_sessions_except(pool, user_id, None, tokens_expire_at).await
+}
+
+async fn revoke_sessions_except(
+    pool: &PgPool,
+    user_id: &str,
+    kept_session_id: Option<&str>,
+    tokens_expire_at: DateTime<Utc>,
+) -> Result<usize, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let revoked = sqlx::query_scalar::<_, String>(
+        r#"
+        UPDATE sessions
+        SET revoked_at = NOW()
+        WHERE user_id = $1 AND ($2::TEXT IS NULL OR id <> $2) AND revoked_at IS NULL
+        RETURNING id
+        "#,
+    )
+    .bind(user_id)
+    .bind(kept_session_id)
+    .fetch_all(&mut *tx)
+    .await?;
+
+    revoke_session_tokens(&mut tx, user_id, &revoked, tokens_expire_at).await?;
+    tx.commit().await?;
+
+    Ok(revoked.len())
+}
+
+async fn revoke_session_tokens(
+    conn: &mut PgConnection,
+    user_id: &str,
+    session_ids: &[String],
+    tokens_expire_at: DateTime<Utc>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        UPDATE refresh_tokens
+        SET revoked = TRUE
+        WHERE family_id = ANY($1)
+        "#,
+    )
+    .bind(session_ids)
+    .execute(&mut *conn)
+    .await?;
+
+    // Access tokens carry their session as `sid`, consumers reject it like a `jti`
+    sqlx::query(
+        r#"
+        INSERT INTO revoked_tokens (jti, user_id, expires_at)
+        SELECT id, $2, $3 FROM UNNEST($1::TEXT[]) AS id
+        ON CONFLICT (jti) DO NOTHING
+        "#,
+    )
+    .bind(session_ids)
+    .bind(user_id)
+    .bind(tokens_expire_at)
+    .execute(&mut *conn)
+    .await?;
+
+    Ok(())
+}
+
+/// Stores a new token for `purpose`, invalidating the ones mailed before it
+pub async fn insert_email_token(
+    pool: &PgPool,
+    user_id: &str,
+    purpose: EmailTokenPurpose,
+    token_hash: &str,
+    expires_at: DateTime<Utc>,
+) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query(
+        r#"
+        DELETE FROM email_tokens
+        WHERE user_id = $1 AND purpose = $2 AND used_at IS NULL
+        "#,
+    )
+    .bind(user_id)
+    .bind(purpose.as_str())
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO email_tokens (token_hash, user_id, purpose, expires_at)
+        VALUES ($1, $2, $3, $4)
+        "#,
+    )
+    .bind(token_hash)
+    .bind(user_id)
+    .bind(purpose.as_str())
+    .bind(expires_at)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await
+}
+
+/// Marks the token as used, returning its user, unless it was already used or expired
+pub async fn consume_email_token(
+    pool: &PgPool,
+    purpose: EmailTokenPurpose,
+    token_hash: &str,
+) -> Result<Option<String>, sqlx::Error> {
+    sqlx::query_scalar::<_, String>(
+        r#"
+        UPDATE email_tokens
+        SET used_at = NOW()
+        WHERE token_hash = $1 AND purpose = $2 AND used_at IS NULL AND expires_at > NOW()
+        RETURNING user_id
+        "#,
+    )
+    .bind(token_hash)
+    .bind(purpose.as_str())
+    .fetch_optional(pool)
+    .await
+}
+
+pub async fn get_totp_secret(pool: &PgPool, user_id: &str) -> Result<Option<String>, sqlx::Error> {
+    let secret = sqlx::query_scalar::<_, Option<String>>(
+        r#"
+        SELECT totp_secret
+        FROM users
+        WHERE id = $1
+        "#,
+    )
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await?;
+
+    Ok(secret.flatten())
+}
+
+/// Stores the secret until a first code confirms it, unless 2FA is already enabled
+pub async fn set_pending_totp_secret(
+    pool: &PgPool,
+    user_id: &str,
+    secret: &str,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        r#"
+        UPDATE users
+        SET totp_secret = $2, totp_last_step = NULL
+        WHERE id = $1 AND NOT totp_enabled
+        "#,
+    )
+    .bind(user_id)
+    .bind(secret)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Enables 2FA, replacing any recovery codes left from a previous enrollment
+pub async fn enable_totp(
+    pool: &PgPool,
+    user_id: &str,
+    recovery_code_hashes: &[String],
+) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query(
+        r#"
+        UPDATE users
+        SET totp_enabled = TRUE
+        WHERE id = $1
+        "#,
+    )
+    .bind(user_id)
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query("DELETE FROM recovery_codes WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+
+    sqlx::query(
+        r#"
+        INSERT INTO recovery_codes (code_hash, user_id)
+        SELECT code_hash, $2 FROM UNNEST($1::TEXT[]) AS code_hash
+        "#,
+    )
+    .bind(recovery_code_hashes)
+    .bind(user_id)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await
+}
+
+pub async fn disable_totp(pool: &PgPool, user_id: &str) -> Result<(), sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query(
+        r#"
+        UPDATE users
+        SET totp_enabled = FALSE, totp_secret = NULL, totp_last_step = NULL
+        WHERE id = $1
+        "#,
+    )
+    .bind(user_id)
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query("DELETE FROM recovery_codes WHERE user_id = $1")
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await
+}
+
+/// Records the time step of an accepted code, unless it or a later one was already used
+pub async fn use_totp_step(pool: &PgPool, user_id: &str, step: i64) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        r#"
+        UPDATE users
+        SET totp_last_step = $2
+        WHERE id = $1 AND (totp_last_step IS NULL OR totp_last_step < $2)
+        "#,
+    )
+    .bind(user_id)
+    .bind(step)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn use_recovery_code(
+    pool: &PgPool,
+    user_id: &str,
+    code_hash: &str,
+) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query(
+        r#"
+        UPDATE recovery_codes
+        SET used_at = NOW()
+        WHERE code_hash = $1 AND user_id = $2 AND used_at IS NULL
+        "#,
+    )
+    .bind(code_hash)
+    .bind(user_id)
+    .execute(pool)
+    .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn insert_login_challenge(
+    pool: &PgPool,
+    user_id: &str,
+    identifier: &Identifier,
+    token_hash: &str,
+    expires_at: DateTime<Utc>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO login_challenges (token_hash, user_id, identifier, expires_at)
+        VALUES ($1, $2, $3, $4)
+        "#,
+    )
+    .bind(token_hash)
+    .bind(user_id)
+    .bind(identifier.as_str())
+    .bind(expires_at)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+/// Counts an attempt on the challenge, returning its user and the identifier
+/// they signed in with while it has attempts left
+pub async fn attempt_login_challenge(
+    pool: &PgPool,
+    token_hash: &str,
+    max_attempts: i32,
+) -> Result<Option<(String, String)>, sqlx::Error> {
+    // Those issued before identifiers were kept have to sign in again
+    sqlx::query_as::<_, (String, String)>(
+        r#"
+        UPDATE login_challenges
+        SET attempts = attempts + 1
+        WHERE token_hash = $1 AND expires_at > NOW() AND attempts < $2
+            AND identifier IS NOT NULL
+        RETURNING user_id, identifier
+        "#,
+    )
+    .bind(token_hash)
+    .bind(max_attempts)
+    .fetch_optional(pool)
+    .await
+}
+
+pub async fn delete_login_challenge(pool: &PgPool, token_hash: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM login_challenges WHERE token_hash = $1 OR expires_at <= NOW()")
+        .bind(token_hash)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+/// When the next login attempt is allowed for any of the keys, if it's later than now
+pub async fn get_login_blocked_until(
+    pool: &PgPool,
+    keys: &[String],
+) -> Result<Option<DateTime<Utc>>, sqlx::Error> {
+    sqlx::query_scalar::<_, Option<DateTime<Utc>>>(
+        r#"
+        SELECT MAX(blocked_until)
+        FROM login_failures
+        WHERE key = ANY($1) AND blocked_until > NOW()
+        "#,
+    )
+    .bind(keys)
+    .fetch_one(pool)
+    .await
+}
+
+/// Counts a failed login, returning the failures within `window` of each other
+pub async fn record_login_failure(
+    pool: &PgPool,
+    key: &str,
+    window: Duration,
+) -> Result<i32, sqlx::Error> {
+    sqlx::query_scalar::<_, i32>(
+        r#"
+        INSERT INTO login_failures (key, failures, last_failure_at)
+        VALUES ($1, 1, NOW())
+        ON CONFLICT (key) DO UPDATE
+        SET failures = CASE
+                WHEN login_failures.last_failure_at < NOW() - $2 THEN 1
+                ELSE login_failures.failures + 1
+            END,
+            last_failure_at = NOW()
+        RETURNING failures
+        "#,
+    )
+    .bind(key)
+    .bind(window)
+    .fetch_one(pool)
+    .await
+}
+
+pub async fn block_login(
+    pool: &PgPool,
+    key: &str,
+    blocked_until: DateTime<Utc>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        UPDATE login_failures
+        SET blocked_until = $2
+        WHERE key = $1
+        "#,
+    )
+    .bind(key)
+    .bind(blocked_until)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn clear_login_failures(pool: &PgPool, key: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("DELETE FROM login_failures WHERE key = $1")
+        .bind(key)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+pub async fn insert_oauth_client(
+    pool: &PgPool,
+    owner_id: &str,
+    name: &str,
+    secret_hash: Option<&str>,
+    redirect_uris: &[String],
+    scopes: &[String],
+) -> Result<OAuthClient, sqlx::Error> {
+    sqlx::query_as::<_, OAuthClient>(
+        r#"
+        INSERT INTO oauth_clients (id, owner_id, name, secret_hash, redirect_uris, scopes)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        RETURNING id, owner_id, name, secret_hash, redirect_uris, scopes, created_at
+        "#,
+    )
+    .bind(Uuid::new_v4().to_string())
+    .bind(owner_id)
+    .bind(name)
+    .bind(secret_hash)
+    .bind(redirect_uris)
+    .bind(scopes)
+    .fetch_one(pool)
+    .await
+}
+
+pub async fn get_oauth_client(pool: &PgPool, id: &str) -> Result<Option<OAuthClient>, sqlx::Error> {
+    sqlx::query_as::<_, OAuthClient>(
+        r#"
+        SELECT id, owner_id, name, secret_hash, redirect_uris, scopes, created_at
+        FROM oauth_clients
+        WHERE id = $1
+        "#,
+    )
+    .bind(id)
+    .fetch_optional(pool)
+    .await
+}
+
+pub async fn get_oauth_clients_of(
+    pool: &PgPool,
+    owner_id: &str,
+) -> Result<Vec<OAuthClient>, sqlx::Error> {
+    sqlx::query_as::<_, OAuthClient>(
+        r#"
+        SELECT id, owner_id, name, secret_hash, redirect_uris, scopes, created_at
+        FROM oauth_clients
+        WHERE owner_id = $1
+        ORDER BY created_at
+        "#,
+    )
+    .bind(owner_id)
+    .fetch_all(pool)
+    .await
+}
+
+/// Deletes the client, if it belongs to the owner, along with the sessions it
+/// was authorized in. Tokens already issued are rejected until `tokens_expire_at`.
+pub async fn delete_oauth_client(
+    pool: &PgPool,
+    owner_id: &str,
+    id: &str,
+    tokens_expire_at: DateTime<Utc>,
+) -> Result<bool, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let owned = sqlx::query_scalar::<_, String>(
+        r#"
+        SELECT id
+        FROM oauth_clients
+        WHERE id = $1 AND owner_id = $2
+        FOR UPDATE
+        "#,
+    )
+    .bind(id)
+    .bind(owner_id)
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    if owned.is_none() {
+        return Ok(false);
+    }
+
+    // Before the sessions are deleted with the client, while it's known whose they are
+    let revoked = sqlx::query_as::<_, (String, String)>(
+        r#"
+        UPDATE sessions
+        SET revoked_at = NOW()
+        WHERE client_id = $1 AND revoked_at IS NULL
+        RETURNING id, user_id
+        "#,
+    )
+    .bind(id)
+    .fetch_all(&mut *tx)
+    .await?;
+
+    let (mut session_ids, mut user_ids): (Vec<String>, Vec<String>) = revoked.into_iter().unzip();
+
+    sqlx::query(
+        r#"
+        UPDATE refresh_tokens
+        SET revoked = TRUE
+        WHERE family_id = ANY($1)
+        "#,
+    )
+    .bind(&session_ids)
+    .execute(&mut *tx)
+    .await?;
+
+    // Tokens of the bot itself have the client as their session
+    session_ids.push(id.to_owned());
+    user_ids.push(owner_id.to_owned());
+
+    sqlx::query(
+        r#"
+        INSERT INTO revoked_tokens (jti, user_id, expires_at)
+        SELECT id, user_id, $3 FROM UNNEST($1::TEXT[], $2::TEXT[]) AS revoked(id, user_id)
+        ON CONFLICT (jti) DO NOTHING
+        "#,
+    )
+    .bind(&session_ids)
+    .bind(&user_ids)
+    .bind(tokens_expire_at)
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query("DELETE FROM oauth_clients WHERE id = $1")
+        .bind(id)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+
+    Ok(true)
+}
+
+/// Adds `scopes` to those the user already let the client have
+pub async fn grant_consent(
+    pool: &PgPool,
+    user_id: &str,
+    client_id: &str,
+    scopes: &[String],
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO oauth_consents (user_id, client_id, scopes)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (user_id, client_id) DO UPDATE
+        SET scopes = ARRAY(SELECT DISTINCT UNNEST(oauth_consents.scopes || EXCLUDED.scopes)),
+            granted_at = NOW()
+        "#,
+    )
+    .bind(user_id)
+    .bind(client_id)
+    .bind(scopes)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn get_consented_scopes(
+    pool: &PgPool,
+    user_id: &str,
+    client_id: &str,
+) -> Result<Option<Vec<String>>, sqlx::Error> {
+    sqlx::query_scalar::<_, Vec<String>>(
+        r#"
+        SELECT scopes
+        FROM oauth_consents
+        WHERE user_id = $1 AND client_id = $2
+        "#,
+    )
+    .bind(user_id)
+    .bind(client_id)
+    .fetch_optional(pool)
+    .await
+}
+
+pub async fn get_consents(pool: &PgPool, user_id: &str) -> Result<Vec<OAuthConsent>, sqlx::Error> {
+    sqlx::query_as::<_, OAuthConsent>(
+        r#"
+        SELECT c.client_id, o.name AS client_name, c.scopes, c.granted_at
+        FROM oauth_consents c
+        JOIN oauth_clients o ON o.id = c.client_id
+        WHERE c.user_id = $1
+        ORDER BY c.granted_at DESC
+        "#,
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await
+}
+
+/// Forgets the consent and signs the client out of every session of the user
+pub async fn revoke_consent(
+    pool: &PgPool,
+    user_id: &str,
+    client_id: &str,
+    tokens_expire_at: DateTime<Utc>,
+) -> Result<bool, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let deleted = sqlx::query(
+        r#"
+        DELETE FROM oauth_consents
+        WHERE user_id = $1 AND client_id = $2
+        "#,
+    )
+    .bind(user_id)
+    .bind(client_id)
+    .execute(&mut *tx)
+    .await?;
+
+    let revoked = sqlx::query_scalar::<_, String>(
+        r#"
+        UPDATE sessions
+        SET revoked_at = NOW()
+        WHERE user_id = $1 AND client_id = $2 AND revoked_at IS NULL
+        RETURNING id
+        "#,
+    )
+    .bind(user_id)
+    .bind(client_id)
+    .fetch_all(&mut *tx)
+    .await?;
+
+    revoke_session_tokens(&mut tx, user_id, &revoked, tokens_expire_at).await?;
+    tx.commit().await?;
+
+    Ok(deleted.rows_affected() > 0)
+}
+
+/// Records a session of an app, authorized by the user for `scopes`
+pub async fn insert_oauth_session(
+    pool: &PgPool,
+    id: &str,
+    user_id: &str,
+    client: &OAuthClient,
+    ip: Option<&str>,
+    scopes: &[String],
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO sessions (id, user_id, device, ip, client_id, scopes)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        "#,
+    )
+    .bind(id)
+    .bind(user_id)
+    .bind(&client.name)
+    .bind(ip)
+    .bind(&client.id)
+    .bind(scopes)
+    .execute(pool)
+    .await?;
+
+    Ok(())
+}
+
+pub async fn insert_authorization_code(
+    pool: &PgPool,
+    code_hash: &str,
+    code: &AuthorizationCode,
+    expires_at: DateTime<Utc>,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        r#"
+        INSERT INTO oauth_codes (code_hash, client_id, user_id, redirect_uri, scopes, code_challenge, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7)
+        "#,
+    )
+    .bind(code_hash)
+    .bind(&code.client_id)
+    .bind(&code.user_id)
+    .bind(&code.redirect_uri)
+    .bind(&code.scopes)
+    .bind(&code.code_challenge)
+    .bind(expires_at)
+    .execute(pool)
+    .await?;
+
+    // Expired codes can't be exchanged anymore, nor tell a replay apart
+    sqlx::query("DELETE FROM oauth_codes WHERE expires_at < NOW() - INTERVAL '1 day'")
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
+
+pub enum CodeOutcome {
+    Valid(AuthorizationCode),
+    /// Exchanged before, so it leaked: the session it got is to be revoked
+    Reused {
+        user_id: String,
+        session_id: Option<String>,
+    },
+    Invalid,
+}
+
+/// Marks the code as used, it's only valid once
+pub async fn consume_authorization_code(
+    pool: &PgPool,
+    code_hash: &str,
+) -> Result<CodeOutcome, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+
+    let stored = sqlx::query_as::<_, StoredAuthorizationCode>(
+        r#"
+        SELECT client_id, user_id, redirect_uri, scopes, code_challenge, expires_at, used_at, session_id
+        FROM oauth_codes
+        WHERE code_hash = $1
+        FOR UPDATE
+        "#,
+    )
+    .bind(code_hash)
+    .fetch_optional(&mut *tx)
+    .await?;
+
+    let Some(stored) = stored else {
+        return Ok(CodeOutcome::Invalid);
+    };
+
+    if stored.used_at.is_some() {
+        return Ok(CodeOutcome::Reused {
+            user_id: stored.code.user_id,
+            session_id: stored.session_id,
+        });
+    }
+    if stored.expires_at <= Utc::now() {
+        return Ok(CodeOutcome::Invalid);
+    }
+
+    sqlx::query("UPDATE oauth_codes SET used_at = NOW() WHERE code_hash = $1")
+        .bind(code_hash)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+
+    Ok(CodeOutcome::Valid(stored.code))
+}
+
+/// Remembers what the code was exchanged for, to revoke it if the code is replayed
+pub async fn set_authorization_code_session(
+    pool: &PgPool,
+    code_hash: &str,
+    session_id: &str,
+) -> Result<(), sqlx::Error> {
+    sqlx::query("UPDATE oauth_codes SET session_id = $2 WHERE code_hash = $1")
+        .bind(code_hash)
+        .bind(session_id)
+        .execute(pool)
+        .await?;
+
+    Ok(())
+}
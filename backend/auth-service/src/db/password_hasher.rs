@@ -1,27 +1,127 @@
+use std::env::var;
+use std::sync::LazyLock;
+
+use anyhow::{Context, anyhow};
 use argon2::{
-    Argon2,
+    Algorithm, Argon2, Params, Version,
     password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString, rand_core::OsRng},
 };
 
-pub async fn hash_password(password: &str) -> Result<String, argon2::password_hash::Error> {
-    //Salt is the way the random part is done
-    let salt = SaltString::generate(&mut OsRng);
+pub static ARGON2_SETTINGS: LazyLock<Argon2Settings> =
+    LazyLock::new(|| Argon2Settings::from_env().expect("Invalid Argon2 settings"));
+
+/// How new passwords are hashed. Existing hashes keep the parameters they were
+/// made with, which are stored in them, and are upgraded on the next login.
+#[derive(Debug, Clone)]
+pub struct Argon2Settings {
+    algorithm: Algorithm,
+    params: Params,
+}
+
+impl Argon2Settings {
+    pub fn new(algorithm: Algorithm, params: Params) -> Self {
+        Self { algorithm, params }
+    }
+
+    /// `ARGON2_ALGORITHM` (argon2id, argon2i or argon2d), `ARGON2_MEMORY_KIB`,
+    /// `ARGON2_ITERATIONS` and `ARGON2_PARALLELISM`, each defaulting to the
+    /// argon2 crate's recommendation
+    fn from_env() -> anyhow::Result<Self> {
+        let algorithm = match var("ARGON2_ALGORITHM") {
+            Ok(algorithm) => Algorithm::new(&algorithm)
+                .map_err(|_| anyhow!("Unknown ARGON2_ALGORITHM `{algorithm}`"))?,
+            Err(_) => Algorithm::default(),
+        };
+        let cost = |name: &str, default: u32| -> anyhow::Result<u32> {
+            match var(name) {
+                Ok(value) => value
+                    .parse()
+                    .with_context(|| format!("{name} must be a number")),
+                Err(_) => Ok(default),
+            }
+        };
+        let params = Params::new(
+            cost("ARGON2_MEMORY_KIB", Params::DEFAULT_M_COST)?,
+            cost("ARGON2_ITERATIONS", Params::DEFAULT_T_COST)?,
+            cost("ARGON2_PARALLELISM", Params::DEFAULT_P_COST)?,
+            None,
+        )
+        .map_err(|e| anyhow!("Invalid Argon2 parameters: {e}"))?;
+
+        Ok(Self::new(algorithm, params))
+    }
+
+    fn argon2(&self) -> Argon2<'_> {
+        Argon2::new(self.algorithm, Version::V0x13, self.params.clone())
+    }
+
+    pub fn hash(&self, password: &str) -> Result<String, argon2::password_hash::Error> {
+        //Salt is the way the random part is done
+        let salt = SaltString::generate(&mut OsRng);
+        let password_hash = self.argon2().hash_password(password.as_bytes(), &salt)?;
 
-    // If we want to change the way the password is hashed
-    let argon2 = Argon2::default();
+        Ok(password_hash.to_string())
+    }
+
+    /// Whether `hash` was made with these settings, unparseable ones can't be upgraded anyway
+    pub fn is_current(&self, hash: &str) -> bool {
+        let Ok(hash) = PasswordHash::new(hash) else {
+            return true;
+        };
+        let Ok(params) = Params::try_from(&hash) else {
+            return true;
+        };
+
+        hash.algorithm == self.algorithm.ident()
+            && hash.version == Some(Version::V0x13.into())
+            && params.m_cost() == self.params.m_cost()
+            && params.t_cost() == self.params.t_cost()
+            && params.p_cost() == self.params.p_cost()
+    }
+}
 
-    let password_hash = argon2.hash_password(password.as_bytes(), &salt)?;
+// Argon2 takes long enough on purpose to stall the other requests of the thread,
+// so it runs on the blocking pool
+pub async fn hash_password(password: &str) -> Result<String, argon2::password_hash::Error> {
+    let password = password.to_owned();
+    tokio::task::spawn_blocking(move || ARGON2_SETTINGS.hash(&password))
+        .await
+        .unwrap_or(Err(argon2::password_hash::Error::Crypto))
+}
 
-    Ok(password_hash.to_string())
+pub async fn verify_password(password: &str, hash: &str) -> bool {
+    let (password, hash) = (password.to_owned(), hash.to_owned());
+    tokio::task::spawn_blocking(move || verify(&password, &hash))
+        .await
+        .unwrap_or(false)
 }
 
 //When the String is Hashed the salt is store, so we have to take the hash to get that data
-pub fn verify_password(password: &str, hash: &str) -> bool {
+fn verify(password: &str, hash: &str) -> bool {
     let parsed_hash = PasswordHash::new(hash);
     if parsed_hash.is_err() {
         return false;
     }
+    // The algorithm and parameters are taken from the hash, not the settings
     Argon2::default()
         .verify_password(password.as_bytes(), &parsed_hash.unwrap())
         .is_ok()
 }
+
+/// Whether a verified password should be hashed again with the current settings
+pub fn needs_rehash(hash: &str) -> bool {
+    !ARGON2_SETTINGS.is_current(hash)
+}
+
+// Checking a password against it takes as long as against a real hash, so
+// unknown usernames can't be told apart by how long the answer takes
+static DUMMY_HASH: LazyLock<String> = LazyLock::new(|| {
+    ARGON2_SETTINGS
+        .hash("not anyone's password")
+        .expect("Failed to hash the dummy password")
+});
+
+pub async fn verify_dummy_password(password: &str) {
+    let password = password.to_owned();
+    let _ = tokio::task::spawn_blocking(move || verify(&password, &DUMMY_HASH)).await;
+}
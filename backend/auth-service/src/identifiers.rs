@@ -0,0 +1,154 @@
+use axum::Json;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use serde_json::json;
+use thiserror::Error;
+use unicode_normalization::UnicodeNormalization;
+
+/// Usernames may also have these besides letters and digits
+const USERNAME_PUNCTUATION: &[char] = &['_', '.', '-'];
+
+/// Lowercase Cyrillic and Greek letters drawn like Latin ones. A name made
+/// only of them reads as a Latin name it isn't.
+const LATIN_LOOKALIKES: &[char] = &[
+    'а', 'в', 'е', 'з', 'і', 'ј', 'к', 'м', 'н', 'о', 'р', 'с', 'т', 'у', 'х', 'ѕ', 'һ', 'ԁ', 'ԛ',
+    'ԝ', 'ӏ', 'α', 'ε', 'ι', 'κ', 'ν', 'ο', 'ρ', 'τ', 'υ', 'χ', 'ϳ',
+];
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum IdentifierError {
+    #[error("Usernames may only have letters, digits, '_', '.' and '-'")]
+    InvalidUsername,
+    #[error("Invalid email address")]
+    InvalidEmail,
+    #[error("Mixes characters that look alike but aren't the same")]
+    Confusable,
+}
+
+impl IntoResponse for IdentifierError {
+    fn into_response(self) -> Response {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "message": self.to_string() })),
+        )
+            .into_response()
+    }
+}
+
+/// What a user signs in with, normalized as it's stored for comparisons
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Identifier {
+    Username(String),
+    Email(String),
+}
+
+impl Identifier {
+    /// Emails are told apart by their `@`, which usernames can't have. Only
+    /// folded, the rules for new usernames and emails didn't always hold and
+    /// accounts from before them must still be able to sign in.
+    pub fn parse(identifier: &str) -> Result<Self, IdentifierError> {
+        let identifier = fold(identifier);
+        if identifier.contains('@') {
+            Ok(Identifier::Email(identifier))
+        } else if identifier.is_empty() {
+            Err(IdentifierError::InvalidUsername)
+        } else {
+            Ok(Identifier::Username(identifier))
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            Identifier::Username(username) => username,
+            Identifier::Email(email) => email,
+        }
+    }
+}
+
+/// The form users are shown, which only loses compatibility variants
+/// (e.g. fullwidth letters) and surrounding spaces
+pub fn display_form(value: &str) -> String {
+    value.trim().nfkc().collect()
+}
+
+/// NFKC, then case folded, so every way of writing a name compares equal
+pub fn fold(value: &str) -> String {
+    // Lowercasing can leave text unnormalized, hence the second pass
+    display_form(value).to_lowercase().nfkc().collect()
+}
+
+pub fn normalize_username(username: &str) -> Result<String, IdentifierError> {
+    let username = fold(username);
+    let valid = !username.is_empty()
+        && username
+            .chars()
+            .all(|c| c.is_alphanumeric() || USERNAME_PUNCTUATION.contains(&c));
+    if !valid {
+        return Err(IdentifierError::InvalidUsername);
+    }
+    if is_confusable(&username) {
+        return Err(IdentifierError::Confusable);
+    }
+    Ok(username)
+}
+
+/// Only checked for the shape of an address, whether it exists is up to its verification
+pub fn normalize_email(email: &str) -> Result<String, IdentifierError> {
+    let email = fold(email);
+    let Some((local, domain)) = email.split_once('@') else {
+        return Err(IdentifierError::InvalidEmail);
+    };
+    let valid = !local.is_empty()
+        && domain.contains('.')
+        && !domain.starts_with('.')
+        && !domain.ends_with('.')
+        && !domain.contains('@')
+        && !email.chars().any(|c| c.is_whitespace() || c.is_control());
+    if !valid {
+        return Err(IdentifierError::InvalidEmail);
+    }
+    if is_confusable(local) || is_confusable(domain) {
+        return Err(IdentifierError::Confusable);
+    }
+    Ok(email)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Script {
+    Latin,
+    Greek,
+    Cyrillic,
+}
+
+/// Only the scripts with letters drawn like each other, others can be mixed freely
+fn script(c: char) -> Option<Script> {
+    if !c.is_alphabetic() {
+        return None;
+    }
+    match c {
+        'a'..='z' | 'A'..='Z' | '\u{00C0}'..='\u{024F}' | '\u{1E00}'..='\u{1EFF}' => {
+            Some(Script::Latin)
+        }
+        '\u{0370}'..='\u{03FF}' | '\u{1F00}'..='\u{1FFF}' => Some(Script::Greek),
+        '\u{0400}'..='\u{052F}' => Some(Script::Cyrillic),
+        _ => None,
+    }
+}
+
+/// Whether `name`, already folded, mixes Latin, Greek and Cyrillic letters, or
+/// is written in Greek or Cyrillic letters that could all pass for Latin ones
+pub fn is_confusable(name: &str) -> bool {
+    let mut scripts = name.chars().filter_map(script);
+    let Some(first) = scripts.next() else {
+        return false;
+    };
+    if scripts.any(|script| script != first) {
+        return true;
+    }
+
+    first != Script::Latin
+        && name
+            .chars()
+            .filter(|c| c.is_alphabetic())
+            .all(|c| LATIN_LOOKALIKES.contains(&c))
+}
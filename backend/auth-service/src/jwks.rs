@@ -0,0 +1,12 @@
+use axum::http::header;
+use axum::{Json, response::IntoResponse};
+
+use crate::jwt::KEYS;
+
+/// Public keys verifying our tokens, cached by the gateway and the other services.
+pub async fn get_jwks() -> impl IntoResponse {
+    (
+        [(header::CACHE_CONTROL, "public, max-age=300")],
+        Json(&KEYS.jwks),
+    )
+}
@@ -1,32 +1,177 @@
+use anyhow::{Context, anyhow};
+use axum::http::{HeaderMap, header};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
 use chrono::{Duration, Utc};
-use jsonwebtoken::{EncodingKey, Header, encode};
+use ed25519_dalek::{SigningKey, pkcs8::DecodePrivateKey};
+use jsonwebtoken::jwk::{
+    AlgorithmParameters, CommonParameters, EllipticCurve, Jwk, JwkSet, KeyAlgorithm,
+    OctetKeyPairParameters, OctetKeyPairType, PublicKeyUse,
+};
+use jsonwebtoken::{
+    Algorithm, DecodingKey, EncodingKey, Header, Validation, decode, decode_header, encode,
+};
 use serde::{Deserialize, Serialize};
-use std::env;
+use std::sync::LazyLock;
+use std::{env, fs};
+use uuid::Uuid;
+
+use crate::oauth::OFFERED_SCOPES;
+
+/// Short lived, clients get new ones with their refresh token
+pub(crate) const ACCESS_TOKEN_MINUTES: i64 = 15;
+
+pub(crate) static KEYS: LazyLock<Keys> =
+    LazyLock::new(|| Keys::load().expect("Could not load JWT signing keys"));
 
 #[derive(Debug, Serialize, Deserialize)]
 pub(crate) struct Claims {
-    // TODO(Sa4dUs): Add more claim fields if needed
-    exp: u64,
+    pub(crate) exp: u64,
+    /// Identifies the token, to revoke it on logout
+    pub(crate) jti: String,
+    /// Session the token was issued for, revoking the session revokes the token
+    pub(crate) sid: String,
     // Actual payload
+    pub(crate) user_id: String,
+    roles: Vec<String>,
+    /// Space separated, as in OAuth 2.0
+    scope: String,
+    /// The OAuth client the token was issued to, first-party tokens have none
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) client_id: Option<String>,
+}
+
+/// Ed25519 keys read from `JWT_KEYS_DIR`, where every `{kid}.pem` file is a
+/// PKCS#8 private key. Tokens are signed with the `JWT_SIGNING_KID` one, the
+/// others are still published so that their tokens stay valid while rotating.
+///
+/// A new key can be generated with `openssl genpkey -algorithm ed25519 -out keys/{kid}.pem`.
+pub(crate) struct Keys {
+    kid: String,
+    encoding: EncodingKey,
+    pub(crate) jwks: JwkSet,
+}
+
+impl Keys {
+    fn load() -> anyhow::Result<Self> {
+        let dir = env::var("JWT_KEYS_DIR").context("JWT_KEYS_DIR not defined in .env")?;
+        let kid = env::var("JWT_SIGNING_KID").context("JWT_SIGNING_KID not defined in .env")?;
+
+        let mut encoding = None;
+        let mut keys = Vec::new();
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            let Some(key_id) = name.strip_suffix(".pem") else {
+                continue;
+            };
+
+            let pem = fs::read_to_string(&path)?;
+            let signing_key = SigningKey::from_pkcs8_pem(&pem)
+                .map_err(|e| anyhow!("Invalid Ed25519 key {}: {e}", path.display()))?;
+            if key_id == kid {
+                encoding = Some(EncodingKey::from_ed_pem(pem.as_bytes())?);
+            }
+            keys.push(public_jwk(key_id, &signing_key));
+        }
+
+        let encoding = encoding.ok_or_else(|| anyhow!("No `{kid}.pem` key in {dir}"))?;
+        Ok(Keys {
+            kid,
+            encoding,
+            jwks: JwkSet { keys },
+        })
+    }
+}
+
+fn public_jwk(kid: &str, key: &SigningKey) -> Jwk {
+    Jwk {
+        common: CommonParameters {
+            public_key_use: Some(PublicKeyUse::Signature),
+            key_algorithm: Some(KeyAlgorithm::EdDSA),
+            key_id: Some(kid.to_string()),
+            ..Default::default()
+        },
+        algorithm: AlgorithmParameters::OctetKeyPair(OctetKeyPairParameters {
+            key_type: OctetKeyPairType::OctetKeyPair,
+            curve: EllipticCurve::Ed25519,
+            x: URL_SAFE_NO_PAD.encode(key.verifying_key().as_bytes()),
+        }),
+    }
+}
+
+/// For our own clients, which are trusted with every scope apps can be granted
+pub fn generate_jwt(
+    user_id: String,
+    session_id: String,
+    roles: Vec<String>,
+    scopes: &[String],
+) -> Result<String, jsonwebtoken::errors::Error> {
+    let mut scopes = scopes.to_vec();
+    for scope in OFFERED_SCOPES.iter() {
+        if !scopes.contains(scope) {
+            scopes.push(scope.clone());
+        }
+    }
+
+    sign(user_id, session_id, None, roles, &scopes)
+}
+
+/// For an OAuth client, with only the scopes it was granted
+pub fn generate_client_jwt(
     user_id: String,
+    session_id: String,
+    client_id: String,
+    roles: Vec<String>,
+    scopes: &[String],
+) -> Result<String, jsonwebtoken::errors::Error> {
+    sign(user_id, session_id, Some(client_id), roles, scopes)
 }
 
-pub fn generate_jwt(user_id: String) -> Result<String, jsonwebtoken::errors::Error> {
+fn sign(
+    user_id: String,
+    session_id: String,
+    client_id: Option<String>,
+    roles: Vec<String>,
+    scopes: &[String],
+) -> Result<String, jsonwebtoken::errors::Error> {
     let expiration = Utc::now()
-        .checked_add_signed(Duration::hours(24))
+        .checked_add_signed(Duration::minutes(ACCESS_TOKEN_MINUTES))
         .expect("timestamp")
         .timestamp() as u64;
 
     let claims = Claims {
         exp: expiration,
+        jti: Uuid::new_v4().to_string(),
+        sid: session_id,
         user_id,
+        roles,
+        scope: scopes.join(" "),
+        client_id,
     };
 
-    let secret = env::var("JWT_SECRET").expect("JWT_SECRET not defined in .env");
+    let mut header = Header::new(Algorithm::EdDSA);
+    header.kid = Some(KEYS.kid.clone());
+
+    encode(&header, &claims, &KEYS.encoding)
+}
+
+/// Verifies a token we issued, with any of the published keys
+pub(crate) fn verify_jwt(token: &str) -> Option<Claims> {
+    let kid = decode_header(token).ok()?.kid?;
+    let key = DecodingKey::from_jwk(KEYS.jwks.find(&kid)?).ok()?;
+
+    decode::<Claims>(token, &key, &Validation::new(Algorithm::EdDSA))
+        .map(|data| data.claims)
+        .ok()
+}
 
-    encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(secret.as_bytes()),
-    )
+/// Claims of the `Authorization: Bearer` token, if it's one we issued
+pub(crate) fn bearer_claims(headers: &HeaderMap) -> Option<Claims> {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .and_then(verify_jwt)
 }
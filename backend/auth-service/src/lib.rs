@@ -1,9 +1,23 @@
+pub mod account;
 pub mod api_utils;
 pub mod app;
 pub mod db;
+pub mod identifiers;
+pub mod jwks;
 pub mod jwt;
+pub mod lockout;
 pub mod log_out;
+pub mod mail;
 pub mod models;
+pub mod oauth;
+pub mod password_policy;
+pub mod password_reset;
+pub mod refresh;
 pub mod register;
+pub mod revocations;
+pub mod sessions;
 pub mod sign_in;
 pub mod tests;
+pub mod totp;
+pub mod two_factor;
+pub mod verify_email;
@@ -0,0 +1,200 @@
+use axum::http::header;
+use axum::response::{IntoResponse, Response};
+use chrono::{DateTime, Duration, Utc};
+use serde_json::to_vec;
+use sqlx::PgPool;
+use topic_structs::UserLockedOut;
+use tracing::{error, warn};
+
+use crate::api_utils::responses::{INTERNAL_SERVER_ERROR, TOO_MANY_LOGIN_ATTEMPTS, WRONG_PASSWORD};
+use crate::db::operations::{
+    block_login, get_login_blocked_until, get_user_by_identifier, record_login_failure,
+};
+use crate::db::password_hasher::verify_password;
+use crate::identifiers::{Identifier, fold};
+use crate::models::app_state::AppState;
+use crate::models::auth_info::AuthInfo;
+use crate::sessions::ClientInfo;
+
+/// Failures are forgotten after this long without another one
+const FAILURE_WINDOW_MINUTES: i64 = 15;
+/// Failures allowed before attempts are slowed down
+const FREE_FAILURES: i32 = 3;
+const MAX_DELAY_SECONDS: i64 = 60;
+/// Failures before the identifier or the client are locked out
+const LOCKOUT_FAILURES: i32 = 10;
+const LOCKOUT_MINUTES: i64 = 15;
+/// Password reset mails asked for an email or from a client within the window,
+/// more are refused until it's over
+const RESET_REQUESTS: i32 = 3;
+const RESET_WINDOW_MINUTES: i64 = 60;
+
+/// Normalized, so changing how a username or email is written doesn't start over
+pub fn user_key(identifier: &Identifier) -> String {
+    format!("user:{}", identifier.as_str())
+}
+
+/// Failures count against the identifier, so guessing from many addresses is
+/// slowed down, and against the client, so is trying many usernames
+fn login_keys(identifier: &Identifier, client: &ClientInfo) -> Vec<String> {
+    let mut keys = vec![user_key(identifier)];
+    if let Some(ip) = client.ip() {
+        keys.push(format!("ip:{ip}"));
+    }
+    keys
+}
+
+/// How long logins are blocked after `failures` in a row: doubling from a
+/// second once the free ones are used up, then a lockout
+pub fn block_for(failures: i32) -> Option<Duration> {
+    if failures >= LOCKOUT_FAILURES {
+        return Some(Duration::minutes(LOCKOUT_MINUTES));
+    }
+    if failures <= FREE_FAILURES {
+        return None;
+    }
+
+    let exponent = (failures - FREE_FAILURES - 1).min(16) as u32;
+    Some(Duration::seconds((1i64 << exponent).min(MAX_DELAY_SECONDS)))
+}
+
+/// Seconds until the identifier or the client can try again, if they're blocked
+pub async fn login_blocked_for(
+    db: &PgPool,
+    identifier: &Identifier,
+    client: &ClientInfo,
+) -> Result<Option<i64>, sqlx::Error> {
+    let blocked_until = get_login_blocked_until(db, &login_keys(identifier, client)).await?;
+    Ok(blocked_until.map(|until| (until - Utc::now()).num_seconds().max(1)))
+}
+
+/// Counts the failure against the identifier and the client, blocking them after too many
+pub async fn count_login_failure(
+    state: &AppState,
+    identifier: &Identifier,
+    client: &ClientInfo,
+) -> Result<(), sqlx::Error> {
+    let window = Duration::minutes(FAILURE_WINDOW_MINUTES);
+
+    for key in login_keys(identifier, client) {
+        let failures = record_login_failure(&state.db, &key, window).await?;
+        let Some(duration) = block_for(failures) else {
+            continue;
+        };
+
+        let blocked_until = Utc::now() + duration;
+        block_login(&state.db, &key, blocked_until).await?;
+
+        if failures >= LOCKOUT_FAILURES {
+            warn!(key, failures, "Login locked out");
+            if key == user_key(identifier) {
+                publish_lockout(state, identifier, blocked_until, client).await;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Failures of a signed in user count against their username, as if they signed in with it
+pub fn account_identifier(user: &AuthInfo) -> Identifier {
+    Identifier::Username(fold(&user.username))
+}
+
+/// Checks the password of a signed in user before a change to their account.
+/// Wrong ones count like at sign in, so a stolen access token can't be used to
+/// guess it.
+pub async fn check_account_password(
+    state: &AppState,
+    user: &AuthInfo,
+    password: &str,
+    client: &ClientInfo,
+) -> Result<(), Response> {
+    let identifier = account_identifier(user);
+
+    match login_blocked_for(&state.db, &identifier, client).await {
+        Ok(None) => {}
+        Ok(Some(retry_after)) => {
+            return Err((
+                [(header::RETRY_AFTER, retry_after.to_string())],
+                TOO_MANY_LOGIN_ATTEMPTS,
+            )
+                .into_response());
+        }
+        Err(e) => {
+            error!("Failed to check login failures: {}", e);
+            return Err(INTERNAL_SERVER_ERROR.into_response());
+        }
+    }
+
+    if !verify_password(password, &user.hashed_password).await {
+        if let Err(e) = count_login_failure(state, &identifier, client).await {
+            error!("Failed to count login failure: {}", e);
+        }
+        return Err(WRONG_PASSWORD.into_response());
+    }
+
+    Ok(())
+}
+
+/// Counted apart from login failures, asking for resets doesn't lock anyone out.
+/// Whether or not the email is registered, so the answer can't tell.
+fn reset_keys(email: Option<&str>, ip: Option<&str>) -> Vec<String> {
+    let email = email.map(|email| format!("reset:email:{email}"));
+    let ip = ip.map(|ip| format!("reset:ip:{ip}"));
+    email.into_iter().chain(ip).collect()
+}
+
+/// Counts a request for a password reset mail, returning the seconds until
+/// another is allowed when there were too many for the email or from the client
+pub async fn count_reset_request(
+    db: &PgPool,
+    email: Option<&str>,
+    ip: Option<&str>,
+) -> Result<Option<i64>, sqlx::Error> {
+    let keys = reset_keys(email, ip);
+    if let Some(until) = get_login_blocked_until(db, &keys).await? {
+        return Ok(Some((until - Utc::now()).num_seconds().max(1)));
+    }
+
+    let window = Duration::minutes(RESET_WINDOW_MINUTES);
+    for key in keys {
+        if record_login_failure(db, &key, window).await? >= RESET_REQUESTS {
+            block_login(db, &key, Utc::now() + window).await?;
+        }
+    }
+
+    Ok(None)
+}
+
+/// Lets the owner know someone is guessing their password
+async fn publish_lockout(
+    state: &AppState,
+    identifier: &Identifier,
+    locked_until: DateTime<Utc>,
+    client: &ClientInfo,
+) {
+    let user = match get_user_by_identifier(&state.db, identifier).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return,
+        Err(e) => {
+            error!("Failed to get locked out user: {}", e);
+            return;
+        }
+    };
+
+    let event = UserLockedOut {
+        id: user.id.clone(),
+        username: user.username,
+        locked_until: locked_until.timestamp(),
+        ip: client.ip().map(str::to_owned),
+    };
+
+    let Ok(event_bytes) = to_vec(&event) else {
+        return;
+    };
+
+    if let Err(e) = state.lockout_producer.send(user.id, event_bytes).await {
+        error!("Failed to send UserLockedOut event to Fluvio: {}", e);
+    }
+}
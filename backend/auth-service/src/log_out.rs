@@ -1,27 +1,55 @@
-use crate::api_utils::responses::INTERNAL_SERVER_ERROR;
+use crate::api_utils::responses::{INTERNAL_SERVER_ERROR, INVALID_TOKEN};
+use crate::db::operations::{revoke_session, revoke_token};
+use crate::jwt::{ACCESS_TOKEN_MINUTES, bearer_claims};
 use crate::models::app_state::AppState;
 use axum::extract::State;
+use axum::http::HeaderMap;
 use axum::{Json, response::IntoResponse};
-use chrono::Utc;
+use chrono::{DateTime, Duration, Utc};
 use serde_json::to_vec;
 use topic_structs::UserLoggedOut;
 use tracing::error;
 
-pub async fn log_user_out(State(state): State<AppState>, user_id: String) -> impl IntoResponse {
-    let logout_time = Utc::now().timestamp();
+pub async fn log_user_out(State(state): State<AppState>, headers: HeaderMap) -> impl IntoResponse {
+    // The user is whoever the token belongs to, not what the client claims
+    let claims = match bearer_claims(&headers) {
+        Some(claims) => claims,
+        None => return INVALID_TOKEN.into_response(),
+    };
+
+    let Some(expires_at) = DateTime::<Utc>::from_timestamp(claims.exp as i64, 0) else {
+        return INVALID_TOKEN.into_response();
+    };
+
+    if let Err(e) = revoke_token(&state.db, &claims.jti, &claims.user_id, expires_at).await {
+        error!("Failed to revoke token: {}", e);
+        return INTERNAL_SERVER_ERROR.into_response();
+    }
+
+    // Ends the session too, so its refresh token can't get new access tokens
+    let tokens_expire_at = Utc::now() + Duration::minutes(ACCESS_TOKEN_MINUTES);
+    if let Err(e) = revoke_session(&state.db, &claims.user_id, &claims.sid, tokens_expire_at).await
+    {
+        error!("Failed to revoke session: {}", e);
+        return INTERNAL_SERVER_ERROR.into_response();
+    }
 
     let event = UserLoggedOut {
-        id: user_id.clone(),
-        logout_time,
+        id: claims.user_id.clone(),
+        logout_time: Utc::now().timestamp(),
     };
 
     let Ok(event_bytes) = to_vec(&event) else {
         return INTERNAL_SERVER_ERROR.into_response();
     };
 
-    if let Err(e) = state.logout_producer.send(user_id, event_bytes).await {
+    // The token is already revoked, services reject it from `/revocations`
+    if let Err(e) = state
+        .logout_producer
+        .send(claims.user_id, event_bytes)
+        .await
+    {
         error!("Failed to send UserLoggedOut event to Fluvio: {}", e);
-        return INTERNAL_SERVER_ERROR.into_response();
     }
 
     Json("User logged out successfully").into_response()
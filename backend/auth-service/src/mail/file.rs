@@ -0,0 +1,43 @@
+use std::{
+    future::Future,
+    path::PathBuf,
+    pin::Pin,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use chrono::Utc;
+
+use crate::mail::{Mail, MailTransport};
+
+/// Writes every mail as a JSON file in `dir`, for development
+pub struct FileMailer {
+    dir: PathBuf,
+    /// Orders the mail sent within the same millisecond
+    sent: AtomicU64,
+}
+
+impl FileMailer {
+    pub fn new(dir: impl Into<PathBuf>) -> anyhow::Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self {
+            dir,
+            sent: AtomicU64::new(0),
+        })
+    }
+}
+
+impl MailTransport for FileMailer {
+    fn send<'a>(
+        &'a self,
+        mail: &'a Mail,
+    ) -> Pin<Box<dyn Future<Output = anyhow::Result<()>> + Send + 'a>> {
+        Box::pin(async move {
+            // Sorted by the time they were sent
+            let sent = self.sent.fetch_add(1, Ordering::Relaxed);
+            let name = format!("{}-{sent:06}.json", Utc::now().timestamp_millis());
+            tokio::fs::write(self.dir.join(name), serde_json::to_vec_pretty(mail)?).await?;
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,36 @@
+use std::{future::Future, pin::Pin, sync::Mutex};
+
+use crate::mail::{Mail, MailTransport};
+
+/// Keeps every mail sent, for tests
+#[derive(Default)]
+pub struct MemoryMailer {
+    sent: Mutex<Vec<Mail>>,
+}
+
+impl MemoryMailer {
+    pub fn sent(&self) -> Vec<Mail> {
+        self.sent.lock().unwrap().clone()
+    }
+
+    /// The last mail sent to `to`
+    pub fn last_to(&self, to: &str) -> Option<Mail> {
+        self.sent
+            .lock()
+            .unwrap()
+            .iter()
+            .rev()
+            .find(|mail| mail.to == to)
+            .cloned()
+    }
+}
+
+impl MailTransport for MemoryMailer {
+    fn send<'a>(
+        &'a self,
+        mail: &'a Mail,
+    ) -> Pin<Box<dyn Future<Output = anyhow::Result<()>> + Send + 'a>> {
+        self.sent.lock().unwrap().push(mail.clone());
+        Box::pin(std::future::ready(Ok(())))
+    }
+}
@@ -0,0 +1,45 @@
+use std::{env::var, future::Future, pin::Pin, sync::Arc};
+
+use serde::{Deserialize, Serialize};
+
+use crate::mail::{file::FileMailer, memory::MemoryMailer, smtp::SmtpMailer};
+
+pub mod file;
+pub mod memory;
+pub mod smtp;
+
+/// A plain text email
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// Delivers outgoing mail. Besides SMTP, mail can be written to files or kept
+/// in memory, so it can be read without a mail server.
+pub trait MailTransport: Send + Sync {
+    fn send<'a>(
+        &'a self,
+        mail: &'a Mail,
+    ) -> Pin<Box<dyn Future<Output = anyhow::Result<()>> + Send + 'a>>;
+}
+
+/// Picks the transport set by `MAIL_TRANSPORT`: `smtp` (the default), `file` or `memory`
+pub fn from_env() -> anyhow::Result<Arc<dyn MailTransport>> {
+    let transport = var("MAIL_TRANSPORT").unwrap_or("smtp".to_owned());
+
+    match transport.trim() {
+        "smtp" => {
+            let url = var("SMTP_URL").map_err(|_| anyhow::anyhow!("SMTP_URL must be set"))?;
+            let from = var("MAIL_FROM").map_err(|_| anyhow::anyhow!("MAIL_FROM must be set"))?;
+            Ok(Arc::new(SmtpMailer::new(url.trim(), from.trim())?))
+        }
+        "file" => {
+            let dir = var("MAIL_DIR").unwrap_or("mail".to_owned());
+            Ok(Arc::new(FileMailer::new(dir.trim())?))
+        }
+        "memory" => Ok(Arc::new(MemoryMailer::default())),
+        other => anyhow::bail!("Unknown MAIL_TRANSPORT `{other}`"),
+    }
+}
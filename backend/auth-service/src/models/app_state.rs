@@ -1,11 +1,18 @@
+use std::sync::Arc;
+
 use fluvio::{TopicProducer, spu::SpuSocketPool};
 
+use crate::mail::MailTransport;
+
 #[derive(Clone)]
 pub struct AppState {
     pub db: sqlx::PgPool,
     pub register_producer: TopicProducerMono,
     pub login_producer: TopicProducerMono,
     pub logout_producer: TopicProducerMono,
+    pub delete_producer: TopicProducerMono,
+    pub lockout_producer: TopicProducerMono,
+    pub mailer: Arc<dyn MailTransport>,
 }
 
 // `TopicProducer<S>` requires a generic parameter `S`
@@ -6,4 +6,8 @@ pub struct AuthInfo {
     pub id: String,
     pub username: String,
     pub hashed_password: String,
+    pub roles: Vec<String>,
+    pub scopes: Vec<String>,
+    // Signing in takes a second factor too
+    pub totp_enabled: bool,
 }
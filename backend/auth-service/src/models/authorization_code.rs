@@ -0,0 +1,23 @@
+use chrono::{DateTime, Utc};
+use sqlx::FromRow;
+
+/// What the user approved, handed to the client as a single use code
+#[derive(Clone, FromRow)]
+pub struct AuthorizationCode {
+    pub client_id: String,
+    pub user_id: String,
+    pub redirect_uri: String,
+    pub scopes: Vec<String>,
+    // PKCE, only the client that started the flow knows its verifier
+    pub code_challenge: String,
+}
+
+#[derive(FromRow)]
+pub struct StoredAuthorizationCode {
+    #[sqlx(flatten)]
+    pub code: AuthorizationCode,
+    pub expires_at: DateTime<Utc>,
+    // Set once it's exchanged, for the session it got
+    pub used_at: Option<DateTime<Utc>>,
+    pub session_id: Option<String>,
+}
@@ -0,0 +1,15 @@
+/// What a mailed token proves, it can't be used for anything else
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum EmailTokenPurpose {
+    VerifyEmail,
+    ResetPassword,
+}
+
+impl EmailTokenPurpose {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            EmailTokenPurpose::VerifyEmail => "verify_email",
+            EmailTokenPurpose::ResetPassword => "reset_password",
+        }
+    }
+}
@@ -1,3 +1,10 @@
 pub mod app_state;
 pub mod auth_info;
+pub mod authorization_code;
+pub mod email_token;
+pub mod oauth_client;
+pub mod oauth_consent;
+pub mod refresh_token;
+pub mod revoked_token;
+pub mod session;
 pub mod user_info;
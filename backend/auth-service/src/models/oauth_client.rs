@@ -0,0 +1,26 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::FromRow;
+
+/// An app or bot registered by a user to get tokens through OAuth 2.0
+#[derive(Clone, FromRow, Serialize)]
+pub struct OAuthClient {
+    #[serde(rename = "client_id")]
+    pub id: String,
+    #[serde(skip)]
+    pub owner_id: String,
+    pub name: String,
+    // Public clients, which can't keep a secret, have none
+    #[serde(skip)]
+    pub secret_hash: Option<String>,
+    pub redirect_uris: Vec<String>,
+    // The most it can be granted
+    pub scopes: Vec<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl OAuthClient {
+    pub fn is_confidential(&self) -> bool {
+        self.secret_hash.is_some()
+    }
+}
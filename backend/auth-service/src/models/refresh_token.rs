@@ -0,0 +1,13 @@
+use chrono::{DateTime, Utc};
+use sqlx::FromRow;
+
+#[derive(Clone, FromRow)]
+pub struct RefreshToken {
+    pub id: String,
+    pub user_id: String,
+    pub family_id: String,
+    pub expires_at: DateTime<Utc>,
+    // Set once it's exchanged, it can't be used again
+    pub used_at: Option<DateTime<Utc>>,
+    pub revoked: bool,
+}
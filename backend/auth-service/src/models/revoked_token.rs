@@ -0,0 +1,16 @@
+use serde::Serialize;
+use sqlx::FromRow;
+
+#[derive(Clone, FromRow, Serialize)]
+pub struct RevokedToken {
+    pub jti: String,
+    // Unix time, the token is rejected anyway past it
+    pub expires_at: i64,
+    /// Transaction that revoked it
+    #[serde(skip)]
+    pub xact_id: i64,
+    /// Oldest transaction still in progress when it was listed, the ones from it
+    /// on may yet commit revocations
+    #[serde(skip)]
+    pub in_progress_from: i64,
+}
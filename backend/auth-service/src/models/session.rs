@@ -0,0 +1,15 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::FromRow;
+
+#[derive(Clone, FromRow, Serialize)]
+pub struct Session {
+    pub id: String,
+    // User agent, unless the client names itself
+    pub device: Option<String>,
+    pub ip: Option<String>,
+    // Set for apps signed in through OAuth, named in `device`
+    pub client_id: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub last_used_at: DateTime<Utc>,
+}
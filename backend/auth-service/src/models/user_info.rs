@@ -6,5 +6,8 @@ pub struct UserInfo {
     pub username: String,
     pub hashed_password: String,
     pub email: String,
+    pub email_verified: bool,
     pub telephone: Option<String>,
+    pub roles: Vec<String>,
+    pub scopes: Vec<String>,
 }
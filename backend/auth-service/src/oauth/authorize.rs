@@ -0,0 +1,194 @@
+use axum::extract::{Query, State};
+use axum::http::HeaderMap;
+use axum::{Json, response::IntoResponse};
+use chrono::{Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tracing::error;
+
+use crate::db::operations::{
+    get_consented_scopes, get_oauth_client, grant_consent, insert_authorization_code,
+};
+use crate::models::app_state::AppState;
+use crate::models::authorization_code::AuthorizationCode;
+use crate::models::oauth_client::OAuthClient;
+use crate::oauth::{OAuthError, allowed_scopes, redirect_with, requested_scopes};
+use crate::refresh::generate_token;
+use crate::sessions::authenticate;
+
+/// Exchanged right away by the client, RFC 6749 recommends at most 10 minutes
+const CODE_MINUTES: i64 = 10;
+
+/// The query third parties send users to the consent page with, which the
+/// page passes on here along with the user's token
+#[derive(Deserialize)]
+pub struct AuthorizationRequest {
+    response_type: String,
+    client_id: String,
+    redirect_uri: Option<String>,
+    scope: Option<String>,
+    state: Option<String>,
+    code_challenge: Option<String>,
+    code_challenge_method: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct AuthorizationDecision {
+    #[serde(flatten)]
+    request: AuthorizationRequest,
+    approved: bool,
+}
+
+/// What the consent page shows
+#[derive(Serialize)]
+struct AuthorizationPrompt {
+    client_id: String,
+    client_name: String,
+    scopes: Vec<String>,
+    /// Every scope was granted before, the page may approve without asking
+    consented: bool,
+}
+
+/// Where the consent page sends the user back to, with the code or the error
+#[derive(Serialize)]
+struct AuthorizationRedirect {
+    redirect_to: String,
+}
+
+struct ValidRequest {
+    client: OAuthClient,
+    redirect_uri: String,
+    scopes: Vec<String>,
+    code_challenge: String,
+}
+
+/// Errors are answered to the consent page rather than redirected, the
+/// redirect URI can't be trusted until it's been checked against the client
+async fn validate(db: &PgPool, request: &AuthorizationRequest) -> Result<ValidRequest, OAuthError> {
+    let client = match get_oauth_client(db, &request.client_id).await {
+        Ok(Some(client)) => client,
+        Ok(None) => return Err(OAuthError::InvalidRequest("Unknown client")),
+        Err(e) => {
+            error!("Failed to get OAuth client: {}", e);
+            return Err(OAuthError::ServerError);
+        }
+    };
+
+    let redirect_uri = match (&request.redirect_uri, client.redirect_uris.as_slice()) {
+        (Some(uri), registered) if registered.contains(uri) => uri.clone(),
+        (Some(_), _) => {
+            return Err(OAuthError::InvalidRequest(
+                "redirect_uri isn't registered for the client",
+            ));
+        }
+        (None, [only]) => only.clone(),
+        (None, _) => return Err(OAuthError::InvalidRequest("redirect_uri is required")),
+    };
+
+    if request.response_type != "code" {
+        return Err(OAuthError::UnsupportedResponseType);
+    }
+
+    // Required of every client, it keeps stolen codes from being exchanged
+    let code_challenge = match (&request.code_challenge, &request.code_challenge_method) {
+        (Some(challenge), Some(method)) if method == "S256" && challenge.len() == 43 => {
+            challenge.clone()
+        }
+        _ => return Err(OAuthError::InvalidRequest("PKCE with S256 is required")),
+    };
+
+    let scopes = requested_scopes(request.scope.as_deref(), &allowed_scopes(&client))?;
+
+    Ok(ValidRequest {
+        client,
+        redirect_uri,
+        scopes,
+        code_challenge,
+    })
+}
+
+pub async fn get_authorization(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(request): Query<AuthorizationRequest>,
+) -> impl IntoResponse {
+    let claims = match authenticate(&state.db, &headers).await {
+        Ok(claims) => claims,
+        Err(response) => return response,
+    };
+
+    let valid = match validate(&state.db, &request).await {
+        Ok(valid) => valid,
+        Err(e) => return e.into_response(),
+    };
+
+    let consented = match get_consented_scopes(&state.db, &claims.user_id, &valid.client.id).await {
+        Ok(consented) => consented
+            .is_some_and(|consented| valid.scopes.iter().all(|scope| consented.contains(scope))),
+        Err(e) => {
+            error!("Failed to get consent: {}", e);
+            return OAuthError::ServerError.into_response();
+        }
+    };
+
+    Json(AuthorizationPrompt {
+        client_id: valid.client.id,
+        client_name: valid.client.name,
+        scopes: valid.scopes,
+        consented,
+    })
+    .into_response()
+}
+
+pub async fn decide_authorization(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(decision): Json<AuthorizationDecision>,
+) -> impl IntoResponse {
+    let claims = match authenticate(&state.db, &headers).await {
+        Ok(claims) => claims,
+        Err(response) => return response,
+    };
+
+    let request = decision.request;
+    let valid = match validate(&state.db, &request).await {
+        Ok(valid) => valid,
+        Err(e) => return e.into_response(),
+    };
+
+    if !decision.approved {
+        let redirect_to = redirect_with(
+            &valid.redirect_uri,
+            &[("error", "access_denied")],
+            request.state.as_deref(),
+        );
+        return Json(AuthorizationRedirect { redirect_to }).into_response();
+    }
+
+    if let Err(e) = grant_consent(&state.db, &claims.user_id, &valid.client.id, &valid.scopes).await
+    {
+        error!("Failed to record consent: {}", e);
+        return OAuthError::ServerError.into_response();
+    }
+
+    let (code, hash) = generate_token();
+    let authorization = AuthorizationCode {
+        client_id: valid.client.id,
+        user_id: claims.user_id,
+        redirect_uri: valid.redirect_uri.clone(),
+        scopes: valid.scopes,
+        code_challenge: valid.code_challenge,
+    };
+    let expires_at = Utc::now() + Duration::minutes(CODE_MINUTES);
+    if let Err(e) = insert_authorization_code(&state.db, &hash, &authorization, expires_at).await {
+        error!("Failed to store authorization code: {}", e);
+        return OAuthError::ServerError.into_response();
+    }
+
+    let redirect_to = redirect_with(
+        &valid.redirect_uri,
+        &[("code", &code)],
+        request.state.as_deref(),
+    );
+    Json(AuthorizationRedirect { redirect_to }).into_response()
+}
@@ -0,0 +1,138 @@
+use axum::extract::{Path, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::{Json, response::IntoResponse};
+use serde::{Deserialize, Serialize};
+use tracing::error;
+
+use crate::api_utils::responses::{CLIENT_NOT_FOUND, INTERNAL_SERVER_ERROR};
+use crate::db::operations::{delete_oauth_client, get_oauth_clients_of, insert_oauth_client};
+use crate::models::app_state::AppState;
+use crate::models::oauth_client::OAuthClient;
+use crate::oauth::{OAuthError, OFFERED_SCOPES, parse_scope, valid_redirect_uri};
+use crate::refresh::generate_token;
+use crate::sessions::{authenticate, tokens_expire_at};
+
+const MAX_NAME_LEN: usize = 100;
+
+#[derive(Deserialize)]
+pub struct RegisterClientData {
+    name: String,
+    #[serde(default)]
+    redirect_uris: Vec<String>,
+    #[serde(default)]
+    scopes: Vec<String>,
+    /// Browser and mobile apps, which can't keep a secret. They must use PKCE,
+    /// which every client does anyway, and can't get tokens for themselves.
+    #[serde(default)]
+    public: bool,
+}
+
+#[derive(Serialize)]
+struct RegisteredClient {
+    #[serde(flatten)]
+    client: OAuthClient,
+    /// Only shown now, it's stored hashed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    client_secret: Option<String>,
+}
+
+fn validate_client(data: &RegisterClientData) -> Result<(), OAuthError> {
+    let name_len = data.name.trim().chars().count();
+    if name_len == 0 || name_len > MAX_NAME_LEN {
+        return Err(OAuthError::InvalidClientMetadata(
+            "The name must have between 1 and 100 characters",
+        ));
+    }
+    if !data.redirect_uris.iter().all(|uri| valid_redirect_uri(uri)) {
+        return Err(OAuthError::InvalidRedirectUri);
+    }
+    // Without a secret nor a redirect URI there's no way to get a token
+    if data.public && data.redirect_uris.is_empty() {
+        return Err(OAuthError::InvalidClientMetadata(
+            "Public clients need a redirect URI",
+        ));
+    }
+    if data
+        .scopes
+        .iter()
+        .any(|scope| !OFFERED_SCOPES.contains(scope))
+    {
+        return Err(OAuthError::InvalidScope);
+    }
+    Ok(())
+}
+
+pub async fn register_client(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(data): Json<RegisterClientData>,
+) -> impl IntoResponse {
+    let claims = match authenticate(&state.db, &headers).await {
+        Ok(claims) => claims,
+        Err(response) => return response,
+    };
+
+    if let Err(e) = validate_client(&data) {
+        return e.into_response();
+    }
+
+    let secret = (!data.public).then(generate_token);
+    let client = match insert_oauth_client(
+        &state.db,
+        &claims.user_id,
+        data.name.trim(),
+        secret.as_ref().map(|(_, hash)| hash.as_str()),
+        &data.redirect_uris,
+        &parse_scope(&data.scopes.join(" ")),
+    )
+    .await
+    {
+        Ok(client) => client,
+        Err(e) => {
+            error!("Failed to register OAuth client: {}", e);
+            return INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let registered = RegisteredClient {
+        client,
+        client_secret: secret.map(|(secret, _)| secret),
+    };
+    (StatusCode::CREATED, Json(registered)).into_response()
+}
+
+pub async fn list_clients(State(state): State<AppState>, headers: HeaderMap) -> impl IntoResponse {
+    let claims = match authenticate(&state.db, &headers).await {
+        Ok(claims) => claims,
+        Err(response) => return response,
+    };
+
+    match get_oauth_clients_of(&state.db, &claims.user_id).await {
+        Ok(clients) => Json(clients).into_response(),
+        Err(e) => {
+            error!("Failed to get OAuth clients: {}", e);
+            INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Every token issued to the client stops working, for users and the bot alike
+pub async fn delete_client(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(client_id): Path<String>,
+) -> impl IntoResponse {
+    let claims = match authenticate(&state.db, &headers).await {
+        Ok(claims) => claims,
+        Err(response) => return response,
+    };
+
+    match delete_oauth_client(&state.db, &claims.user_id, &client_id, tokens_expire_at()).await {
+        Ok(true) => Json("Client deleted").into_response(),
+        Ok(false) => CLIENT_NOT_FOUND.into_response(),
+        Err(e) => {
+            error!("Failed to delete OAuth client: {}", e);
+            INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
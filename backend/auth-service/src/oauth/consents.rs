@@ -0,0 +1,45 @@
+use axum::extract::{Path, State};
+use axum::http::HeaderMap;
+use axum::{Json, response::IntoResponse};
+use tracing::error;
+
+use crate::api_utils::responses::{CONSENT_NOT_FOUND, INTERNAL_SERVER_ERROR};
+use crate::db::operations::{get_consents, revoke_consent};
+use crate::models::app_state::AppState;
+use crate::sessions::{authenticate, tokens_expire_at};
+
+pub async fn list_consents(State(state): State<AppState>, headers: HeaderMap) -> impl IntoResponse {
+    let claims = match authenticate(&state.db, &headers).await {
+        Ok(claims) => claims,
+        Err(response) => return response,
+    };
+
+    match get_consents(&state.db, &claims.user_id).await {
+        Ok(consents) => Json(consents).into_response(),
+        Err(e) => {
+            error!("Failed to get consents: {}", e);
+            INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// The app is signed out, and has to ask again to get back in
+pub async fn revoke_client_consent(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(client_id): Path<String>,
+) -> impl IntoResponse {
+    let claims = match authenticate(&state.db, &headers).await {
+        Ok(claims) => claims,
+        Err(response) => return response,
+    };
+
+    match revoke_consent(&state.db, &claims.user_id, &client_id, tokens_expire_at()).await {
+        Ok(true) => Json("Consent revoked").into_response(),
+        Ok(false) => CONSENT_NOT_FOUND.into_response(),
+        Err(e) => {
+            error!("Failed to revoke consent: {}", e);
+            INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
@@ -0,0 +1,227 @@
+use std::env::var;
+use std::sync::LazyLock;
+
+use axum::Json;
+use axum::http::{HeaderMap, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use base64::{
+    Engine,
+    engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
+};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+use crate::models::authorization_code::AuthorizationCode;
+use crate::models::oauth_client::OAuthClient;
+
+pub mod authorize;
+pub mod clients;
+pub mod consents;
+pub mod token;
+
+/// Scopes apps can ask for, as a space or comma separated `OAUTH_SCOPES`. They
+/// should match the ones the gateway requires on routes open to apps.
+pub static OFFERED_SCOPES: LazyLock<Vec<String>> =
+    LazyLock::new(|| parse_scope(&var("OAUTH_SCOPES").unwrap_or_default().replace(',', " ")));
+
+/// Errors of the authorization and token endpoints, as RFC 6749 has them
+#[derive(Debug, PartialEq, Eq)]
+pub enum OAuthError {
+    InvalidRequest(&'static str),
+    /// Unknown client or wrong secret
+    InvalidClient,
+    InvalidGrant(&'static str),
+    UnauthorizedClient(&'static str),
+    UnsupportedGrantType,
+    UnsupportedResponseType,
+    InvalidScope,
+    /// From client registration, as RFC 7591 has them
+    InvalidRedirectUri,
+    InvalidClientMetadata(&'static str),
+    ServerError,
+}
+
+impl OAuthError {
+    fn code(&self) -> &'static str {
+        match self {
+            OAuthError::InvalidRequest(_) => "invalid_request",
+            OAuthError::InvalidClient => "invalid_client",
+            OAuthError::InvalidGrant(_) => "invalid_grant",
+            OAuthError::UnauthorizedClient(_) => "unauthorized_client",
+            OAuthError::UnsupportedGrantType => "unsupported_grant_type",
+            OAuthError::UnsupportedResponseType => "unsupported_response_type",
+            OAuthError::InvalidScope => "invalid_scope",
+            OAuthError::InvalidRedirectUri => "invalid_redirect_uri",
+            OAuthError::InvalidClientMetadata(_) => "invalid_client_metadata",
+            OAuthError::ServerError => "server_error",
+        }
+    }
+
+    fn description(&self) -> &'static str {
+        match self {
+            OAuthError::InvalidRequest(description)
+            | OAuthError::InvalidGrant(description)
+            | OAuthError::UnauthorizedClient(description)
+            | OAuthError::InvalidClientMetadata(description) => description,
+            OAuthError::InvalidClient => "Unknown client or wrong credentials",
+            OAuthError::UnsupportedGrantType => {
+                "Supported grant types are authorization_code, refresh_token and client_credentials"
+            }
+            OAuthError::UnsupportedResponseType => "Only the code response type is supported",
+            OAuthError::InvalidScope => "Unknown scope, or not allowed for the client",
+            OAuthError::InvalidRedirectUri => {
+                "Redirect URIs must be https, http on loopback, or a private-use scheme, without a fragment"
+            }
+            OAuthError::ServerError => "Internal server error",
+        }
+    }
+}
+
+impl IntoResponse for OAuthError {
+    fn into_response(self) -> Response {
+        let status = match self {
+            OAuthError::InvalidClient => StatusCode::UNAUTHORIZED,
+            OAuthError::ServerError => StatusCode::INTERNAL_SERVER_ERROR,
+            _ => StatusCode::BAD_REQUEST,
+        };
+        let body = Json(json!({
+            "error": self.code(),
+            "error_description": self.description(),
+        }));
+
+        if self == OAuthError::InvalidClient {
+            return (status, [(header::WWW_AUTHENTICATE, "Basic")], body).into_response();
+        }
+        (status, body).into_response()
+    }
+}
+
+/// Space separated scopes, without repeating any
+pub fn parse_scope(scope: &str) -> Vec<String> {
+    let mut scopes: Vec<String> = Vec::new();
+    for scope in scope.split_whitespace() {
+        if !scopes.iter().any(|s| s == scope) {
+            scopes.push(scope.to_owned());
+        }
+    }
+    scopes
+}
+
+/// What the client registered with that's still offered
+pub fn allowed_scopes(client: &OAuthClient) -> Vec<String> {
+    client
+        .scopes
+        .iter()
+        .filter(|scope| OFFERED_SCOPES.contains(scope))
+        .cloned()
+        .collect()
+}
+
+/// The scopes asked for, all the allowed ones if none are
+pub fn requested_scopes(
+    scope: Option<&str>,
+    allowed: &[String],
+) -> Result<Vec<String>, OAuthError> {
+    let requested = parse_scope(scope.unwrap_or_default());
+    if requested.is_empty() {
+        return Ok(allowed.to_vec());
+    }
+    if requested.iter().any(|scope| !allowed.contains(scope)) {
+        return Err(OAuthError::InvalidScope);
+    }
+    Ok(requested)
+}
+
+/// RFC 7636 with S256, the only method allowed
+pub fn verify_pkce(code_verifier: &str, code_challenge: &str) -> bool {
+    let valid_verifier = (43..=128).contains(&code_verifier.len())
+        && code_verifier
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '.' | '_' | '~'));
+
+    valid_verifier && URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier)) == code_challenge
+}
+
+/// A code is only exchanged by the client it was issued to, with the `redirect_uri`
+/// it was asked for and the verifier of its challenge. Codes always carry a
+/// `redirect_uri`, so it's required too (RFC 6749, section 4.1.3).
+pub fn check_code_exchange(
+    code: &AuthorizationCode,
+    client_id: &str,
+    redirect_uri: Option<&str>,
+    code_verifier: &str,
+) -> Result<(), OAuthError> {
+    let Some(redirect_uri) = redirect_uri else {
+        return Err(OAuthError::InvalidRequest("redirect_uri is required"));
+    };
+    if code.client_id != client_id || code.redirect_uri != redirect_uri {
+        return Err(OAuthError::InvalidGrant(
+            "The code was issued to another client or redirect_uri",
+        ));
+    }
+    if !verify_pkce(code_verifier, &code.code_challenge) {
+        return Err(OAuthError::InvalidGrant(
+            "code_verifier doesn't match the code_challenge",
+        ));
+    }
+
+    Ok(())
+}
+
+/// Registered redirect URIs are matched exactly, so they must be precise:
+/// https, http only on loopback for native apps, or a private-use scheme
+/// such as `com.example.app:/callback` (RFC 8252)
+pub fn valid_redirect_uri(uri: &str) -> bool {
+    if uri.contains('#') || uri.chars().any(|c| c.is_whitespace() || c.is_control()) {
+        return false;
+    }
+
+    let after_prefix = |prefix: &str| {
+        uri.strip_prefix(prefix)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with([':', '/', '?']))
+    };
+    if let Some(host) = uri.strip_prefix("https://") {
+        return !host.is_empty() && !host.starts_with(['/', '?', ':']);
+    }
+    if ["http://localhost", "http://127.0.0.1", "http://[::1]"]
+        .into_iter()
+        .any(after_prefix)
+    {
+        return true;
+    }
+
+    // Reverse domain names, so apps can't take over each other's schemes
+    match uri.split_once(':') {
+        Some((scheme, rest)) => {
+            scheme.contains('.')
+                && scheme
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '+' | '-'))
+                && !rest.is_empty()
+        }
+        None => false,
+    }
+}
+
+/// `redirect_uri` with `params` and the client's `state` added to its query
+pub fn redirect_with(redirect_uri: &str, params: &[(&str, &str)], state: Option<&str>) -> String {
+    let mut params = params.to_vec();
+    if let Some(state) = state {
+        params.push(("state", state));
+    }
+    let query = serde_urlencoded::to_string(&params).unwrap_or_default();
+    let separator = if redirect_uri.contains('?') { '&' } else { '?' };
+    format!("{redirect_uri}{separator}{query}")
+}
+
+/// The client id and secret of `Authorization: Basic`
+pub fn basic_credentials(headers: &HeaderMap) -> Option<(String, String)> {
+    let encoded = headers
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Basic ")?;
+    let decoded = String::from_utf8(STANDARD.decode(encoded.trim()).ok()?).ok()?;
+    let (id, secret) = decoded.split_once(':')?;
+    Some((id.to_owned(), secret.to_owned()))
+}
@@ -0,0 +1,285 @@
+use axum::extract::State;
+use axum::http::{HeaderMap, header};
+use axum::{Form, Json, response::IntoResponse};
+use chrono::{Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tracing::{error, warn};
+use uuid::Uuid;
+
+use crate::db::operations::{
+    CodeOutcome, RefreshOutcome, consume_authorization_code, get_oauth_client,
+    insert_oauth_session, revoke_session, rotate_refresh_token, set_authorization_code_session,
+};
+use crate::jwt::{ACCESS_TOKEN_MINUTES, generate_client_jwt};
+use crate::models::app_state::AppState;
+use crate::models::oauth_client::OAuthClient;
+use crate::oauth::{
+    OAuthError, allowed_scopes, basic_credentials, check_code_exchange, requested_scopes,
+};
+use crate::refresh::{REFRESH_TOKEN_DAYS, generate_token, hash_token, issue_refresh_token};
+use crate::sessions::{ClientInfo, tokens_expire_at};
+
+/// Given to tokens bots get for themselves, so routes can tell them apart
+const BOT_ROLE: &str = "bot";
+
+/// Form encoded, as RFC 6749 has it. Which fields are needed depends on the grant.
+#[derive(Deserialize)]
+pub struct TokenRequest {
+    grant_type: String,
+    code: Option<String>,
+    redirect_uri: Option<String>,
+    code_verifier: Option<String>,
+    refresh_token: Option<String>,
+    scope: Option<String>,
+    client_id: Option<String>,
+    client_secret: Option<String>,
+}
+
+#[derive(Serialize)]
+struct TokenResponse {
+    access_token: String,
+    token_type: &'static str,
+    expires_in: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    refresh_token: Option<String>,
+    scope: String,
+}
+
+impl TokenResponse {
+    fn new(access_token: String, refresh_token: Option<String>, scopes: &[String]) -> Self {
+        Self {
+            access_token,
+            token_type: "Bearer",
+            expires_in: ACCESS_TOKEN_MINUTES * 60,
+            refresh_token,
+            scope: scopes.join(" "),
+        }
+    }
+}
+
+pub async fn issue_token(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    client_info: ClientInfo,
+    Form(request): Form<TokenRequest>,
+) -> impl IntoResponse {
+    let client = match authenticate_client(&state.db, &headers, &request).await {
+        Ok(client) => client,
+        Err(e) => return e.into_response(),
+    };
+
+    let tokens = match request.grant_type.as_str() {
+        "authorization_code" => exchange_code(&state.db, &client, &client_info, &request).await,
+        "refresh_token" => refresh(&state.db, &client, &request).await,
+        "client_credentials" => client_credentials(&client, &request),
+        _ => Err(OAuthError::UnsupportedGrantType),
+    };
+
+    match tokens {
+        Ok(tokens) => (
+            [
+                (header::CACHE_CONTROL, "no-store"),
+                (header::PRAGMA, "no-cache"),
+            ],
+            Json(tokens),
+        )
+            .into_response(),
+        Err(e) => e.into_response(),
+    }
+}
+
+/// With `Authorization: Basic`, or `client_id` and `client_secret` in the form.
+/// Public clients only send their id.
+async fn authenticate_client(
+    db: &PgPool,
+    headers: &HeaderMap,
+    request: &TokenRequest,
+) -> Result<OAuthClient, OAuthError> {
+    let (client_id, secret) = match basic_credentials(headers) {
+        Some((client_id, secret)) => (client_id, Some(secret)),
+        None => match &request.client_id {
+            Some(client_id) => (client_id.clone(), request.client_secret.clone()),
+            None => return Err(OAuthError::InvalidClient),
+        },
+    };
+
+    let client = match get_oauth_client(db, &client_id).await {
+        Ok(Some(client)) => client,
+        Ok(None) => return Err(OAuthError::InvalidClient),
+        Err(e) => {
+            error!("Failed to get OAuth client: {}", e);
+            return Err(OAuthError::ServerError);
+        }
+    };
+
+    match (&client.secret_hash, secret) {
+        (Some(hash), Some(secret)) if *hash == hash_token(&secret) => Ok(client),
+        (None, None) => Ok(client),
+        _ => Err(OAuthError::InvalidClient),
+    }
+}
+
+async fn exchange_code(
+    db: &PgPool,
+    client: &OAuthClient,
+    client_info: &ClientInfo,
+    request: &TokenRequest,
+) -> Result<TokenResponse, OAuthError> {
+    let (Some(code), Some(code_verifier)) = (&request.code, &request.code_verifier) else {
+        return Err(OAuthError::InvalidRequest(
+            "code and code_verifier are required",
+        ));
+    };
+
+    let code_hash = hash_token(code);
+    let code = match consume_authorization_code(db, &code_hash).await {
+        Ok(CodeOutcome::Valid(code)) => code,
+        Ok(CodeOutcome::Reused {
+            user_id,
+            session_id,
+        }) => {
+            warn!("Authorization code was used again, revoked the session it got");
+            let revoked = match session_id {
+                Some(session_id) => {
+                    revoke_session(db, &user_id, &session_id, tokens_expire_at()).await
+                }
+                None => Ok(false),
+            };
+            if let Err(e) = revoked {
+                error!("Failed to revoke session: {}", e);
+            }
+            return Err(OAuthError::InvalidGrant("Invalid authorization code"));
+        }
+        Ok(CodeOutcome::Invalid) => {
+            return Err(OAuthError::InvalidGrant("Invalid authorization code"));
+        }
+        Err(e) => {
+            error!("Failed to consume authorization code: {}", e);
+            return Err(OAuthError::ServerError);
+        }
+    };
+
+    check_code_exchange(
+        &code,
+        &client.id,
+        request.redirect_uri.as_deref(),
+        code_verifier,
+    )?;
+
+    // Shown among the user's sessions, where the app can be signed out
+    let session_id = Uuid::new_v4().to_string();
+    let session = async {
+        insert_oauth_session(
+            db,
+            &session_id,
+            &code.user_id,
+            client,
+            client_info.ip(),
+            &code.scopes,
+        )
+        .await?;
+        set_authorization_code_session(db, &code_hash, &session_id).await?;
+        issue_refresh_token(db, &code.user_id, &session_id).await
+    };
+    let refresh_token = match session.await {
+        Ok(refresh_token) => refresh_token,
+        Err(e) => {
+            error!("Failed to start OAuth session: {}", e);
+            return Err(OAuthError::ServerError);
+        }
+    };
+
+    // Apps act for the user, but never with their roles
+    let access_token = generate_client_jwt(
+        code.user_id,
+        session_id,
+        client.id.clone(),
+        Vec::new(),
+        &code.scopes,
+    )
+    .map_err(|_| OAuthError::ServerError)?;
+
+    Ok(TokenResponse::new(
+        access_token,
+        Some(refresh_token),
+        &code.scopes,
+    ))
+}
+
+async fn refresh(
+    db: &PgPool,
+    client: &OAuthClient,
+    request: &TokenRequest,
+) -> Result<TokenResponse, OAuthError> {
+    let Some(old_token) = &request.refresh_token else {
+        return Err(OAuthError::InvalidRequest("refresh_token is required"));
+    };
+
+    let (refresh_token, new_hash) = generate_token();
+    let expires_at = Utc::now() + Duration::days(REFRESH_TOKEN_DAYS);
+
+    let outcome = rotate_refresh_token(
+        db,
+        &hash_token(old_token),
+        &new_hash,
+        expires_at,
+        Some(&client.id),
+        tokens_expire_at(),
+    )
+    .await;
+
+    let (user, session_id, scopes) = match outcome {
+        Ok(RefreshOutcome::Rotated {
+            user,
+            session_id,
+            scopes,
+        }) => (user, session_id, scopes.unwrap_or_default()),
+        Ok(RefreshOutcome::Reused) => {
+            warn!("Rotated refresh token was used again, revoked its session");
+            return Err(OAuthError::InvalidGrant("Invalid refresh token"));
+        }
+        Ok(RefreshOutcome::Invalid) => {
+            return Err(OAuthError::InvalidGrant("Invalid refresh token"));
+        }
+        Err(e) => {
+            error!("Could not rotate refresh token: {}", e);
+            return Err(OAuthError::ServerError);
+        }
+    };
+
+    let access_token =
+        generate_client_jwt(user.id, session_id, client.id.clone(), Vec::new(), &scopes)
+            .map_err(|_| OAuthError::ServerError)?;
+
+    Ok(TokenResponse::new(
+        access_token,
+        Some(refresh_token),
+        &scopes,
+    ))
+}
+
+/// For bots, acting as themselves: the client is both their user and their
+/// session, so deleting it revokes their tokens
+fn client_credentials(
+    client: &OAuthClient,
+    request: &TokenRequest,
+) -> Result<TokenResponse, OAuthError> {
+    if !client.is_confidential() {
+        return Err(OAuthError::UnauthorizedClient(
+            "Only clients with a secret can get tokens for themselves",
+        ));
+    }
+
+    let scopes = requested_scopes(request.scope.as_deref(), &allowed_scopes(client))?;
+    let access_token = generate_client_jwt(
+        client.id.clone(),
+        client.id.clone(),
+        client.id.clone(),
+        vec![BOT_ROLE.to_owned()],
+        &scopes,
+    )
+    .map_err(|_| OAuthError::ServerError)?;
+
+    Ok(TokenResponse::new(access_token, None, &scopes))
+}
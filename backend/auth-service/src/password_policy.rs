@@ -0,0 +1,102 @@
+use std::collections::HashSet;
+use std::env::var;
+use std::fs;
+use std::sync::LazyLock;
+
+use anyhow::Context;
+use axum::Json;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use serde_json::json;
+use thiserror::Error;
+
+/// Most common passwords, in case no breached list is configured
+const COMMON_PASSWORDS: &str = include_str!("common_passwords.txt");
+
+pub const DEFAULT_MIN_LENGTH: usize = 8;
+/// Hashing is the expensive part of a login, it shouldn't get any longer
+pub const MAX_LENGTH: usize = 128;
+
+pub static PASSWORD_POLICY: LazyLock<PasswordPolicy> =
+    LazyLock::new(|| PasswordPolicy::from_env().expect("Could not load the password policy"));
+
+#[derive(Debug, Error, PartialEq, Eq)]
+pub enum PasswordPolicyError {
+    #[error("Password must be at least {0} characters long")]
+    TooShort(usize),
+    #[error("Password must be at most {0} characters long")]
+    TooLong(usize),
+    #[error("Password is known from a data breach, choose another one")]
+    Breached,
+}
+
+impl IntoResponse for PasswordPolicyError {
+    fn into_response(self) -> Response {
+        (
+            StatusCode::BAD_REQUEST,
+            Json(json!({ "message": self.to_string() })),
+        )
+            .into_response()
+    }
+}
+
+/// Checked when a password is chosen, never on login, so changing it doesn't lock anyone out
+pub struct PasswordPolicy {
+    min_length: usize,
+    /// Lowercased, breached passwords are tried with every casing
+    breached: HashSet<String>,
+}
+
+impl PasswordPolicy {
+    pub fn new<'a>(min_length: usize, breached: impl IntoIterator<Item = &'a str>) -> Self {
+        let breached = breached
+            .into_iter()
+            .map(str::trim)
+            .filter(|password| !password.is_empty())
+            .map(str::to_lowercase)
+            .collect();
+        Self {
+            min_length,
+            breached,
+        }
+    }
+
+    /// `PASSWORD_MIN_LENGTH` characters, and none of the passwords in the
+    /// `BREACHED_PASSWORDS_FILE`, one per line, besides the common ones
+    fn from_env() -> anyhow::Result<Self> {
+        let min_length = match var("PASSWORD_MIN_LENGTH") {
+            Ok(length) => length
+                .parse()
+                .context("PASSWORD_MIN_LENGTH must be a number")?,
+            Err(_) => DEFAULT_MIN_LENGTH,
+        };
+        let breached = match var("BREACHED_PASSWORDS_FILE") {
+            Ok(path) => fs::read_to_string(&path)
+                .with_context(|| format!("Could not read BREACHED_PASSWORDS_FILE {path}"))?,
+            Err(_) => String::new(),
+        };
+
+        Ok(Self::new(
+            min_length,
+            COMMON_PASSWORDS.lines().chain(breached.lines()),
+        ))
+    }
+
+    pub fn check(&self, password: &str) -> Result<(), PasswordPolicyError> {
+        let length = password.chars().count();
+        if length < self.min_length {
+            return Err(PasswordPolicyError::TooShort(self.min_length));
+        }
+        if length > MAX_LENGTH {
+            return Err(PasswordPolicyError::TooLong(MAX_LENGTH));
+        }
+        if self.breached.contains(&password.to_lowercase()) {
+            return Err(PasswordPolicyError::Breached);
+        }
+        Ok(())
+    }
+}
+
+pub fn check_password(password: &str) -> Result<(), PasswordPolicyError> {
+    PASSWORD_POLICY.check(password)
+}
@@ -0,0 +1,160 @@
+use axum::extract::State;
+use axum::http::header;
+use axum::{Json, response::IntoResponse};
+use chrono::{Duration, Utc};
+use serde::Deserialize;
+use tracing::error;
+
+use crate::api_utils::responses::{
+    INTERNAL_SERVER_ERROR, INVALID_EMAIL_TOKEN, TOO_MANY_RESET_REQUESTS,
+};
+use crate::db::operations::{
+    change_password, consume_email_token, get_user_by_email, get_user_by_id, insert_email_token,
+    revoke_all_sessions, set_email_verified,
+};
+use crate::db::password_hasher::hash_password;
+use crate::identifiers::Identifier;
+use crate::lockout::count_reset_request;
+use crate::mail::Mail;
+use crate::models::app_state::AppState;
+use crate::models::email_token::EmailTokenPurpose;
+use crate::models::user_info::UserInfo;
+use crate::password_policy::check_password;
+use crate::refresh::{generate_token, hash_token};
+use crate::sessions::{ClientInfo, tokens_expire_at};
+use crate::verify_email::APP_URL;
+
+const RESET_PASSWORD_MINUTES: i64 = 30;
+
+#[derive(Deserialize)]
+pub struct ForgotPasswordData {
+    email: String,
+}
+
+#[derive(Deserialize)]
+pub struct ResetPasswordData {
+    token: String,
+    new_password: String,
+}
+
+async fn send_reset_email(state: &AppState, user: &UserInfo) -> anyhow::Result<()> {
+    let (token, hash) = generate_token();
+    let expires_at = Utc::now() + Duration::minutes(RESET_PASSWORD_MINUTES);
+    insert_email_token(
+        &state.db,
+        &user.id,
+        EmailTokenPurpose::ResetPassword,
+        &hash,
+        expires_at,
+    )
+    .await?;
+
+    let mail = Mail {
+        to: user.email.clone(),
+        subject: "Reset your password".to_owned(),
+        body: format!(
+            "Hi {}, open this link to choose a new password:\n\n{}/reset-password?token={token}\n\nIt expires in {RESET_PASSWORD_MINUTES} minutes. If you didn't ask for it, ignore this email.",
+            user.username, *APP_URL
+        ),
+    };
+    state.mailer.send(&mail).await
+}
+
+pub async fn forgot_password(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    Json(data): Json<ForgotPasswordData>,
+) -> impl IntoResponse {
+    // Looked up like sign ins, emails from before the rules for new ones included
+    let email = match Identifier::parse(&data.email) {
+        Ok(Identifier::Email(email)) => Some(email),
+        _ => None,
+    };
+
+    // So nobody's mailbox can be flooded, nor every address tried from one client
+    match count_reset_request(&state.db, email.as_deref(), client.ip()).await {
+        Ok(None) => {}
+        Ok(Some(retry_after)) => {
+            return (
+                [(header::RETRY_AFTER, retry_after.to_string())],
+                TOO_MANY_RESET_REQUESTS,
+            )
+                .into_response();
+        }
+        Err(e) => {
+            error!("Failed to count reset requests: {}", e);
+            return INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+
+    // Answered the same way, and before any mail is sent, whether or not the
+    // email is registered, so it can't be used to find out
+    tokio::spawn(async move {
+        let Some(email) = email else {
+            return;
+        };
+        match get_user_by_email(&state.db, &email).await {
+            Ok(Some(user)) => {
+                if let Err(e) = send_reset_email(&state, &user).await {
+                    error!("Failed to send password reset email: {}", e);
+                }
+            }
+            Ok(None) => {}
+            Err(e) => error!("Failed to get user: {}", e),
+        }
+    });
+
+    Json("If the email is registered, a reset link has been sent to it").into_response()
+}
+
+pub async fn reset_password(
+    State(state): State<AppState>,
+    Json(data): Json<ResetPasswordData>,
+) -> impl IntoResponse {
+    // Before the token is used up, so another password can be tried with it
+    if let Err(e) = check_password(&data.new_password) {
+        return e.into_response();
+    }
+
+    let purpose = EmailTokenPurpose::ResetPassword;
+    let user_id = match consume_email_token(&state.db, purpose, &hash_token(&data.token)).await {
+        Ok(Some(user_id)) => user_id,
+        Ok(None) => return INVALID_EMAIL_TOKEN.into_response(),
+        Err(e) => {
+            error!("Failed to consume email token: {}", e);
+            return INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let user = match get_user_by_id(&state.db, &user_id).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return INVALID_EMAIL_TOKEN.into_response(),
+        Err(e) => {
+            error!("Failed to get user: {}", e);
+            return INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let hashed_password = match hash_password(&data.new_password).await {
+        Ok(p) => p,
+        Err(_) => return INTERNAL_SERVER_ERROR.into_response(),
+    };
+
+    if let Err(e) = change_password(&state.db, &user.username, &hashed_password).await {
+        error!("Failed to change password: {}", e);
+        return INTERNAL_SERVER_ERROR.into_response();
+    }
+
+    // Whoever may have had the old password is signed out
+    if let Err(e) = revoke_all_sessions(&state.db, &user.id, tokens_expire_at()).await {
+        error!("Failed to revoke sessions: {}", e);
+        return INTERNAL_SERVER_ERROR.into_response();
+    }
+
+    // Opening the link proves the email is theirs
+    if let Err(e) = set_email_verified(&state.db, &user.id).await {
+        error!("Failed to verify email: {}", e);
+    }
+
+    Json("Password reset").into_response()
+}
@@ -0,0 +1,103 @@
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use axum::extract::State;
+use axum::{Json, response::IntoResponse};
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use chrono::{Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use tracing::{error, warn};
+
+use crate::api_utils::responses::{INTERNAL_SERVER_ERROR, INVALID_REFRESH_TOKEN};
+use crate::db::operations::{RefreshOutcome, insert_refresh_token, rotate_refresh_token};
+use crate::jwt::generate_jwt;
+use crate::models::app_state::AppState;
+use crate::sessions::tokens_expire_at;
+
+pub(crate) const REFRESH_TOKEN_DAYS: i64 = 30;
+
+#[derive(Serialize)]
+struct RefreshResponse {
+    token: String,
+    refresh_token: String,
+}
+
+#[derive(Deserialize)]
+pub struct RefreshData {
+    refresh_token: String,
+}
+
+/// A random token, and the hash stored in its place. Mailed tokens are made the same way.
+pub(crate) fn generate_token() -> (String, String) {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    let token = URL_SAFE_NO_PAD.encode(bytes);
+    let hash = hash_token(&token);
+    (token, hash)
+}
+
+// Tokens are random, a fast hash is enough
+pub(crate) fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Starts the token family of a new session
+pub async fn issue_refresh_token(
+    db: &PgPool,
+    user_id: &str,
+    session_id: &str,
+) -> Result<String, sqlx::Error> {
+    let (token, hash) = generate_token();
+    let expires_at = Utc::now() + Duration::days(REFRESH_TOKEN_DAYS);
+
+    insert_refresh_token(db, user_id, session_id, &hash, expires_at).await?;
+
+    Ok(token)
+}
+
+pub async fn refresh_tokens(
+    State(state): State<AppState>,
+    Json(data): Json<RefreshData>,
+) -> impl IntoResponse {
+    let (refresh_token, new_hash) = generate_token();
+    let expires_at = Utc::now() + Duration::days(REFRESH_TOKEN_DAYS);
+
+    let outcome = match rotate_refresh_token(
+        &state.db,
+        &hash_token(&data.refresh_token),
+        &new_hash,
+        expires_at,
+        None,
+        tokens_expire_at(),
+    )
+    .await
+    {
+        Ok(outcome) => outcome,
+        Err(e) => {
+            error!("Could not rotate refresh token: {}", e);
+            return INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let (auth_info, session_id) = match outcome {
+        RefreshOutcome::Rotated {
+            user, session_id, ..
+        } => (user, session_id),
+        RefreshOutcome::Reused => {
+            warn!("Rotated refresh token was used again, revoked its session");
+            return INVALID_REFRESH_TOKEN.into_response();
+        }
+        RefreshOutcome::Invalid => return INVALID_REFRESH_TOKEN.into_response(),
+    };
+
+    let token = match generate_jwt(auth_info.id, session_id, auth_info.roles, &auth_info.scopes) {
+        Ok(t) => t,
+        Err(_) => return INTERNAL_SERVER_ERROR.into_response(),
+    };
+
+    Json(RefreshResponse {
+        token,
+        refresh_token,
+    })
+    .into_response()
+}
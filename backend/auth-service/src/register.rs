@@ -1,9 +1,15 @@
-use crate::api_utils::responses::{INTERNAL_SERVER_ERROR, USERNAME_ALREADY_USED};
+use crate::api_utils::responses::{
+    EMAIL_ALREADY_USED, INTERNAL_SERVER_ERROR, USERNAME_ALREADY_USED,
+};
 use crate::db::db_errors::UserInsertError;
 use crate::db::operations::insert_user;
 use crate::db::password_hasher::hash_password;
+use crate::identifiers::{display_form, normalize_email, normalize_username};
 use crate::jwt::generate_jwt;
 use crate::models::app_state::AppState;
+use crate::password_policy::check_password;
+use crate::sessions::{ClientInfo, start_session};
+use crate::verify_email::send_verification_email;
 
 use axum::extract::State;
 use axum::{Json, response::IntoResponse};
@@ -15,6 +21,7 @@ use tracing::error;
 #[derive(Serialize)]
 struct RegisterResponse {
     token: String,
+    refresh_token: String,
     user_id: String,
     username: String,
 }
@@ -29,8 +36,22 @@ pub struct RegisterData {
 
 pub async fn register_user(
     State(state): State<AppState>,
+    client: ClientInfo,
     Json(entering_user): Json<RegisterData>,
 ) -> impl IntoResponse {
+    let username_normalized = match normalize_username(&entering_user.username) {
+        Ok(username) => username,
+        Err(e) => return e.into_response(),
+    };
+    let email_normalized = match normalize_email(&entering_user.email) {
+        Ok(email) => email,
+        Err(e) => return e.into_response(),
+    };
+
+    if let Err(e) = check_password(&entering_user.password) {
+        return e.into_response();
+    }
+
     let hashed_password = match hash_password(&entering_user.password).await {
         Ok(p) => p,
         Err(_) => return INTERNAL_SERVER_ERROR.into_response(),
@@ -38,19 +59,32 @@ pub async fn register_user(
 
     let user_info = match insert_user(
         &state.db,
-        &entering_user.username,
+        &display_form(&entering_user.username),
+        &username_normalized,
         &hashed_password,
-        &entering_user.email,
+        &display_form(&entering_user.email),
+        &email_normalized,
         entering_user.telephone.as_deref(),
     )
     .await
     {
         Ok(user) => user,
         Err(UserInsertError::UsernameTaken) => return USERNAME_ALREADY_USED.into_response(),
+        Err(UserInsertError::EmailTaken) => return EMAIL_ALREADY_USED.into_response(),
         Err(UserInsertError::Database(_)) => return INTERNAL_SERVER_ERROR.into_response(),
     };
 
-    let token = match generate_jwt(user_info.id.to_string()) {
+    let (session_id, refresh_token) = match start_session(&state.db, &user_info.id, &client).await {
+        Ok(session) => session,
+        Err(_) => return INTERNAL_SERVER_ERROR.into_response(),
+    };
+
+    let token = match generate_jwt(
+        user_info.id.to_string(),
+        session_id,
+        user_info.roles.clone(),
+        &user_info.scopes,
+    ) {
         Ok(t) => t,
         Err(_) => return INTERNAL_SERVER_ERROR.into_response(),
     };
@@ -73,8 +107,18 @@ pub async fn register_user(
         return INTERNAL_SERVER_ERROR.into_response();
     }
 
+    // Registration doesn't wait on the mail server, the link can be sent again
+    let mail_state = state.clone();
+    let (user_id, email) = (user_info.id.clone(), user_info.email.clone());
+    tokio::spawn(async move {
+        if let Err(e) = send_verification_email(&mail_state, &user_id, &email).await {
+            error!("Failed to send verification email: {}", e);
+        }
+    });
+
     let response = RegisterResponse {
         token,
+        refresh_token,
         user_id: user_info.id.to_string(),
         username: user_info.username,
     };
@@ -0,0 +1,83 @@
+use std::env::var;
+use std::sync::LazyLock;
+
+use axum::extract::{Query, State};
+use axum::http::{HeaderMap, header};
+use axum::{Json, response::IntoResponse};
+use serde::{Deserialize, Serialize};
+use tracing::error;
+
+use crate::api_utils::responses::{INTERNAL_SERVER_ERROR, INVALID_TOKEN};
+use crate::db::operations::get_revoked_tokens_since;
+use crate::models::app_state::AppState;
+use crate::models::revoked_token::RevokedToken;
+use crate::refresh::hash_token;
+
+const PAGE_SIZE: i64 = 1000;
+
+/// Bearer token of the services allowed to poll revocations, as `REVOCATIONS_TOKEN`.
+/// Without it they aren't served to anyone.
+static REVOCATIONS_TOKEN: LazyLock<Option<String>> = LazyLock::new(|| {
+    var("REVOCATIONS_TOKEN")
+        .ok()
+        .filter(|token| !token.is_empty())
+});
+
+#[derive(Deserialize)]
+pub struct RevocationsQuery {
+    #[serde(default)]
+    since: i64,
+}
+
+#[derive(Serialize)]
+struct RevocationsResponse {
+    revoked: Vec<RevokedToken>,
+    /// Passed as `since` to get the next revocations. Those of transactions that were
+    /// still in progress are listed again, so consumers are caught up once it stays put.
+    cursor: i64,
+}
+
+/// Tokens revoked after `since` that haven't expired yet, for the services
+/// rejecting them.
+pub async fn list_revocations(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Query(query): Query<RevocationsQuery>,
+) -> impl IntoResponse {
+    if !is_authorized(&headers) {
+        return INVALID_TOKEN.into_response();
+    }
+
+    let revoked = match get_revoked_tokens_since(&state.db, query.since, PAGE_SIZE).await {
+        Ok(revoked) => revoked,
+        Err(e) => {
+            error!("Failed to get revoked tokens: {}", e);
+            return INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let cursor = next_cursor(query.since, &revoked);
+
+    Json(RevocationsResponse { revoked, cursor }).into_response()
+}
+
+/// Past the last transaction listed, but not past one that was in progress: it may
+/// commit after transactions that began later
+pub(crate) fn next_cursor(since: i64, revoked: &[RevokedToken]) -> i64 {
+    revoked.last().map_or(since, |token| {
+        token.xact_id.min(token.in_progress_from - 1).max(since)
+    })
+}
+
+fn is_authorized(headers: &HeaderMap) -> bool {
+    let bearer = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "));
+
+    match (REVOCATIONS_TOKEN.as_deref(), bearer) {
+        // Hashed first, so how long comparing takes doesn't tell how much matched
+        (Some(token), Some(bearer)) => hash_token(token) == hash_token(bearer),
+        _ => false,
+    }
+}
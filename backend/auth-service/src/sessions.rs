@@ -0,0 +1,222 @@
+use std::convert::Infallible;
+use std::env::var;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::LazyLock;
+
+use axum::extract::{ConnectInfo, FromRequestParts, Path, State};
+use axum::http::{HeaderMap, header, request::Parts};
+use axum::response::Response;
+use axum::{Json, response::IntoResponse};
+use chrono::{Duration, Utc};
+use ipnet::IpNet;
+use serde::Serialize;
+use sqlx::PgPool;
+use tracing::error;
+use uuid::Uuid;
+
+use crate::api_utils::responses::{INTERNAL_SERVER_ERROR, INVALID_TOKEN, SESSION_NOT_FOUND};
+use crate::db::operations::{
+    get_active_sessions, insert_session, is_session_active, revoke_other_sessions, revoke_session,
+};
+use crate::jwt::{ACCESS_TOKEN_MINUTES, Claims, bearer_claims};
+use crate::models::app_state::AppState;
+use crate::models::session::Session;
+use crate::refresh::issue_refresh_token;
+
+const DEVICE_HEADER: &str = "x-device-name";
+const MAX_DEVICE_LEN: usize = 200;
+
+/// Proxies allowed to set `X-Forwarded-For`, as a comma separated `TRUSTED_PROXIES`
+/// of addresses or networks, like the gateway's
+static TRUSTED_PROXIES: LazyLock<Vec<IpNet>> = LazyLock::new(|| {
+    var("TRUSTED_PROXIES")
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter_map(|proxy| {
+            proxy
+                .parse::<IpNet>()
+                .or_else(|_| proxy.parse::<IpAddr>().map(IpNet::from))
+                .ok()
+        })
+        .collect()
+});
+
+/// Where a client logs in from, as shown in its session
+pub struct ClientInfo {
+    device: Option<String>,
+    ip: Option<String>,
+}
+
+impl<S> FromRequestParts<S> for ClientInfo
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let header = |name| {
+            parts
+                .headers
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(str::trim)
+                .filter(|v| !v.is_empty())
+        };
+
+        let device = header(DEVICE_HEADER)
+            .or(header(header::USER_AGENT.as_str()))
+            .map(|device| device.chars().take(MAX_DEVICE_LEN).collect());
+
+        let peer = parts
+            .extensions
+            .get::<ConnectInfo<SocketAddr>>()
+            .map(|ConnectInfo(addr)| addr.ip());
+        let forwarded_for = header("x-forwarded-for");
+        let ip = peer.map(|peer| client_ip(peer, forwarded_for, &TRUSTED_PROXIES).to_string());
+
+        Ok(ClientInfo { device, ip })
+    }
+}
+
+impl ClientInfo {
+    pub fn ip(&self) -> Option<&str> {
+        self.ip.as_deref()
+    }
+}
+
+/// Walks `X-Forwarded-For` from the closest hop, skipping trusted proxies.
+/// Only a trusted peer can vouch for the header, anyone else could make it up.
+pub fn client_ip(peer: IpAddr, forwarded_for: Option<&str>, trusted_proxies: &[IpNet]) -> IpAddr {
+    let trusted = |ip: &IpAddr| trusted_proxies.iter().any(|net| net.contains(ip));
+    if !trusted(&peer) {
+        return peer;
+    }
+
+    let hops: Vec<IpAddr> = forwarded_for
+        .into_iter()
+        .flat_map(|v| v.split(','))
+        .filter_map(|hop| hop.trim().parse().ok())
+        .collect();
+
+    hops.iter()
+        .rev()
+        .find(|hop| !trusted(hop))
+        .or(hops.first())
+        .copied()
+        .unwrap_or(peer)
+}
+
+/// Records a new session, returning its id and its first refresh token
+pub async fn start_session(
+    db: &PgPool,
+    user_id: &str,
+    client: &ClientInfo,
+) -> Result<(String, String), sqlx::Error> {
+    let session_id = Uuid::new_v4().to_string();
+    insert_session(
+        db,
+        &session_id,
+        user_id,
+        client.device.as_deref(),
+        client.ip.as_deref(),
+    )
+    .await?;
+
+    let refresh_token = issue_refresh_token(db, user_id, &session_id).await?;
+
+    Ok((session_id, refresh_token))
+}
+
+/// Claims of the bearer token, as long as its session hasn't been revoked.
+/// Only first-party tokens are accepted, apps can't manage the account.
+pub(crate) async fn authenticate(db: &PgPool, headers: &HeaderMap) -> Result<Claims, Response> {
+    let Some(claims) = bearer_claims(headers).filter(|claims| claims.client_id.is_none()) else {
+        return Err(INVALID_TOKEN.into_response());
+    };
+
+    match is_session_active(db, &claims.sid).await {
+        Ok(true) => Ok(claims),
+        Ok(false) => Err(INVALID_TOKEN.into_response()),
+        Err(e) => {
+            error!("Failed to check session: {}", e);
+            Err(INTERNAL_SERVER_ERROR.into_response())
+        }
+    }
+}
+
+/// Access tokens of a revoked session are rejected until all of them have expired
+pub(crate) fn tokens_expire_at() -> chrono::DateTime<Utc> {
+    Utc::now() + Duration::minutes(ACCESS_TOKEN_MINUTES)
+}
+
+#[derive(Serialize)]
+struct SessionResponse {
+    #[serde(flatten)]
+    session: Session,
+    /// The session of the token listing them
+    current: bool,
+}
+
+pub async fn list_sessions(State(state): State<AppState>, headers: HeaderMap) -> impl IntoResponse {
+    let claims = match authenticate(&state.db, &headers).await {
+        Ok(claims) => claims,
+        Err(response) => return response,
+    };
+
+    let sessions = match get_active_sessions(&state.db, &claims.user_id).await {
+        Ok(sessions) => sessions,
+        Err(e) => {
+            error!("Failed to get sessions: {}", e);
+            return INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let sessions: Vec<_> = sessions
+        .into_iter()
+        .map(|session| SessionResponse {
+            current: session.id == claims.sid,
+            session,
+        })
+        .collect();
+
+    Json(sessions).into_response()
+}
+
+pub async fn sign_out_session(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Path(session_id): Path<String>,
+) -> impl IntoResponse {
+    let claims = match authenticate(&state.db, &headers).await {
+        Ok(claims) => claims,
+        Err(response) => return response,
+    };
+
+    match revoke_session(&state.db, &claims.user_id, &session_id, tokens_expire_at()).await {
+        Ok(true) => Json("Session signed out").into_response(),
+        Ok(false) => SESSION_NOT_FOUND.into_response(),
+        Err(e) => {
+            error!("Failed to revoke session: {}", e);
+            INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+pub async fn sign_out_other_sessions(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let claims = match authenticate(&state.db, &headers).await {
+        Ok(claims) => claims,
+        Err(response) => return response,
+    };
+
+    match revoke_other_sessions(&state.db, &claims.user_id, &claims.sid, tokens_expire_at()).await {
+        Ok(_) => Json("Other sessions signed out").into_response(),
+        Err(e) => {
+            error!("Failed to revoke sessions: {}", e);
+            INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
@@ -1,41 +1,129 @@
 use axum::extract::State;
-use axum::{Json, http::StatusCode, response::IntoResponse};
+use axum::response::Response;
+use axum::{
+    Json,
+    http::{StatusCode, header},
+    response::IntoResponse,
+};
 use serde::{Deserialize, Serialize};
 use serde_json::to_vec;
 use std::time::{SystemTime, UNIX_EPOCH};
 
-use crate::api_utils::responses::INTERNAL_SERVER_ERROR;
-use crate::db::operations::verify_user_credentials;
+use crate::api_utils::responses::{INTERNAL_SERVER_ERROR, TOO_MANY_LOGIN_ATTEMPTS};
+use crate::db::operations::{change_password, clear_login_failures, verify_user_credentials};
+use crate::db::password_hasher::{hash_password, needs_rehash};
+use crate::identifiers::Identifier;
 use crate::jwt::generate_jwt;
+use crate::lockout::{count_login_failure, login_blocked_for, user_key};
 use crate::models::app_state::AppState;
+use crate::models::auth_info::AuthInfo;
+use crate::sessions::{ClientInfo, start_session};
+use crate::two_factor::issue_login_challenge;
 use topic_structs::UserLoggedIn;
 use tracing::error;
 
 #[derive(Serialize)]
 struct SignInResponse {
     token: String,
+    refresh_token: String,
     user_id: String,
     username: String,
 }
 
+/// Exchanged, with a code, for the tokens at `/login/2fa`
+#[derive(Serialize)]
+struct ChallengeResponse {
+    two_factor_required: bool,
+    challenge_token: String,
+}
+
 #[derive(Deserialize)]
 pub struct SignInData {
-    username: String,
+    /// Username or email, clients from before only sent usernames
+    #[serde(alias = "username")]
+    identifier: String,
     password: String,
 }
 
 pub async fn sign_in_user(
     State(state): State<AppState>,
+    client: ClientInfo,
     Json(entering_user): Json<SignInData>,
 ) -> impl IntoResponse {
+    // No account could have it, so it's as wrong as any other credentials
+    let Ok(identifier) = Identifier::parse(&entering_user.identifier) else {
+        return (StatusCode::UNAUTHORIZED, "Invalid credentials").into_response();
+    };
+
+    match login_blocked_for(&state.db, &identifier, &client).await {
+        Ok(None) => {}
+        Ok(Some(retry_after)) => {
+            return (
+                [(header::RETRY_AFTER, retry_after.to_string())],
+                TOO_MANY_LOGIN_ATTEMPTS,
+            )
+                .into_response();
+        }
+        Err(e) => {
+            error!("Failed to check login failures: {}", e);
+            return INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+
     let auth_info =
-        match verify_user_credentials(&state.db, &entering_user.username, &entering_user.password)
-            .await
-        {
+        match verify_user_credentials(&state.db, &identifier, &entering_user.password).await {
             Some(info) => info,
-            None => return (StatusCode::UNAUTHORIZED, "Invalid credentials").into_response(),
+            None => {
+                if let Err(e) = count_login_failure(&state, &identifier, &client).await {
+                    error!("Failed to count login failure: {}", e);
+                }
+                return (StatusCode::UNAUTHORIZED, "Invalid credentials").into_response();
+            }
+        };
+
+    // Only now is the password at hand to upgrade hashes made with older settings
+    if needs_rehash(&auth_info.hashed_password) {
+        match hash_password(&entering_user.password).await {
+            Ok(hashed_password) => {
+                if let Err(e) =
+                    change_password(&state.db, &auth_info.username, &hashed_password).await
+                {
+                    error!("Failed to store rehashed password: {}", e);
+                }
+            }
+            Err(e) => error!("Failed to rehash password: {}", e),
+        }
+    }
+
+    if auth_info.totp_enabled {
+        // Failures are only cleared once the second factor is checked too
+        return match issue_login_challenge(&state.db, &auth_info.id, &identifier).await {
+            Ok(challenge_token) => Json(ChallengeResponse {
+                two_factor_required: true,
+                challenge_token,
+            })
+            .into_response(),
+            Err(e) => {
+                error!("Failed to issue login challenge: {}", e);
+                INTERNAL_SERVER_ERROR.into_response()
+            }
         };
+    }
+
+    // Only the identifier's failures, the client may still be trying others
+    if let Err(e) = clear_login_failures(&state.db, &user_key(&identifier)).await {
+        error!("Failed to clear login failures: {}", e);
+    }
 
+    complete_sign_in(&state, auth_info, &client).await
+}
+
+/// Publishes the login and starts a session, once every factor has been checked
+pub(crate) async fn complete_sign_in(
+    state: &AppState,
+    auth_info: AuthInfo,
+    client: &ClientInfo,
+) -> Response {
     let login_time = SystemTime::now()
         .duration_since(UNIX_EPOCH)
         .unwrap()
@@ -55,13 +143,24 @@ pub async fn sign_in_user(
         error!("The event logging couldn't be sent through Fluvio: {:?}", e);
     }
 
-    let token = match generate_jwt(auth_info.clone().id) {
+    let (session_id, refresh_token) = match start_session(&state.db, &auth_info.id, client).await {
+        Ok(session) => session,
+        Err(_) => return INTERNAL_SERVER_ERROR.into_response(),
+    };
+
+    let token = match generate_jwt(
+        auth_info.id.clone(),
+        session_id,
+        auth_info.roles.clone(),
+        &auth_info.scopes,
+    ) {
         Ok(t) => t,
         Err(_) => return INTERNAL_SERVER_ERROR.into_response(),
     };
 
     let response = SignInResponse {
         token,
+        refresh_token,
         user_id: auth_info.id,
         username: auth_info.username,
     };
@@ -0,0 +1,76 @@
+use argon2::{Algorithm, Params};
+use sqlx::PgPool;
+
+use crate::db::operations::{
+    change_password, delete_user, get_active_sessions, get_revoked_tokens_since, get_user_by_id,
+    revoke_all_sessions, verify_user_credentials,
+};
+use crate::db::password_hasher::Argon2Settings;
+use crate::identifiers::Identifier;
+use crate::sessions::tokens_expire_at;
+use crate::tests::support::{session, setup, user};
+
+// Cheap enough for tests
+fn hash(password: &str) -> String {
+    Argon2Settings::new(Algorithm::Argon2id, Params::new(64, 1, 1, None).unwrap())
+        .hash(password)
+        .unwrap()
+}
+
+#[sqlx::test(migrations = false)]
+async fn changed_passwords_replace_the_old_ones(pool: PgPool) {
+    setup(&pool).await;
+    let alice = user(&pool, "alice", &hash("correct horse battery staple")).await;
+    let identifier = Identifier::parse("alice").unwrap();
+
+    change_password(&pool, &alice.username, &hash("tr0ub4dor&3"))
+        .await
+        .unwrap();
+
+    assert!(
+        verify_user_credentials(&pool, &identifier, "tr0ub4dor&3")
+            .await
+            .is_some()
+    );
+    assert!(
+        verify_user_credentials(&pool, &identifier, "correct horse battery staple")
+            .await
+            .is_none()
+    );
+}
+
+#[sqlx::test(migrations = false)]
+async fn deleted_accounts_leave_their_sessions_revoked(pool: PgPool) {
+    setup(&pool).await;
+    let alice = user(&pool, "alice", "hash").await;
+    let bob = user(&pool, "bob", "hash").await;
+    let (laptop, _) = session(&pool, &alice.id, "laptop").await;
+    let (phone, _) = session(&pool, &alice.id, "phone").await;
+    let (desktop, _) = session(&pool, &bob.id, "desktop").await;
+
+    revoke_all_sessions(&pool, &alice.id, tokens_expire_at())
+        .await
+        .unwrap();
+    assert!(delete_user(&pool, &alice.username).await.unwrap());
+
+    assert!(get_user_by_id(&pool, &alice.id).await.unwrap().is_none());
+    assert!(
+        get_active_sessions(&pool, &alice.id)
+            .await
+            .unwrap()
+            .is_empty()
+    );
+    assert!(!delete_user(&pool, &alice.username).await.unwrap());
+
+    // Access tokens already issued are still rejected until they expire
+    let revoked: Vec<_> = get_revoked_tokens_since(&pool, 0, 100)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|token| token.jti)
+        .collect();
+    assert!(revoked.contains(&laptop));
+    assert!(revoked.contains(&phone));
+    assert!(!revoked.contains(&desktop));
+    assert_eq!(get_active_sessions(&pool, &bob.id).await.unwrap().len(), 1);
+}
@@ -0,0 +1,166 @@
+use sqlx::PgPool;
+
+use crate::db::init::init;
+use crate::db::operations::insert_user;
+use crate::identifiers::{
+    Identifier, IdentifierError, display_form, is_confusable, normalize_email, normalize_username,
+};
+use crate::tests::support::setup;
+
+#[test]
+fn usernames_compare_equal_however_written() {
+    assert_eq!(normalize_username("Alice"), Ok("alice".to_owned()));
+    assert_eq!(normalize_username("  ALICE "), Ok("alice".to_owned()));
+    // Fullwidth letters
+    assert_eq!(normalize_username("Ａｌｉｃｅ"), Ok("alice".to_owned()));
+    // Precomposed and combining accents
+    assert_eq!(
+        normalize_username("Jos\u{e9}"),
+        normalize_username("Jose\u{301}")
+    );
+    assert_eq!(display_form(" Ａｌｉｃｅ "), "Alice");
+}
+
+#[test]
+fn usernames_only_have_letters_digits_and_some_punctuation() {
+    assert_eq!(
+        normalize_username("dev_cord.42-x"),
+        Ok("dev_cord.42-x".to_owned())
+    );
+    assert_eq!(normalize_username("ñandú"), Ok("ñandú".to_owned()));
+    assert_eq!(
+        normalize_username("alice bob"),
+        Err(IdentifierError::InvalidUsername)
+    );
+    assert_eq!(
+        normalize_username("ali\u{200b}ce"),
+        Err(IdentifierError::InvalidUsername)
+    );
+    assert_eq!(
+        normalize_username(""),
+        Err(IdentifierError::InvalidUsername)
+    );
+}
+
+#[test]
+fn confusable_usernames_are_rejected() {
+    // Cyrillic `а` among Latin letters
+    assert_eq!(
+        normalize_username("p\u{430}ypal"),
+        Err(IdentifierError::Confusable)
+    );
+    // Only Cyrillic letters that pass for Latin ones
+    assert_eq!(
+        normalize_username("\u{440}\u{430}\u{443}\u{440}\u{430}\u{405}"),
+        Err(IdentifierError::Confusable)
+    );
+    assert!(!is_confusable("дмитрий"));
+    assert!(!is_confusable("αθηνά"));
+    assert!(!is_confusable("user42"));
+}
+
+#[test]
+fn emails_are_folded_and_checked_for_shape() {
+    assert_eq!(
+        normalize_email(" Alice@Example.COM "),
+        Ok("alice@example.com".to_owned())
+    );
+    assert_eq!(normalize_email("alice"), Err(IdentifierError::InvalidEmail));
+    assert_eq!(
+        normalize_email("alice@localhost"),
+        Err(IdentifierError::InvalidEmail)
+    );
+    assert_eq!(
+        normalize_email("al ice@example.com"),
+        Err(IdentifierError::InvalidEmail)
+    );
+    assert_eq!(
+        normalize_email("alice@ex\u{430}mple.com"),
+        Err(IdentifierError::Confusable)
+    );
+}
+
+#[test]
+fn identifiers_are_emails_when_they_have_an_at() {
+    assert_eq!(
+        Identifier::parse("Alice@Example.com"),
+        Ok(Identifier::Email("alice@example.com".to_owned()))
+    );
+    assert_eq!(
+        Identifier::parse("Alice"),
+        Ok(Identifier::Username("alice".to_owned()))
+    );
+}
+
+#[test]
+fn signing_in_only_folds_identifiers() {
+    // Registration rejects them, accounts from before may have them
+    assert_eq!(
+        Identifier::parse("Alice Bob"),
+        Ok(Identifier::Username("alice bob".to_owned()))
+    );
+    assert_eq!(
+        Identifier::parse("p\u{430}ypal"),
+        Ok(Identifier::Username("p\u{430}ypal".to_owned()))
+    );
+    assert_eq!(
+        Identifier::parse("Alice@Localhost"),
+        Ok(Identifier::Email("alice@localhost".to_owned()))
+    );
+    assert_eq!(
+        Identifier::parse("  "),
+        Err(IdentifierError::InvalidUsername)
+    );
+}
+
+#[sqlx::test(migrations = false)]
+async fn existing_users_are_normalized_around_clashes(pool: PgPool) {
+    setup(&pool).await;
+    for (username, email) in [
+        ("Alice", "Alice@Example.com"),
+        ("alice", "alice@example.com"),
+        ("bob", "bob@example.com"),
+    ] {
+        insert_user(&pool, username, username, "hash", email, email, None)
+            .await
+            .unwrap();
+    }
+    // As they were before normalized columns
+    for statement in [
+        "DROP INDEX users_username_normalized_idx, users_email_normalized_idx",
+        "ALTER TABLE users ALTER COLUMN username_normalized DROP NOT NULL, ALTER COLUMN email_normalized DROP NOT NULL",
+        "UPDATE users SET username_normalized = NULL, email_normalized = NULL",
+    ] {
+        sqlx::query(statement).execute(&pool).await.unwrap();
+    }
+
+    init(&pool).await.unwrap();
+
+    let users = sqlx::query_as::<_, (String, String, String)>(
+        "SELECT username, username_normalized, email_normalized FROM users ORDER BY username_normalized",
+    )
+    .fetch_all(&pool)
+    .await
+    .unwrap();
+    let usernames: Vec<_> = users.iter().map(|(_, normalized, _)| normalized).collect();
+    assert_eq!(usernames, ["alice", "alice-2", "bob"]);
+    // The one renamed is shown as renamed
+    assert_eq!(users[1].0.to_lowercase(), "alice-2");
+
+    let emails: Vec<_> = users.iter().map(|(_, _, email)| email).collect();
+    assert_eq!(
+        emails
+            .iter()
+            .filter(|email| **email == "alice@example.com")
+            .count(),
+        1
+    );
+    assert_eq!(
+        emails
+            .iter()
+            .filter(|email| email.starts_with("clash:"))
+            .count(),
+        1
+    );
+    assert_eq!(emails[2], "bob@example.com");
+}
@@ -0,0 +1,83 @@
+use std::net::IpAddr;
+
+use chrono::Duration;
+use sqlx::PgPool;
+
+use crate::db::operations::attempt_login_challenge;
+use crate::identifiers::Identifier;
+use crate::lockout::{block_for, count_reset_request};
+use crate::refresh::hash_token;
+use crate::sessions::client_ip;
+use crate::tests::support::{setup, user};
+use crate::two_factor::issue_login_challenge;
+
+#[test]
+fn failures_are_slowed_down_then_locked_out() {
+    let blocks: Vec<_> = (1..=10).map(block_for).collect();
+
+    assert_eq!(blocks[..3], [None, None, None]);
+    assert_eq!(blocks[3], Some(Duration::seconds(1)));
+    assert_eq!(blocks[4], Some(Duration::seconds(2)));
+    assert_eq!(blocks[8], Some(Duration::seconds(32)));
+    assert_eq!(blocks[9], Some(Duration::minutes(15)));
+}
+
+#[test]
+fn forwarded_for_is_trusted_only_from_proxies() {
+    let ip = |ip: &str| ip.parse::<IpAddr>().unwrap();
+    let gateway = ip("10.0.0.2");
+    let trusted = ["10.0.0.0/24".parse().unwrap()];
+
+    assert_eq!(
+        client_ip(ip("203.0.113.9"), Some("198.51.100.7"), &trusted),
+        ip("203.0.113.9")
+    );
+    assert_eq!(
+        client_ip(gateway, Some("192.0.2.1, 198.51.100.7, 10.0.0.5"), &trusted),
+        ip("198.51.100.7")
+    );
+    assert_eq!(client_ip(gateway, None, &trusted), gateway);
+}
+
+#[sqlx::test(migrations = false)]
+async fn reset_requests_are_limited_per_email_and_client(pool: PgPool) {
+    setup(&pool).await;
+    let count = |email, ip| count_reset_request(&pool, Some(email), Some(ip));
+
+    for ip in ["192.0.2.1", "192.0.2.2", "192.0.2.3"] {
+        assert_eq!(count("alice@example.com", ip).await.unwrap(), None);
+    }
+    let retry_after = count("alice@example.com", "192.0.2.4").await.unwrap();
+    assert!(retry_after.is_some_and(|seconds| seconds > 59 * 60));
+
+    // The last client can still ask for others, until it asked too many times too
+    assert_eq!(count("bob@example.com", "192.0.2.3").await.unwrap(), None);
+    assert_eq!(count("carol@example.com", "192.0.2.3").await.unwrap(), None);
+    assert!(
+        count("dave@example.com", "192.0.2.3")
+            .await
+            .unwrap()
+            .is_some()
+    );
+    assert_eq!(count("dave@example.com", "192.0.2.5").await.unwrap(), None);
+}
+
+#[sqlx::test(migrations = false)]
+async fn two_factor_challenges_count_against_the_sign_in_identifier(pool: PgPool) {
+    setup(&pool).await;
+    let alice = user(&pool, "alice", "hash").await;
+    let identifier = Identifier::parse("Alice@Example.com").unwrap();
+    let challenge = issue_login_challenge(&pool, &alice.id, &identifier)
+        .await
+        .unwrap();
+
+    for _ in 0..2 {
+        let attempt = attempt_login_challenge(&pool, &hash_token(&challenge), 2).await;
+        assert_eq!(
+            attempt.unwrap(),
+            Some((alice.id.clone(), "alice@example.com".to_string()))
+        );
+    }
+    let attempt = attempt_login_challenge(&pool, &hash_token(&challenge), 2).await;
+    assert_eq!(attempt.unwrap(), None);
+}
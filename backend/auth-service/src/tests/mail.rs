@@ -0,0 +1,66 @@
+use crate::mail::{Mail, MailTransport, file::FileMailer, memory::MemoryMailer};
+
+fn mail(to: &str, body: &str) -> Mail {
+    Mail {
+        to: to.to_owned(),
+        subject: "Verify your email".to_owned(),
+        body: body.to_owned(),
+    }
+}
+
+#[tokio::test]
+async fn memory_mailer_keeps_sent_mail() {
+    let mailer = MemoryMailer::default();
+    mailer
+        .send(&mail("alice@example.com", "first"))
+        .await
+        .unwrap();
+    mailer
+        .send(&mail("bob@example.com", "other"))
+        .await
+        .unwrap();
+    mailer
+        .send(&mail("alice@example.com", "second"))
+        .await
+        .unwrap();
+
+    assert_eq!(mailer.sent().len(), 3);
+    assert_eq!(
+        mailer.last_to("alice@example.com"),
+        Some(mail("alice@example.com", "second"))
+    );
+    assert_eq!(mailer.last_to("carol@example.com"), None);
+}
+
+#[tokio::test]
+async fn file_mailer_writes_a_file_per_mail() {
+    let dir = std::env::temp_dir().join(format!("mail-{}", uuid::Uuid::new_v4()));
+    let mailer = FileMailer::new(&dir).unwrap();
+    mailer
+        .send(&mail("alice@example.com", "first"))
+        .await
+        .unwrap();
+    mailer
+        .send(&mail("alice@example.com", "second"))
+        .await
+        .unwrap();
+
+    let mut files: Vec<_> = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    files.sort();
+    let sent: Vec<Mail> = files
+        .iter()
+        .map(|file| serde_json::from_slice(&std::fs::read(file).unwrap()).unwrap())
+        .collect();
+
+    assert_eq!(
+        sent,
+        [
+            mail("alice@example.com", "first"),
+            mail("alice@example.com", "second")
+        ]
+    );
+    std::fs::remove_dir_all(dir).unwrap();
+}
@@ -5,3 +5,36 @@ mod tests {
         assert_eq!(2 + 2, 4);
     }
 }
+
+#[cfg(test)]
+mod mail;
+
+#[cfg(test)]
+mod totp;
+
+#[cfg(test)]
+mod lockout;
+
+#[cfg(test)]
+mod password;
+
+#[cfg(test)]
+mod identifiers;
+
+#[cfg(test)]
+mod oauth;
+
+#[cfg(test)]
+mod support;
+
+#[cfg(test)]
+mod refresh;
+
+#[cfg(test)]
+mod sessions;
+
+#[cfg(test)]
+mod account;
+
+#[cfg(test)]
+mod revocations;
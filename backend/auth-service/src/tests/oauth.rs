@@ -0,0 +1,127 @@
+use axum::http::{HeaderMap, HeaderValue, header};
+
+use crate::models::authorization_code::AuthorizationCode;
+use crate::oauth::{
+    OAuthError, basic_credentials, check_code_exchange, parse_scope, redirect_with,
+    requested_scopes, valid_redirect_uri, verify_pkce,
+};
+
+#[test]
+fn pkce_verifier_must_hash_to_the_challenge() {
+    // From RFC 7636, appendix B
+    let verifier = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk";
+    let challenge = "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM";
+
+    assert!(verify_pkce(verifier, challenge));
+    assert!(!verify_pkce(
+        "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXK",
+        challenge
+    ));
+    // Too short to be a verifier, even if it hashed right
+    assert!(!verify_pkce("short", challenge));
+}
+
+#[test]
+fn codes_are_exchanged_with_their_redirect_uri() {
+    let verifier = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk";
+    let code = AuthorizationCode {
+        client_id: "app".to_owned(),
+        user_id: "alice".to_owned(),
+        redirect_uri: "https://app.example.com/callback".to_owned(),
+        scopes: vec!["profile".to_owned()],
+        code_challenge: "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM".to_owned(),
+    };
+    let redirect_uri = Some("https://app.example.com/callback");
+
+    assert_eq!(
+        check_code_exchange(&code, "app", redirect_uri, verifier),
+        Ok(())
+    );
+    assert!(matches!(
+        check_code_exchange(&code, "app", None, verifier),
+        Err(OAuthError::InvalidRequest(_))
+    ));
+    assert!(matches!(
+        check_code_exchange(
+            &code,
+            "app",
+            Some("https://app.example.com/other"),
+            verifier
+        ),
+        Err(OAuthError::InvalidGrant(_))
+    ));
+    assert!(matches!(
+        check_code_exchange(&code, "other-app", redirect_uri, verifier),
+        Err(OAuthError::InvalidGrant(_))
+    ));
+}
+
+#[test]
+fn scopes_are_limited_to_the_allowed_ones() {
+    let allowed = parse_scope("messages:read profile messages:read");
+    assert_eq!(allowed, ["messages:read", "profile"]);
+
+    assert_eq!(requested_scopes(None, &allowed), Ok(allowed.clone()));
+    assert_eq!(requested_scopes(Some("  "), &allowed), Ok(allowed.clone()));
+    assert_eq!(
+        requested_scopes(Some("profile"), &allowed),
+        Ok(vec!["profile".to_owned()])
+    );
+    assert_eq!(
+        requested_scopes(Some("profile messages:write"), &allowed),
+        Err(OAuthError::InvalidScope)
+    );
+}
+
+#[test]
+fn redirect_uris_must_be_precise() {
+    assert!(valid_redirect_uri("https://bot.example.com/callback"));
+    assert!(valid_redirect_uri("http://localhost:8080/callback"));
+    assert!(valid_redirect_uri("http://127.0.0.1/callback"));
+    assert!(valid_redirect_uri("com.example.app:/callback"));
+
+    assert!(!valid_redirect_uri("http://bot.example.com/callback"));
+    assert!(!valid_redirect_uri("http://localhost.evil.com/callback"));
+    assert!(!valid_redirect_uri(
+        "https://bot.example.com/callback#token"
+    ));
+    assert!(!valid_redirect_uri("https:///callback"));
+    assert!(!valid_redirect_uri("javascript:alert(1)"));
+    assert!(!valid_redirect_uri("myapp:/callback"));
+}
+
+#[test]
+fn redirects_carry_the_code_and_state() {
+    assert_eq!(
+        redirect_with(
+            "https://bot.example.com/callback",
+            &[("code", "abc")],
+            Some("x y&z")
+        ),
+        "https://bot.example.com/callback?code=abc&state=x+y%26z"
+    );
+    assert_eq!(
+        redirect_with(
+            "https://bot.example.com/callback?from=devcord",
+            &[("error", "access_denied")],
+            None
+        ),
+        "https://bot.example.com/callback?from=devcord&error=access_denied"
+    );
+}
+
+#[test]
+fn client_credentials_are_read_from_basic_auth() {
+    let mut headers = HeaderMap::new();
+    assert_eq!(basic_credentials(&headers), None);
+
+    // `client:s3cr:et`
+    headers.insert(
+        header::AUTHORIZATION,
+        HeaderValue::from_static("Basic Y2xpZW50OnMzY3I6ZXQ="),
+    );
+    assert_eq!(
+        basic_credentials(&headers),
+        Some(("client".to_owned(), "s3cr:et".to_owned()))
+    );
+}
@@ -0,0 +1,60 @@
+use argon2::{Algorithm, Params};
+
+use crate::db::password_hasher::{Argon2Settings, verify_password};
+use crate::password_policy::{MAX_LENGTH, PasswordPolicy, PasswordPolicyError};
+
+// Cheap enough for tests
+fn settings(algorithm: Algorithm, memory_kib: u32) -> Argon2Settings {
+    Argon2Settings::new(algorithm, Params::new(memory_kib, 1, 1, None).unwrap())
+}
+
+#[tokio::test]
+async fn hashes_verify_whatever_settings_made_them() {
+    let old = settings(Algorithm::Argon2i, 64);
+    let hash = old.hash("correct horse battery staple").unwrap();
+
+    assert!(hash.starts_with("$argon2i$v=19$m=64,t=1,p=1$"));
+    assert!(verify_password("correct horse battery staple", &hash).await);
+    assert!(!verify_password("correct horse battery stapler", &hash).await);
+}
+
+#[test]
+fn hashes_with_other_settings_need_rehash() {
+    let current = settings(Algorithm::Argon2id, 128);
+    let hash = current.hash("correct horse battery staple").unwrap();
+
+    assert!(current.is_current(&hash));
+    assert!(!settings(Algorithm::Argon2id, 64).is_current(&hash));
+    assert!(!settings(Algorithm::Argon2d, 128).is_current(&hash));
+}
+
+#[test]
+fn password_policy_rejects_short_long_and_breached() {
+    let policy = PasswordPolicy::new(10, ["Password123", "  letmeinplease  ", ""]);
+
+    assert_eq!(
+        policy.check("short"),
+        Err(PasswordPolicyError::TooShort(10))
+    );
+    assert_eq!(
+        policy.check(&"a".repeat(MAX_LENGTH + 1)),
+        Err(PasswordPolicyError::TooLong(MAX_LENGTH))
+    );
+    assert_eq!(
+        policy.check("PASSWORD123"),
+        Err(PasswordPolicyError::Breached)
+    );
+    assert_eq!(
+        policy.check("LetMeInPlease"),
+        Err(PasswordPolicyError::Breached)
+    );
+    assert_eq!(policy.check("correct horse battery staple"), Ok(()));
+}
+
+#[test]
+fn length_is_counted_in_characters() {
+    let policy = PasswordPolicy::new(8, []);
+
+    assert_eq!(policy.check("ñandúñandú"), Ok(()));
+    assert_eq!(policy.check("ñandú"), Err(PasswordPolicyError::TooShort(8)));
+}
@@ -0,0 +1,93 @@
+use chrono::{Duration, Utc};
+use sqlx::PgPool;
+
+use crate::db::operations::{
+    RefreshOutcome, get_revoked_tokens_since, is_session_active, rotate_refresh_token,
+};
+use crate::refresh::{generate_token, hash_token};
+use crate::sessions::tokens_expire_at;
+use crate::tests::support::{rotate, session, setup, user};
+
+#[sqlx::test(migrations = false)]
+async fn refresh_tokens_rotate_within_their_session(pool: PgPool) {
+    setup(&pool).await;
+    let alice = user(&pool, "alice", "hash").await;
+    let (session_id, first) = session(&pool, &alice.id, "laptop").await;
+
+    let (outcome, second) = rotate(&pool, &first).await;
+    let RefreshOutcome::Rotated {
+        user,
+        session_id: rotated_session,
+        ..
+    } = outcome
+    else {
+        panic!("first token wasn't rotated");
+    };
+    assert_eq!(user.id, alice.id);
+    assert_eq!(rotated_session, session_id);
+
+    assert!(matches!(
+        rotate(&pool, &second).await.0,
+        RefreshOutcome::Rotated { .. }
+    ));
+}
+
+#[sqlx::test(migrations = false)]
+async fn reused_refresh_tokens_revoke_their_family(pool: PgPool) {
+    setup(&pool).await;
+    let alice = user(&pool, "alice", "hash").await;
+    let (laptop, first) = session(&pool, &alice.id, "laptop").await;
+    let (_, other) = session(&pool, &alice.id, "phone").await;
+
+    let (_, second) = rotate(&pool, &first).await;
+    assert!(matches!(
+        rotate(&pool, &first).await.0,
+        RefreshOutcome::Reused
+    ));
+
+    // Access tokens of the session are rejected too
+    assert!(!is_session_active(&pool, &laptop).await.unwrap());
+    let revoked = get_revoked_tokens_since(&pool, 0, 10).await.unwrap();
+    assert!(revoked.iter().any(|token| token.jti == laptop));
+
+    // Whoever had rotated it is signed out too, but not the other sessions
+    assert!(matches!(
+        rotate(&pool, &second).await.0,
+        RefreshOutcome::Invalid
+    ));
+    assert!(matches!(
+        rotate(&pool, &other).await.0,
+        RefreshOutcome::Rotated { .. }
+    ));
+}
+
+#[sqlx::test(migrations = false)]
+async fn unknown_and_foreign_refresh_tokens_are_invalid(pool: PgPool) {
+    setup(&pool).await;
+    let alice = user(&pool, "alice", "hash").await;
+    let (_, token) = session(&pool, &alice.id, "laptop").await;
+
+    let (unknown, _) = generate_token();
+    assert!(matches!(
+        rotate(&pool, &unknown).await.0,
+        RefreshOutcome::Invalid
+    ));
+
+    // First-party tokens aren't good for apps
+    let (_, new_hash) = generate_token();
+    let outcome = rotate_refresh_token(
+        &pool,
+        &hash_token(&token),
+        &new_hash,
+        Utc::now() + Duration::days(1),
+        Some("some-app"),
+        tokens_expire_at(),
+    )
+    .await
+    .unwrap();
+    assert!(matches!(outcome, RefreshOutcome::Invalid));
+    assert!(matches!(
+        rotate(&pool, &token).await.0,
+        RefreshOutcome::Rotated { .. }
+    ));
+}
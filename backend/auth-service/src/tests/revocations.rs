@@ -0,0 +1,39 @@
+use chrono::{Duration, Utc};
+use sqlx::PgPool;
+
+use crate::db::operations::get_revoked_tokens_since;
+use crate::revocations::next_cursor;
+use crate::tests::support::{setup, user};
+
+async fn insert_revocation(conn: &mut sqlx::PgConnection, jti: &str, user_id: &str) {
+    sqlx::query("INSERT INTO revoked_tokens (jti, user_id, expires_at) VALUES ($1, $2, $3)")
+        .bind(jti)
+        .bind(user_id)
+        .bind(Utc::now() + Duration::minutes(15))
+        .execute(conn)
+        .await
+        .unwrap();
+}
+
+#[sqlx::test(migrations = false)]
+async fn revocations_committed_out_of_order_are_listed(pool: PgPool) {
+    setup(&pool).await;
+    let alice = user(&pool, "alice", "hash").await;
+
+    // The first one revoked is the last one committed
+    let mut first = pool.begin().await.unwrap();
+    insert_revocation(&mut first, "first", &alice.id).await;
+    let mut second = pool.begin().await.unwrap();
+    insert_revocation(&mut second, "second", &alice.id).await;
+    second.commit().await.unwrap();
+
+    let revoked = get_revoked_tokens_since(&pool, 0, 100).await.unwrap();
+    let jtis: Vec<_> = revoked.iter().map(|token| token.jti.as_str()).collect();
+    assert_eq!(jtis, ["second"]);
+    let cursor = next_cursor(0, &revoked);
+
+    first.commit().await.unwrap();
+
+    let revoked = get_revoked_tokens_since(&pool, cursor, 100).await.unwrap();
+    assert!(revoked.iter().any(|token| token.jti == "first"));
+}
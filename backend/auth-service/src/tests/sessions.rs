@@ -0,0 +1,104 @@
+use sqlx::PgPool;
+
+use crate::db::operations::{
+    RefreshOutcome, get_active_sessions, get_revoked_tokens_since, is_session_active,
+    revoke_other_sessions, revoke_session,
+};
+use crate::sessions::tokens_expire_at;
+use crate::tests::support::{rotate, session, setup, user};
+
+#[sqlx::test(migrations = false)]
+async fn sessions_are_listed_per_user(pool: PgPool) {
+    setup(&pool).await;
+    let alice = user(&pool, "alice", "hash").await;
+    let bob = user(&pool, "bob", "hash").await;
+    let (laptop, _) = session(&pool, &alice.id, "laptop").await;
+    let (phone, _) = session(&pool, &alice.id, "phone").await;
+    session(&pool, &bob.id, "desktop").await;
+
+    let mut sessions: Vec<_> = get_active_sessions(&pool, &alice.id)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|session| (session.id, session.device))
+        .collect();
+    sessions.sort();
+    let mut expected = vec![
+        (laptop, Some("laptop".to_string())),
+        (phone, Some("phone".to_string())),
+    ];
+    expected.sort();
+    assert_eq!(sessions, expected);
+}
+
+#[sqlx::test(migrations = false)]
+async fn revoked_sessions_lose_their_tokens(pool: PgPool) {
+    setup(&pool).await;
+    let alice = user(&pool, "alice", "hash").await;
+    let (laptop, laptop_token) = session(&pool, &alice.id, "laptop").await;
+    let (phone, _) = session(&pool, &alice.id, "phone").await;
+
+    assert!(
+        revoke_session(&pool, &alice.id, &laptop, tokens_expire_at())
+            .await
+            .unwrap()
+    );
+
+    assert!(!is_session_active(&pool, &laptop).await.unwrap());
+    assert!(is_session_active(&pool, &phone).await.unwrap());
+    assert!(matches!(
+        rotate(&pool, &laptop_token).await.0,
+        RefreshOutcome::Invalid
+    ));
+    // Its access tokens are rejected by the gateway until they expire
+    let revoked = get_revoked_tokens_since(&pool, 0, 100).await.unwrap();
+    assert!(revoked.iter().any(|token| token.jti == laptop));
+
+    let sessions = get_active_sessions(&pool, &alice.id).await.unwrap();
+    assert_eq!(sessions.len(), 1);
+    assert_eq!(sessions[0].id, phone);
+}
+
+#[sqlx::test(migrations = false)]
+async fn sessions_are_only_revoked_by_their_user(pool: PgPool) {
+    setup(&pool).await;
+    let alice = user(&pool, "alice", "hash").await;
+    let bob = user(&pool, "bob", "hash").await;
+    let (laptop, _) = session(&pool, &alice.id, "laptop").await;
+
+    assert!(
+        !revoke_session(&pool, &bob.id, &laptop, tokens_expire_at())
+            .await
+            .unwrap()
+    );
+    assert!(is_session_active(&pool, &laptop).await.unwrap());
+}
+
+#[sqlx::test(migrations = false)]
+async fn signing_out_other_sessions_keeps_the_current_one(pool: PgPool) {
+    setup(&pool).await;
+    let alice = user(&pool, "alice", "hash").await;
+    let bob = user(&pool, "bob", "hash").await;
+    let (laptop, laptop_token) = session(&pool, &alice.id, "laptop").await;
+    let (phone, phone_token) = session(&pool, &alice.id, "phone").await;
+    let (tablet, _) = session(&pool, &alice.id, "tablet").await;
+    let (desktop, _) = session(&pool, &bob.id, "desktop").await;
+
+    let revoked = revoke_other_sessions(&pool, &alice.id, &laptop, tokens_expire_at())
+        .await
+        .unwrap();
+    assert_eq!(revoked, 2);
+
+    assert!(is_session_active(&pool, &laptop).await.unwrap());
+    assert!(!is_session_active(&pool, &phone).await.unwrap());
+    assert!(!is_session_active(&pool, &tablet).await.unwrap());
+    assert!(is_session_active(&pool, &desktop).await.unwrap());
+    assert!(matches!(
+        rotate(&pool, &phone_token).await.0,
+        RefreshOutcome::Invalid
+    ));
+    assert!(matches!(
+        rotate(&pool, &laptop_token).await.0,
+        RefreshOutcome::Rotated { .. }
+    ));
+}
@@ -0,0 +1,58 @@
+use chrono::{Duration, Utc};
+use sqlx::PgPool;
+
+use crate::db::init::init;
+use crate::db::operations::{RefreshOutcome, insert_session, insert_user, rotate_refresh_token};
+use crate::models::user_info::UserInfo;
+use crate::refresh::{generate_token, hash_token, issue_refresh_token};
+use crate::sessions::tokens_expire_at;
+
+// Tests taking a pool run with `#[sqlx::test(migrations = false)]`, which makes
+// them a database of their own on the server at `DATABASE_URL`
+
+pub(super) async fn setup(pool: &PgPool) {
+    init(pool).await.unwrap();
+}
+
+pub(super) async fn user(pool: &PgPool, username: &str, hashed_password: &str) -> UserInfo {
+    let email = format!("{username}@example.com");
+    insert_user(
+        pool,
+        username,
+        username,
+        hashed_password,
+        &email,
+        &email,
+        None,
+    )
+    .await
+    .unwrap()
+}
+
+/// A first-party session of the user and its first refresh token
+pub(super) async fn session(pool: &PgPool, user_id: &str, device: &str) -> (String, String) {
+    let session_id = uuid::Uuid::new_v4().to_string();
+    insert_session(pool, &session_id, user_id, Some(device), None)
+        .await
+        .unwrap();
+    let refresh_token = issue_refresh_token(pool, user_id, &session_id)
+        .await
+        .unwrap();
+    (session_id, refresh_token)
+}
+
+/// Exchanges `refresh_token` as a first-party client, returning the new one
+pub(super) async fn rotate(pool: &PgPool, refresh_token: &str) -> (RefreshOutcome, String) {
+    let (new_token, new_hash) = generate_token();
+    let outcome = rotate_refresh_token(
+        pool,
+        &hash_token(refresh_token),
+        &new_hash,
+        Utc::now() + Duration::days(1),
+        None,
+        tokens_expire_at(),
+    )
+    .await
+    .unwrap();
+    (outcome, new_token)
+}
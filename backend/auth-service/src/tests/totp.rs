@@ -0,0 +1,56 @@
+use crate::totp::{
+    generate_recovery_codes, generate_secret, hash_recovery_code, matching_step, otpauth_uri,
+};
+use totp_rs::{Algorithm, Secret, TOTP};
+
+fn code_at(secret: &str, time: u64) -> String {
+    let secret = Secret::Encoded(secret.to_owned()).to_bytes().unwrap();
+    TOTP::new_unchecked(Algorithm::SHA1, 6, 0, 30, secret, None, String::new()).generate(time)
+}
+
+#[test]
+fn codes_are_accepted_within_a_step_of_skew() {
+    let secret = generate_secret();
+    let now = 1_700_000_000;
+    let step = (now / 30) as i64;
+
+    assert_eq!(
+        matching_step(&secret, &code_at(&secret, now), now),
+        Some(step)
+    );
+    assert_eq!(
+        matching_step(&secret, &code_at(&secret, now - 30), now),
+        Some(step - 1)
+    );
+    assert_eq!(
+        matching_step(&secret, &code_at(&secret, now + 30), now),
+        Some(step + 1)
+    );
+    assert_eq!(
+        matching_step(&secret, &code_at(&secret, now - 90), now),
+        None
+    );
+}
+
+#[test]
+fn otpauth_uri_names_the_issuer_and_account() {
+    let secret = generate_secret();
+    let uri = otpauth_uri(&secret, "alice").unwrap();
+
+    assert!(uri.starts_with("otpauth://totp/Devcord:alice?"));
+    assert!(uri.contains(&format!("secret={secret}")));
+}
+
+#[test]
+fn recovery_codes_are_unique_and_forgiving_to_type() {
+    let codes = generate_recovery_codes();
+    let mut unique = codes.clone();
+    unique.sort();
+    unique.dedup();
+    assert_eq!(unique.len(), codes.len());
+
+    let code = &codes[0];
+    let typed = code.replace('-', " ").to_uppercase();
+    assert_eq!(hash_recovery_code(code), hash_recovery_code(&typed));
+    assert_ne!(hash_recovery_code(code), hash_recovery_code(&codes[1]));
+}
@@ -0,0 +1,82 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use totp_rs::{Algorithm, Secret, TOTP};
+
+use crate::refresh::hash_token;
+
+const ISSUER: &str = "Devcord";
+const DIGITS: usize = 6;
+const STEP_SECONDS: u64 = 30;
+/// Steps accepted on either side of the current one, for clients whose clock is off
+const SKEW_STEPS: u64 = 1;
+const RECOVERY_CODES: usize = 10;
+
+/// A new random secret, base32 encoded as authenticator apps expect it
+pub fn generate_secret() -> String {
+    Secret::generate_secret().to_encoded().to_string()
+}
+
+fn totp(secret: &str, account: &str) -> Option<TOTP> {
+    let secret = Secret::Encoded(secret.to_owned()).to_bytes().ok()?;
+    Some(TOTP::new_unchecked(
+        Algorithm::SHA1,
+        DIGITS,
+        0,
+        STEP_SECONDS,
+        secret,
+        Some(ISSUER.to_owned()),
+        account.to_owned(),
+    ))
+}
+
+/// The `otpauth://` URI authenticator apps enroll with, usually shown as a QR code
+pub fn otpauth_uri(secret: &str, account: &str) -> Option<String> {
+    totp(secret, account).map(|totp| totp.get_url())
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// The time step `code` was generated for, if it's valid at `now`. Callers must
+/// reject steps that were already used, so that codes can't be replayed.
+pub fn matching_step(secret: &str, code: &str, now: u64) -> Option<i64> {
+    let totp = totp(secret, "")?;
+    let code: String = code.chars().filter(|c| !c.is_whitespace()).collect();
+
+    let current = now / STEP_SECONDS;
+    (current.saturating_sub(SKEW_STEPS)..=current + SKEW_STEPS)
+        .find(|step| totp.check(&code, step * STEP_SECONDS))
+        .map(|step| step as i64)
+}
+
+/// Single use codes for when the authenticator is lost, as in `1a2b-3c4d-5e6f-7a8b`
+pub fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODES)
+        .map(|_| {
+            let mut bytes = [0u8; 8];
+            OsRng.fill_bytes(&mut bytes);
+            hex::encode(bytes)
+                .as_bytes()
+                .chunks(4)
+                .map(|chunk| std::str::from_utf8(chunk).unwrap())
+                .collect::<Vec<_>>()
+                .join("-")
+        })
+        .collect()
+}
+
+/// Recovery codes are random, so like tokens they only need a fast hash.
+/// Dashes, spaces and case don't matter when typing them back.
+pub fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    hash_token(&normalized)
+}
@@ -0,0 +1,318 @@
+use axum::extract::State;
+use axum::http::{HeaderMap, header};
+use axum::{Json, response::IntoResponse};
+use chrono::{Duration, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tracing::error;
+
+use crate::api_utils::responses::{
+    INTERNAL_SERVER_ERROR, INVALID_LOGIN_CHALLENGE, INVALID_TOKEN, INVALID_TWO_FACTOR_CODE,
+    TOO_MANY_LOGIN_ATTEMPTS, TWO_FACTOR_ALREADY_ENABLED, TWO_FACTOR_NOT_STARTED,
+};
+use crate::db::operations::{
+    attempt_login_challenge, clear_login_failures, delete_login_challenge, disable_totp,
+    enable_totp, get_totp_secret, get_user_by_id, insert_login_challenge, set_pending_totp_secret,
+    use_recovery_code, use_totp_step,
+};
+use crate::identifiers::Identifier;
+use crate::lockout::{
+    account_identifier, check_account_password, count_login_failure, login_blocked_for, user_key,
+};
+use crate::models::app_state::AppState;
+use crate::refresh::{generate_token, hash_token};
+use crate::sessions::{ClientInfo, authenticate};
+use crate::sign_in::complete_sign_in;
+use crate::totp::{
+    generate_recovery_codes, generate_secret, hash_recovery_code, matching_step, now, otpauth_uri,
+};
+
+const CHALLENGE_MINUTES: i64 = 5;
+/// Six digits are quick to guess without a limit
+const MAX_CHALLENGE_ATTEMPTS: i32 = 5;
+
+#[derive(Serialize)]
+struct SetupResponse {
+    secret: String,
+    otpauth_uri: String,
+}
+
+#[derive(Serialize)]
+struct RecoveryCodesResponse {
+    recovery_codes: Vec<String>,
+}
+
+#[derive(Deserialize)]
+pub struct CodeData {
+    code: String,
+}
+
+#[derive(Deserialize)]
+pub struct DisableTwoFactorData {
+    password: String,
+    /// A TOTP or recovery code, while 2FA is enabled
+    code: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct TwoFactorSignInData {
+    challenge_token: String,
+    code: String,
+}
+
+/// Stands in for the tokens when signing in takes a second factor
+pub(crate) async fn issue_login_challenge(
+    db: &PgPool,
+    user_id: &str,
+    identifier: &Identifier,
+) -> Result<String, sqlx::Error> {
+    let (token, hash) = generate_token();
+    let expires_at = Utc::now() + Duration::minutes(CHALLENGE_MINUTES);
+    insert_login_challenge(db, user_id, identifier, &hash, expires_at).await?;
+    Ok(token)
+}
+
+async fn verify_totp(
+    db: &PgPool,
+    user_id: &str,
+    secret: &str,
+    code: &str,
+) -> Result<bool, sqlx::Error> {
+    match matching_step(secret, code, now()) {
+        Some(step) => use_totp_step(db, user_id, step).await,
+        None => Ok(false),
+    }
+}
+
+/// A TOTP code, or else one of the recovery codes
+async fn check_second_factor(db: &PgPool, user_id: &str, code: &str) -> Result<bool, sqlx::Error> {
+    let totp_valid = match get_totp_secret(db, user_id).await? {
+        Some(secret) => verify_totp(db, user_id, &secret, code).await?,
+        None => false,
+    };
+    if totp_valid {
+        return Ok(true);
+    }
+
+    use_recovery_code(db, user_id, &hash_recovery_code(code)).await
+}
+
+pub async fn start_two_factor_setup(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let claims = match authenticate(&state.db, &headers).await {
+        Ok(claims) => claims,
+        Err(response) => return response,
+    };
+
+    let user = match get_user_by_id(&state.db, &claims.user_id).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return INVALID_TOKEN.into_response(),
+        Err(e) => {
+            error!("Failed to get user: {}", e);
+            return INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    let secret = generate_secret();
+    let Some(otpauth_uri) = otpauth_uri(&secret, &user.username) else {
+        return INTERNAL_SERVER_ERROR.into_response();
+    };
+
+    match set_pending_totp_secret(&state.db, &user.id, &secret).await {
+        Ok(true) => Json(SetupResponse {
+            secret,
+            otpauth_uri,
+        })
+        .into_response(),
+        Ok(false) => TWO_FACTOR_ALREADY_ENABLED.into_response(),
+        Err(e) => {
+            error!("Failed to store TOTP secret: {}", e);
+            INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+pub async fn confirm_two_factor_setup(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    Json(data): Json<CodeData>,
+) -> impl IntoResponse {
+    let claims = match authenticate(&state.db, &headers).await {
+        Ok(claims) => claims,
+        Err(response) => return response,
+    };
+
+    let user = match get_user_by_id(&state.db, &claims.user_id).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return INVALID_TOKEN.into_response(),
+        Err(e) => {
+            error!("Failed to get user: {}", e);
+            return INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    if user.totp_enabled {
+        return TWO_FACTOR_ALREADY_ENABLED.into_response();
+    }
+
+    let secret = match get_totp_secret(&state.db, &user.id).await {
+        Ok(Some(secret)) => secret,
+        Ok(None) => return TWO_FACTOR_NOT_STARTED.into_response(),
+        Err(e) => {
+            error!("Failed to get TOTP secret: {}", e);
+            return INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    // The first code proves the authenticator was set up right
+    match verify_totp(&state.db, &user.id, &secret, &data.code).await {
+        Ok(true) => {}
+        Ok(false) => return INVALID_TWO_FACTOR_CODE.into_response(),
+        Err(e) => {
+            error!("Failed to verify TOTP code: {}", e);
+            return INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+
+    // Shown once, only their hashes are kept
+    let recovery_codes = generate_recovery_codes();
+    let hashes: Vec<String> = recovery_codes
+        .iter()
+        .map(|code| hash_recovery_code(code))
+        .collect();
+
+    if let Err(e) = enable_totp(&state.db, &user.id, &hashes).await {
+        error!("Failed to enable TOTP: {}", e);
+        return INTERNAL_SERVER_ERROR.into_response();
+    }
+
+    Json(RecoveryCodesResponse { recovery_codes }).into_response()
+}
+
+pub async fn disable_two_factor(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    client: ClientInfo,
+    Json(data): Json<DisableTwoFactorData>,
+) -> impl IntoResponse {
+    let claims = match authenticate(&state.db, &headers).await {
+        Ok(claims) => claims,
+        Err(response) => return response,
+    };
+
+    let user = match get_user_by_id(&state.db, &claims.user_id).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return INVALID_TOKEN.into_response(),
+        Err(e) => {
+            error!("Failed to get user: {}", e);
+            return INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    if let Err(response) = check_account_password(&state, &user, &data.password, &client).await {
+        return response;
+    }
+
+    // Or a stolen password and access token would be enough to turn it off
+    if user.totp_enabled {
+        let Some(code) = &data.code else {
+            return INVALID_TWO_FACTOR_CODE.into_response();
+        };
+        match check_second_factor(&state.db, &user.id, code).await {
+            Ok(true) => {}
+            Ok(false) => {
+                let identifier = account_identifier(&user);
+                if let Err(e) = count_login_failure(&state, &identifier, &client).await {
+                    error!("Failed to count login failure: {}", e);
+                }
+                return INVALID_TWO_FACTOR_CODE.into_response();
+            }
+            Err(e) => {
+                error!("Failed to check second factor: {}", e);
+                return INTERNAL_SERVER_ERROR.into_response();
+            }
+        }
+    }
+
+    if let Err(e) = disable_totp(&state.db, &user.id).await {
+        error!("Failed to disable TOTP: {}", e);
+        return INTERNAL_SERVER_ERROR.into_response();
+    }
+
+    Json("Two-factor authentication disabled").into_response()
+}
+
+pub async fn sign_in_with_two_factor(
+    State(state): State<AppState>,
+    client: ClientInfo,
+    Json(data): Json<TwoFactorSignInData>,
+) -> impl IntoResponse {
+    let challenge_hash = hash_token(&data.challenge_token);
+
+    let (user_id, identifier) =
+        match attempt_login_challenge(&state.db, &challenge_hash, MAX_CHALLENGE_ATTEMPTS).await {
+            Ok(Some(challenge)) => challenge,
+            Ok(None) => return INVALID_LOGIN_CHALLENGE.into_response(),
+            Err(e) => {
+                error!("Failed to check login challenge: {}", e);
+                return INTERNAL_SERVER_ERROR.into_response();
+            }
+        };
+    let Ok(identifier) = Identifier::parse(&identifier) else {
+        return INVALID_LOGIN_CHALLENGE.into_response();
+    };
+
+    // Locked out since the challenge was issued, or by guessing codes
+    match login_blocked_for(&state.db, &identifier, &client).await {
+        Ok(None) => {}
+        Ok(Some(retry_after)) => {
+            return (
+                [(header::RETRY_AFTER, retry_after.to_string())],
+                TOO_MANY_LOGIN_ATTEMPTS,
+            )
+                .into_response();
+        }
+        Err(e) => {
+            error!("Failed to check login failures: {}", e);
+            return INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+
+    let user = match get_user_by_id(&state.db, &user_id).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return INVALID_LOGIN_CHALLENGE.into_response(),
+        Err(e) => {
+            error!("Failed to get user: {}", e);
+            return INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    match check_second_factor(&state.db, &user.id, &data.code).await {
+        Ok(true) => {}
+        Ok(false) => {
+            // Like wrong passwords, so new challenges can't be used to keep guessing
+            if let Err(e) = count_login_failure(&state, &identifier, &client).await {
+                error!("Failed to count login failure: {}", e);
+            }
+            return INVALID_TWO_FACTOR_CODE.into_response();
+        }
+        Err(e) => {
+            error!("Failed to check second factor: {}", e);
+            return INTERNAL_SERVER_ERROR.into_response();
+        }
+    }
+
+    // Only the identifier's failures, the client may still be trying others
+    if let Err(e) = clear_login_failures(&state.db, &user_key(&identifier)).await {
+        error!("Failed to clear login failures: {}", e);
+    }
+
+    if let Err(e) = delete_login_challenge(&state.db, &challenge_hash).await {
+        error!("Failed to delete login challenge: {}", e);
+        return INTERNAL_SERVER_ERROR.into_response();
+    }
+
+    complete_sign_in(&state, user, &client).await
+}
@@ -0,0 +1,117 @@
+use std::env::var;
+use std::sync::LazyLock;
+
+use axum::extract::State;
+use axum::http::HeaderMap;
+use axum::{Json, response::IntoResponse};
+use chrono::{Duration, Utc};
+use serde::Deserialize;
+use tracing::error;
+
+use crate::api_utils::responses::{
+    EMAIL_ALREADY_VERIFIED, INTERNAL_SERVER_ERROR, INVALID_EMAIL_TOKEN, INVALID_TOKEN,
+};
+use crate::db::operations::{
+    consume_email_token, get_user_info, insert_email_token, set_email_verified,
+};
+use crate::mail::Mail;
+use crate::models::app_state::AppState;
+use crate::models::email_token::EmailTokenPurpose;
+use crate::refresh::{generate_token, hash_token};
+use crate::sessions::authenticate;
+
+const VERIFY_EMAIL_HOURS: i64 = 24;
+
+/// The frontend, where mailed links lead
+pub(crate) static APP_URL: LazyLock<String> = LazyLock::new(|| {
+    var("APP_URL")
+        .unwrap_or("http://localhost:4200".to_owned())
+        .trim()
+        .trim_end_matches('/')
+        .to_string()
+});
+
+#[derive(Deserialize)]
+pub struct VerifyEmailData {
+    token: String,
+}
+
+/// Mails a link to verify `email`, the links mailed before stop working
+pub async fn send_verification_email(
+    state: &AppState,
+    user_id: &str,
+    email: &str,
+) -> anyhow::Result<()> {
+    let (token, hash) = generate_token();
+    let expires_at = Utc::now() + Duration::hours(VERIFY_EMAIL_HOURS);
+    insert_email_token(
+        &state.db,
+        user_id,
+        EmailTokenPurpose::VerifyEmail,
+        &hash,
+        expires_at,
+    )
+    .await?;
+
+    let mail = Mail {
+        to: email.to_owned(),
+        subject: "Verify your email".to_owned(),
+        body: format!(
+            "Open this link to verify your email address:\n\n{}/verify-email?token={token}\n\nIt expires in {VERIFY_EMAIL_HOURS} hours.",
+            *APP_URL
+        ),
+    };
+    state.mailer.send(&mail).await
+}
+
+pub async fn verify_email(
+    State(state): State<AppState>,
+    Json(data): Json<VerifyEmailData>,
+) -> impl IntoResponse {
+    let purpose = EmailTokenPurpose::VerifyEmail;
+    let user_id = match consume_email_token(&state.db, purpose, &hash_token(&data.token)).await {
+        Ok(Some(user_id)) => user_id,
+        Ok(None) => return INVALID_EMAIL_TOKEN.into_response(),
+        Err(e) => {
+            error!("Failed to consume email token: {}", e);
+            return INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    if let Err(e) = set_email_verified(&state.db, &user_id).await {
+        error!("Failed to verify email: {}", e);
+        return INTERNAL_SERVER_ERROR.into_response();
+    }
+
+    Json("Email verified").into_response()
+}
+
+pub async fn resend_verification_email(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+) -> impl IntoResponse {
+    let claims = match authenticate(&state.db, &headers).await {
+        Ok(claims) => claims,
+        Err(response) => return response,
+    };
+
+    let user = match get_user_info(&state.db, &claims.user_id).await {
+        Ok(Some(user)) => user,
+        Ok(None) => return INVALID_TOKEN.into_response(),
+        Err(e) => {
+            error!("Failed to get user: {}", e);
+            return INTERNAL_SERVER_ERROR.into_response();
+        }
+    };
+
+    if user.email_verified {
+        return EMAIL_ALREADY_VERIFIED.into_response();
+    }
+
+    if let Err(e) = send_verification_email(&state, &user.id, &user.email).await {
+        error!("Failed to send verification email: {}", e);
+        return INTERNAL_SERVER_ERROR.into_response();
+    }
+
+    Json("Verification email sent").into_response()
+}
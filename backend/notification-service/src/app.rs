@@ -79,6 +79,23 @@ pub async fn app() -> anyhow::Result<Router> {
         "USER_ANSWER_TOPIC",
         "friendship_answered",
     )));
+    let channels_c = channels.clone();
+    let addr_c = addr.clone();
+    handles.push(tokio::spawn(fluvio_reader::run::<
+        topic_structs::UserLockedOut,
+    >(
+        channels_c,
+        addr_c,
+        "AUTH_LOCKOUT_TOPIC",
+        "account_locked",
+    )));
+    let channels_c = channels.clone();
+    let addr_c = addr.clone();
+    handles.push(tokio::spawn(fluvio_reader::run_deletions(
+        channels_c,
+        addr_c,
+        "AUTH_DELETE_TOPIC",
+    )));
 
     let router: Router = Router::new()
         .route("/", get(notification_handler))
@@ -4,10 +4,11 @@ use async_std::stream::StreamExt;
 use axum::extract::ws::Message;
 use dashmap::DashMap;
 use fluvio::{
-    FluvioConfig, Offset, consumer::ConsumerConfigExtBuilder, metadata::topic::TopicSpec,
+    Fluvio, FluvioConfig, Offset, consumer::ConsumerConfigExtBuilder, metadata::topic::TopicSpec,
 };
 use serde::{Deserialize, Serialize};
 use serde_json::{Error, from_slice, to_string};
+use topic_structs::UserDeleted;
 
 use crate::app::ResponseSender;
 
@@ -20,21 +21,12 @@ where
     pub info: T,
 }
 
-pub async fn run<T>(
-    senders: Arc<DashMap<String, ResponseSender>>,
-    addr: String,
-    topic_env: &str,
-    obj_prefix: &str,
-) -> anyhow::Result<()>
-where
-    T: for<'a> Deserialize<'a> + Serialize,
-{
-    let topic = env::var(topic_env).unwrap_or_else(|_| panic!("Topic env not set: {topic_env}"));
-
+/// Connects to Fluvio, creating `topic` if it doesn't exist yet
+async fn connect(addr: String, topic: &str) -> anyhow::Result<Fluvio> {
     let mut fluvio_config = FluvioConfig::new(addr);
     fluvio_config.use_spu_local_address = true;
 
-    let fluvio = fluvio::Fluvio::connect_with_config(&fluvio_config).await?;
+    let fluvio = Fluvio::connect_with_config(&fluvio_config).await?;
 
     let admin = fluvio.admin().await;
 
@@ -52,6 +44,21 @@ where
         admin.create(topic.to_string(), false, topic_spec).await?;
     }
 
+    Ok(fluvio)
+}
+
+pub async fn run<T>(
+    senders: Arc<DashMap<String, ResponseSender>>,
+    addr: String,
+    topic_env: &str,
+    obj_prefix: &str,
+) -> anyhow::Result<()>
+where
+    T: for<'a> Deserialize<'a> + Serialize,
+{
+    let topic = env::var(topic_env).unwrap_or_else(|_| panic!("Topic env not set: {topic_env}"));
+    let fluvio = connect(addr, &topic).await?;
+
     let consumer_config = ConsumerConfigExtBuilder::default()
         .topic(topic)
         .offset_start(Offset::beginning())
@@ -93,3 +100,37 @@ where
 
     Ok(())
 }
+
+/// Closes the sockets of deleted users, nothing is left to notify them about
+pub async fn run_deletions(
+    senders: Arc<DashMap<String, ResponseSender>>,
+    addr: String,
+    topic_env: &str,
+) -> anyhow::Result<()> {
+    let topic = env::var(topic_env).unwrap_or_else(|_| panic!("Topic env not set: {topic_env}"));
+    let fluvio = connect(addr, &topic).await?;
+
+    // Only users deleted from now on can still be connected
+    let consumer_config = ConsumerConfigExtBuilder::default()
+        .topic(topic)
+        .offset_start(Offset::end())
+        .build()
+        .expect("Failed to build consumer config");
+
+    let mut listener = fluvio.consumer_with_config(consumer_config).await?;
+
+    while let Some(Ok(record)) = listener.next().await {
+        let Ok(deleted) = from_slice::<UserDeleted>(record.value()) else {
+            continue;
+        };
+
+        // Its socket is closed once the sender is dropped
+        let Some((_, sender)) = senders.remove(&deleted.id) else {
+            continue;
+        };
+
+        sender.send(Message::Close(None)).await.ok();
+    }
+
+    Ok(())
+}
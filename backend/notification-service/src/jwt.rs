@@ -1,4 +1,9 @@
-use std::sync::LazyLock;
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    sync::LazyLock,
+    time::{Duration, Instant},
+};
 
 use axum::{
     Json, RequestPartsExt,
@@ -10,31 +15,108 @@ use axum_extra::{
     TypedHeader,
     headers::{Authorization, authorization::Bearer},
 };
-use jsonwebtoken::{DecodingKey, Validation, decode};
+use jsonwebtoken::{
+    Algorithm, DecodingKey, Validation, decode, decode_header,
+    jwk::{AlgorithmParameters, JwkSet},
+};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+use tokio::sync::{Mutex, RwLock};
 
 static KEYS: LazyLock<Keys> = LazyLock::new(|| {
-    let secret = std::env::var("JWT_SECRET").expect("JWT_SECRET must be set");
-    Keys::new(secret.as_bytes())
+    let url = std::env::var("JWKS_URL").expect("JWKS_URL must be set");
+    Keys::new(url)
 });
 
+/// Keys are fetched again after this long, so removed ones stop being trusted
+const MAX_AGE: Duration = Duration::from_secs(300);
+/// Minimum time between fetches triggered by unknown `kid`s
+const REFRESH_COOLDOWN: Duration = Duration::from_secs(10);
+
+/// Public keys published by auth-service, cached by `kid`. An unknown `kid`
+/// fetches them again, which picks up keys added while rotating.
 struct Keys {
-    decoding: DecodingKey,
+    url: String,
+    client: reqwest::Client,
+    decoding: RwLock<HashMap<String, (DecodingKey, Algorithm)>>,
+    fetched_at: Mutex<Option<Instant>>,
 }
 
 impl Keys {
-    fn new(secret: &[u8]) -> Self {
+    fn new(url: String) -> Self {
         Self {
-            decoding: DecodingKey::from_secret(secret),
+            url,
+            client: reqwest::Client::new(),
+            decoding: RwLock::new(HashMap::new()),
+            fetched_at: Mutex::new(None),
+        }
+    }
+
+    async fn get(&self, kid: &str) -> Option<(DecodingKey, Algorithm)> {
+        let stale = self
+            .fetched_at
+            .lock()
+            .await
+            .is_none_or(|fetched_at| fetched_at.elapsed() >= MAX_AGE);
+        if !stale {
+            if let Some(key) = self.decoding.read().await.get(kid) {
+                return Some(key.clone());
+            }
+        }
+
+        self.refresh(if stale { MAX_AGE } else { REFRESH_COOLDOWN })
+            .await;
+        self.decoding.read().await.get(kid).cloned()
+    }
+
+    async fn refresh(&self, cooldown: Duration) {
+        // Held while fetching, so concurrent requests don't fetch again
+        let mut fetched_at = self.fetched_at.lock().await;
+        if fetched_at.is_some_and(|fetched_at| fetched_at.elapsed() < cooldown) {
+            return;
+        }
+        *fetched_at = Some(Instant::now());
+
+        match self.fetch().await {
+            Ok(keys) => *self.decoding.write().await = keys,
+            // The keys we have are kept until auth-service is back
+            Err(e) => tracing::error!("Could not fetch JWKS from {}: {e}", self.url),
         }
     }
+
+    async fn fetch(&self) -> anyhow::Result<HashMap<String, (DecodingKey, Algorithm)>> {
+        let set: JwkSet = self
+            .client
+            .get(&self.url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        let keys = set
+            .keys
+            .iter()
+            // A published symmetric key would let anyone sign tokens
+            .filter(|jwk| !matches!(jwk.algorithm, AlgorithmParameters::OctetKey(_)))
+            .filter_map(|jwk| {
+                let kid = jwk.common.key_id.clone()?;
+                let algorithm = Algorithm::from_str(&jwk.common.key_algorithm?.to_string()).ok()?;
+                Some((kid, (DecodingKey::from_jwk(jwk).ok()?, algorithm)))
+            })
+            .collect();
+
+        Ok(keys)
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub(crate) struct Claims {
     exp: u64,
     pub user_id: String,
+    /// Set on tokens of OAuth apps, which no route here grants a scope to
+    #[serde(default)]
+    client_id: Option<String>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -53,7 +135,7 @@ where
         if let Ok(TypedHeader(bearer)) = parts.extract::<TypedHeader<Authorization<Bearer>>>().await
         {
             let jwt = bearer.token().to_string();
-            let claims = decode_token(&jwt)?;
+            let claims = decode_token(&jwt).await?;
             return Ok(Authenticated { claims, jwt });
         }
 
@@ -61,7 +143,7 @@ where
         if let Some(protocol_header) = parts.headers.get(header::SEC_WEBSOCKET_PROTOCOL) {
             if let Ok(protocols) = protocol_header.to_str() {
                 if let Some(jwt) = protocols.split(',').map(|s| s.trim()).next() {
-                    let claims = decode_token(jwt)?;
+                    let claims = decode_token(jwt).await?;
                     return Ok(Authenticated {
                         claims,
                         jwt: jwt.to_string(),
@@ -74,10 +156,22 @@ where
     }
 }
 
-fn decode_token(token: &str) -> Result<Claims, AuthError> {
-    decode::<Claims>(token, &KEYS.decoding, &Validation::default())
+async fn decode_token(token: &str) -> Result<Claims, AuthError> {
+    let kid = decode_header(token)
+        .map_err(|_| AuthError::InvalidToken)?
+        .kid
+        .ok_or(AuthError::InvalidToken)?;
+    let (key, algorithm) = KEYS.get(&kid).await.ok_or(AuthError::InvalidToken)?;
+    let claims = decode::<Claims>(token, &key, &Validation::new(algorithm))
         .map(|data| data.claims)
-        .map_err(|_| AuthError::InvalidToken)
+        .map_err(|_| AuthError::InvalidToken)?;
+
+    // Denied by default, like routes without scopes at the gateway
+    if claims.client_id.is_some() {
+        return Err(AuthError::NotForApps);
+    }
+
+    Ok(claims)
 }
 
 //This should be a common crate for all services, dead code is allowed to preserve the common structure
@@ -88,6 +182,7 @@ pub enum AuthError {
     MissingCredentials,
     TokenCreation,
     InvalidToken,
+    NotForApps,
 }
 
 impl IntoResponse for AuthError {
@@ -97,6 +192,7 @@ impl IntoResponse for AuthError {
             AuthError::MissingCredentials => (StatusCode::BAD_REQUEST, "Missing credentials"),
             AuthError::TokenCreation => (StatusCode::INTERNAL_SERVER_ERROR, "Token creation error"),
             AuthError::InvalidToken => (StatusCode::BAD_REQUEST, "Invalid token"),
+            AuthError::NotForApps => (StatusCode::FORBIDDEN, "Not available to apps"),
         };
         let body = Json(json!({
             "error": error_message,
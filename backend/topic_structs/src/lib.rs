@@ -24,6 +24,23 @@ pub struct UserLoggedOut {
     pub logout_time: i64,
 }
 
+/// Too many failed logins, signing in is blocked until `locked_until`
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct UserLockedOut {
+    pub id: String,
+    pub username: String,
+    pub locked_until: i64,
+    /// The client that failed last
+    pub ip: Option<String>,
+}
+
+/// The user and everything other services store about them must be removed
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct UserDeleted {
+    pub id: String,
+    pub deletion_time: i64,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct FriendRequestCreated {
     pub from_username: String,
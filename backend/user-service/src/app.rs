@@ -89,6 +89,11 @@ pub async fn app() -> anyhow::Result<(Router, Fluvio, sqlx::PgPool)> {
         .trim()
         .to_string();
 
+    let auth_deleted_consumer_topic = var("AUTH_DELETE_TOPIC")
+        .unwrap_or("auth-delete".to_owned())
+        .trim()
+        .to_string();
+
     let request_producer_topic = var("USER_RESQUEST_TOPIC")
         .unwrap_or("friendships-request".to_owned())
         .trim()
@@ -133,6 +138,13 @@ pub async fn app() -> anyhow::Result<(Router, Fluvio, sqlx::PgPool)> {
             .await?;
     }
 
+    if !topic_names.contains(&auth_deleted_consumer_topic) {
+        let topic_spec = TopicSpec::new_computed(1, 1, None);
+        admin
+            .create(auth_deleted_consumer_topic.clone(), false, topic_spec)
+            .await?;
+    }
+
     let request_producer = fluvio.topic_producer(request_producer_topic).await?;
 
     let answered_producer = fluvio.topic_producer(answered_producer_topic).await?;
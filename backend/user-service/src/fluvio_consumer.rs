@@ -2,14 +2,23 @@ use async_std::stream::StreamExt;
 use dotenvy::var;
 use fluvio::{Fluvio, Offset, consumer::ConsumerConfigExtBuilder};
 use serde_json::from_slice;
-use topic_structs::UserCreated;
+use topic_structs::{UserCreated, UserDeleted};
 
 use crate::{
     api_utils::structs::PrivateUser,
-    sql_utils::calls::{get_public_user, insert_user},
+    sql_utils::calls::{delete_user, get_public_user, insert_user},
 };
 
 pub async fn run(fluvio: Fluvio, db: sqlx::PgPool) -> anyhow::Result<()> {
+    tokio::try_join!(
+        consume_registrations(&fluvio, &db),
+        consume_deletions(&fluvio, &db)
+    )?;
+
+    Ok(())
+}
+
+async fn consume_registrations(fluvio: &Fluvio, db: &sqlx::PgPool) -> anyhow::Result<()> {
     //TODO! do a proper fix on this
     let auth_registered_consumer_topic = var("AUTH_REGISTER_TOPIC")
         .unwrap_or("auth-register".to_owned())
@@ -33,12 +42,12 @@ pub async fn run(fluvio: Fluvio, db: sqlx::PgPool) -> anyhow::Result<()> {
                 username: user_created.username.clone(),
                 created_at: None,
             };
-            if get_public_user(&user.id, &db).await.is_some() {
+            if get_public_user(&user.id, db).await.is_some() {
                 //TODO! User already exists, big time error
                 continue;
             }
 
-            if insert_user(user, &db).await.is_err() {
+            if insert_user(user, db).await.is_err() {
                 //TODO! IDK, panic I guess
             }
         }
@@ -46,3 +55,32 @@ pub async fn run(fluvio: Fluvio, db: sqlx::PgPool) -> anyhow::Result<()> {
 
     Ok(())
 }
+
+async fn consume_deletions(fluvio: &Fluvio, db: &sqlx::PgPool) -> anyhow::Result<()> {
+    let auth_deleted_consumer_topic = var("AUTH_DELETE_TOPIC")
+        .unwrap_or("auth-delete".to_owned())
+        .trim()
+        .to_string();
+
+    // Replayed like registrations, the users deleted leave a tombstone so their
+    // registration is skipped whichever of both comes first
+    let consumer_config = ConsumerConfigExtBuilder::default()
+        .topic(auth_deleted_consumer_topic)
+        .offset_start(Offset::beginning())
+        .build()
+        .expect("Failed to build consumer config");
+
+    let mut consumer_stream = fluvio.consumer_with_config(consumer_config).await?;
+
+    while let Some(Ok(record)) = consumer_stream.next().await {
+        let Ok(user_deleted) = from_slice::<UserDeleted>(record.value()) else {
+            continue;
+        };
+
+        if let Err(e) = delete_user(&user_deleted.id, db).await {
+            tracing::error!("Failed to delete user {}: {e}", user_deleted.id);
+        }
+    }
+
+    Ok(())
+}
@@ -1,4 +1,9 @@
-use std::sync::LazyLock;
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    sync::LazyLock,
+    time::{Duration, Instant},
+};
 
 use axum::{
     Json, RequestPartsExt,
@@ -10,24 +15,98 @@ use axum_extra::{
     TypedHeader,
     headers::{Authorization, authorization::Bearer},
 };
-use jsonwebtoken::{DecodingKey, Validation, decode};
+use jsonwebtoken::{
+    Algorithm, DecodingKey, Validation, decode, decode_header,
+    jwk::{AlgorithmParameters, JwkSet},
+};
 use serde::{Deserialize, Serialize};
 use serde_json::json;
+use tokio::sync::{Mutex, RwLock};
 
 static KEYS: LazyLock<Keys> = LazyLock::new(|| {
-    let secret = std::env::var("JWT_SECRET").expect("JWT_SECRET must be set");
-    Keys::new(secret.as_bytes())
+    let url = std::env::var("JWKS_URL").expect("JWKS_URL must be set");
+    Keys::new(url)
 });
 
+/// Keys are fetched again after this long, so removed ones stop being trusted
+const MAX_AGE: Duration = Duration::from_secs(300);
+/// Minimum time between fetches triggered by unknown `kid`s
+const REFRESH_COOLDOWN: Duration = Duration::from_secs(10);
+
+/// Public keys published by auth-service, cached by `kid`. An unknown `kid`
+/// fetches them again, which picks up keys added while rotating.
 struct Keys {
-    decoding: DecodingKey,
+    url: String,
+    client: reqwest::Client,
+    decoding: RwLock<HashMap<String, (DecodingKey, Algorithm)>>,
+    fetched_at: Mutex<Option<Instant>>,
 }
 
 impl Keys {
-    fn new(secret: &[u8]) -> Self {
+    fn new(url: String) -> Self {
         Self {
-            decoding: DecodingKey::from_secret(secret),
+            url,
+            client: reqwest::Client::new(),
+            decoding: RwLock::new(HashMap::new()),
+            fetched_at: Mutex::new(None),
+        }
+    }
+
+    async fn get(&self, kid: &str) -> Option<(DecodingKey, Algorithm)> {
+        let stale = self
+            .fetched_at
+            .lock()
+            .await
+            .is_none_or(|fetched_at| fetched_at.elapsed() >= MAX_AGE);
+        if !stale {
+            if let Some(key) = self.decoding.read().await.get(kid) {
+                return Some(key.clone());
+            }
         }
+
+        self.refresh(if stale { MAX_AGE } else { REFRESH_COOLDOWN })
+            .await;
+        self.decoding.read().await.get(kid).cloned()
+    }
+
+    async fn refresh(&self, cooldown: Duration) {
+        // Held while fetching, so concurrent requests don't fetch again
+        let mut fetched_at = self.fetched_at.lock().await;
+        if fetched_at.is_some_and(|fetched_at| fetched_at.elapsed() < cooldown) {
+            return;
+        }
+        *fetched_at = Some(Instant::now());
+
+        match self.fetch().await {
+            Ok(keys) => *self.decoding.write().await = keys,
+            // The keys we have are kept until auth-service is back
+            Err(e) => tracing::error!("Could not fetch JWKS from {}: {e}", self.url),
+        }
+    }
+
+    async fn fetch(&self) -> anyhow::Result<HashMap<String, (DecodingKey, Algorithm)>> {
+        let set: JwkSet = self
+            .client
+            .get(&self.url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        let keys = set
+            .keys
+            .iter()
+            // A published symmetric key would let anyone sign tokens
+            .filter(|jwk| !matches!(jwk.algorithm, AlgorithmParameters::OctetKey(_)))
+            .filter_map(|jwk| {
+                let kid = jwk.common.key_id.clone()?;
+                let algorithm = Algorithm::from_str(&jwk.common.key_algorithm?.to_string()).ok()?;
+                Some((kid, (DecodingKey::from_jwk(jwk).ok()?, algorithm)))
+            })
+            .collect();
+
+        Ok(keys)
     }
 }
 
@@ -35,6 +114,9 @@ impl Keys {
 pub(crate) struct Claims {
     exp: u64,
     pub user_id: String,
+    /// Set on tokens of OAuth apps, which no route here grants a scope to
+    #[serde(default)]
+    client_id: Option<String>,
 }
 
 impl<S> FromRequestParts<S> for Claims
@@ -49,10 +131,20 @@ where
             .extract::<TypedHeader<Authorization<Bearer>>>()
             .await
             .map_err(|_| AuthError::InvalidToken)?;
-        // Decode the user data
-        let token_data = decode::<Claims>(bearer.token(), &KEYS.decoding, &Validation::default())
+        // Decode the user data, with the key that signed it
+        let kid = decode_header(bearer.token())
+            .map_err(|_| AuthError::InvalidToken)?
+            .kid
+            .ok_or(AuthError::InvalidToken)?;
+        let (key, algorithm) = KEYS.get(&kid).await.ok_or(AuthError::InvalidToken)?;
+        let token_data = decode::<Claims>(bearer.token(), &key, &Validation::new(algorithm))
             .map_err(|_| AuthError::InvalidToken)?;
 
+        // Denied by default, like routes without scopes at the gateway
+        if token_data.claims.client_id.is_some() {
+            return Err(AuthError::NotForApps);
+        }
+
         Ok(token_data.claims)
     }
 }
@@ -65,6 +157,7 @@ pub enum AuthError {
     MissingCredentials,
     TokenCreation,
     InvalidToken,
+    NotForApps,
 }
 
 impl IntoResponse for AuthError {
@@ -74,6 +167,7 @@ impl IntoResponse for AuthError {
             AuthError::MissingCredentials => (StatusCode::BAD_REQUEST, "Missing credentials"),
             AuthError::TokenCreation => (StatusCode::INTERNAL_SERVER_ERROR, "Token creation error"),
             AuthError::InvalidToken => (StatusCode::BAD_REQUEST, "Invalid token"),
+            AuthError::NotForApps => (StatusCode::FORBIDDEN, "Not available to apps"),
         };
         let body = Json(json!({
             "error": error_message,
@@ -229,17 +229,25 @@ pub async fn get_undirected_private_friend_requests(
 
 //--------------------INSERTS--------------------
 
+/// Does nothing if the user was deleted already
 pub async fn insert_user(user: PrivateUser, db: &sqlx::PgPool) -> anyhow::Result<()> {
+    let mut tx = db.begin().await?;
+    lock_user_id(&user.id, &mut tx).await?;
+
     sqlx::query(
         "
-            INSERT INTO users (id, username) VALUES ($1, $2)
+            INSERT INTO users (id, username)
+            SELECT $1, $2
+            WHERE NOT EXISTS (SELECT 1 FROM deleted_users WHERE id = $1)
         ",
     )
     .bind(user.id)
     .bind(user.username)
-    .execute(db)
+    .execute(&mut *tx)
     .await?;
 
+    tx.commit().await?;
+
     Ok(())
 }
 
@@ -297,6 +305,49 @@ pub async fn insert_block(block: PrivateBlocked, db: &sqlx::PgPool) -> anyhow::R
 
 //--------------------DELETE--------------------
 
+/// Their friendships, blocks and friend requests go with them, as they cascade
+/// Leaves a tombstone, so the user isn't inserted again when its registration comes after
+pub async fn delete_user(id: &str, db: &sqlx::PgPool) -> anyhow::Result<()> {
+    let mut tx = db.begin().await?;
+    lock_user_id(id, &mut tx).await?;
+
+    sqlx::query(
+        "
+        INSERT INTO deleted_users (id)
+        VALUES ($1)
+        ON CONFLICT (id) DO NOTHING
+    ",
+    )
+    .bind(id)
+    .execute(&mut *tx)
+    .await?;
+
+    sqlx::query(
+        "
+        DELETE
+        FROM users
+        WHERE id = $1
+    ",
+    )
+    .bind(id)
+    .execute(&mut *tx)
+    .await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Until the transaction ends, so inserting and deleting the same user don't interleave
+async fn lock_user_id(id: &str, conn: &mut sqlx::PgConnection) -> anyhow::Result<()> {
+    sqlx::query("SELECT pg_advisory_xact_lock(hashtext($1))")
+        .bind(id)
+        .execute(conn)
+        .await?;
+
+    Ok(())
+}
+
 pub async fn delete_friend_request(
     request: PrivateFriendRequest,
     db: &sqlx::PgPool,
@@ -65,5 +65,17 @@ pub async fn init(db: &sqlx::PgPool) -> anyhow::Result<()> {
     .execute(db)
     .await?;
 
+    // Deletions are replayed concurrently with registrations, a deleted user isn't inserted again
+    sqlx::query(
+        "
+        CREATE TABLE IF NOT EXISTS deleted_users (
+        id TEXT PRIMARY KEY,
+        deleted_at TIMESTAMP WITH TIME ZONE DEFAULT CURRENT_TIMESTAMP
+        )
+    ",
+    )
+    .execute(db)
+    .await?;
+
     Ok(())
 }
@@ -45,7 +45,7 @@ export class LogInComponent {
                 email: string;
                 telephone?: string;
             }>(SERVER_ROUTE + "/api/auth/login", {
-                username,
+                identifier: username,
                 password,
             })
             .subscribe({